@@ -0,0 +1,10215 @@
+//! Aegis Architect - Architecture Enforcing Scaffold Tool
+//!
+//! アーキタイプの読み込みとスキャフォールド生成の本体。CLI（`aegis-architect`）は
+//! このクレートの薄いラッパーで、他のツールからも[`Architect`]経由で同じ処理を使える。
+//!
+//! ```no_run
+//! use aegis_architect::{Architect, ScaffoldOptions};
+//!
+//! let architect = Architect::new(vec!["archetypes".into()]);
+//! let report = architect.scaffold(&ScaffoldOptions::new(
+//!     "stock_price",
+//!     "株価を取得する",
+//!     "rust_hexagonal",
+//!     ".",
+//! ))?;
+//! for file in &report.generated {
+//!     println!("[{}] {}", file.layer, file.path.display());
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+use heck::{
+    ToKebabCase, ToLowerCamelCase, ToPascalCase, ToShoutySnakeCase, ToSnakeCase, ToTitleCase,
+};
+use indicatif::{ProgressBar, ProgressStyle};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io::{BufRead, BufReader, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+use tera::Tera;
+
+/// CLIの終了コードで区別するエラーの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// アーキタイプが見つからない
+    ArchetypeNotFound,
+    /// 既存のファイルやモジュールと衝突した（`--force`なし）
+    Conflict,
+    /// テンプレートの解析・レンダリングに失敗した
+    Render,
+    /// アーキタイプの検証で問題が見つかった
+    Validation,
+    /// その他のエラー
+    Other,
+}
+
+impl ErrorKind {
+    /// エラーチェーンから種類を判定する（Teraのエラーを含むものはレンダリングの失敗とみなす）
+    pub fn of(err: &anyhow::Error) -> Self {
+        for cause in err.chain() {
+            if let Some(err) = cause.downcast_ref::<ArchitectError>() {
+                return err.kind;
+            }
+            if cause.is::<tera::Error>() {
+                return ErrorKind::Render;
+            }
+        }
+        ErrorKind::Other
+    }
+
+    /// 種類に対応する終了コード
+    pub fn exit_code(self) -> u8 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::ArchetypeNotFound => 2,
+            ErrorKind::Conflict => 3,
+            ErrorKind::Render => 4,
+            ErrorKind::Validation => 5,
+        }
+    }
+}
+
+/// 種類を区別できるエラー（メッセージはそのまま表示する）
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct ArchitectError {
+    /// エラーの種類
+    pub kind: ErrorKind,
+    message: String,
+}
+
+impl ArchitectError {
+    fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+/// 出力の詳細度
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verbosity {
+    /// エラーと最終結果のみ
+    Quiet,
+    /// バナーや生成ファイルの一覧を含む通常の出力
+    #[default]
+    Normal,
+    /// テンプレートごとの統計も表示
+    Verbose,
+}
+
+impl Verbosity {
+    /// `--quiet`/`--verbose`から詳細度を決める
+    pub fn from_flags(quiet: bool, verbose: bool) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, true) => Verbosity::Verbose,
+            (false, false) => Verbosity::Normal,
+        }
+    }
+}
+
+/// 詳細度が`Normal`以上のときだけ`println!`する
+#[macro_export]
+macro_rules! status {
+    ($verbosity:expr) => {
+        if $verbosity >= $crate::Verbosity::Normal {
+            println!();
+        }
+    };
+    ($verbosity:expr, $($arg:tt)*) => {
+        if $verbosity >= $crate::Verbosity::Normal {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// プロジェクト設定ファイル名
+const CONFIG_FILE_NAME: &str = ".aegisrc";
+
+/// 組み込みのデフォルトアーキタイプ
+pub const DEFAULT_ARCHETYPE: &str = "rust_hexagonal";
+
+/// プロジェクト設定（`.aegisrc`または`--config`で指定したファイル、TOML形式）
+///
+/// 優先順位は CLI > 設定ファイル > 組み込みデフォルト。
+/// 相対パスは設定ファイルを置いたディレクトリからの相対として解決する。
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    /// `--archetype`省略時のアーキタイプ
+    pub default_archetype: Option<String>,
+    /// `--target`省略時の生成先ディレクトリ
+    pub default_target: Option<PathBuf>,
+    /// `--archetypes-dir`省略時のアーキタイプディレクトリ
+    pub archetypes_dir: Option<PathBuf>,
+    /// `false`なら`--no-color`と同じく色付けしない
+    pub color: Option<bool>,
+    /// `--quiet`/`--verbose`省略時の詳細度（`"quiet"`・`"normal"`・`"verbose"`）
+    pub verbosity: Option<Verbosity>,
+    /// `--shared-templates-dir`省略時の共有テンプレートのディレクトリ
+    pub shared_templates_dir: Option<PathBuf>,
+}
+
+impl Config {
+    /// CLI指定がなければ設定、設定もなければ組み込みデフォルトのアーキタイプを返す
+    pub fn archetype(&self, cli: Option<String>) -> String {
+        cli.or_else(|| self.default_archetype.clone())
+            .unwrap_or_else(|| DEFAULT_ARCHETYPE.to_string())
+    }
+
+    /// CLI指定がなければ設定、設定もなければカレントディレクトリを返す
+    pub fn target(&self, cli: Option<PathBuf>) -> PathBuf {
+        cli.or_else(|| self.default_target.clone())
+            .unwrap_or_else(|| PathBuf::from("."))
+    }
+
+    /// `--quiet`/`--verbose`の指定がなければ設定、設定もなければ`Normal`を返す
+    pub fn verbosity(&self, quiet: bool, verbose: bool) -> Verbosity {
+        if quiet || verbose {
+            Verbosity::from_flags(quiet, verbose)
+        } else {
+            self.verbosity.unwrap_or_default()
+        }
+    }
+}
+
+/// カレントディレクトリから親方向に`.aegisrc`を探して読み込む
+pub fn load_config() -> Result<Config> {
+    let current_dir = std::env::current_dir().context("Failed to get current directory")?;
+    load_config_from(&current_dir)
+}
+
+/// `start`から親方向に`.aegisrc`を探して読み込む。見つからなければデフォルト
+fn load_config_from(start: &Path) -> Result<Config> {
+    match start
+        .ancestors()
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|path| path.is_file())
+    {
+        Some(config_path) => load_config_file(&config_path),
+        None => Ok(Config::default()),
+    }
+}
+
+/// 指定した設定ファイルを読み込む（`.aegisrc`の探索はしない。ファイルがなければエラー）
+pub fn load_config_file(config_path: &Path) -> Result<Config> {
+    if !config_path.is_file() {
+        anyhow::bail!("Config file not found: {}", config_path.display());
+    }
+    let content = fs::read_to_string(config_path)
+        .with_context(|| format!("Failed to read config: {:?}", config_path))?;
+    let mut config: Config = toml::from_str(&content)
+        .with_context(|| format!("Failed to parse config: {:?}", config_path))?;
+
+    if let Some(base) = config_path.parent() {
+        for path in [
+            &mut config.default_target,
+            &mut config.archetypes_dir,
+            &mut config.shared_templates_dir,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if path.is_relative() {
+                *path = base.join(&*path);
+            }
+        }
+    }
+
+    Ok(config)
+}
+
+/// Cargo.tomlのうちワークスペースの解決に必要な部分
+#[derive(Debug, Default, Deserialize)]
+struct CargoManifest {
+    package: Option<CargoPackage>,
+    workspace: Option<CargoWorkspace>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CargoPackage {
+    name: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CargoWorkspace {
+    #[serde(default)]
+    members: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+}
+
+/// Cargo.tomlを読み込む
+fn read_cargo_manifest(path: &Path) -> Result<CargoManifest> {
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+    toml::from_str(&content).with_context(|| format!("Failed to parse {:?}", path))
+}
+
+/// `start`から親方向にCargoワークスペースを探し、パッケージ名が`crate_name`の
+/// メンバーのディレクトリを返す
+///
+/// `members`のglobを展開し、`exclude`に含まれるディレクトリは除外する。
+pub fn find_workspace_member(start: &Path, crate_name: &str) -> Result<PathBuf> {
+    let mut workspace = None;
+    for dir in start.ancestors() {
+        let manifest_path = dir.join("Cargo.toml");
+        if !manifest_path.is_file() {
+            continue;
+        }
+        let manifest = read_cargo_manifest(&manifest_path)?;
+        if let Some(ws) = manifest.workspace {
+            workspace = Some((dir.to_path_buf(), ws, manifest.package));
+            break;
+        }
+    }
+    let Some((root, ws, root_package)) = workspace else {
+        anyhow::bail!("No Cargo workspace found in {:?} or its parents", start);
+    };
+
+    let excluded: Vec<PathBuf> = ws.exclude.iter().map(|path| root.join(path)).collect();
+    let mut members: Vec<(String, PathBuf)> = Vec::new();
+    if let Some(package) = root_package {
+        members.push((package.name, root.clone()));
+    }
+    for pattern in &ws.members {
+        let pattern = root.join(pattern);
+        let paths = glob::glob(&pattern.to_string_lossy())
+            .with_context(|| format!("Invalid workspace member pattern: {:?}", pattern))?;
+        for dir in paths.filter_map(Result::ok) {
+            let manifest_path = dir.join("Cargo.toml");
+            if excluded.contains(&dir) || !manifest_path.is_file() {
+                continue;
+            }
+            if let Some(package) = read_cargo_manifest(&manifest_path)?.package {
+                members.push((package.name, dir));
+            }
+        }
+    }
+
+    let mut matches: Vec<PathBuf> = members
+        .iter()
+        .filter(|(name, _)| name == crate_name)
+        .map(|(_, dir)| dir.clone())
+        .collect();
+    matches.dedup();
+    match matches.len() {
+        1 => Ok(matches.remove(0)),
+        0 => {
+            let mut names: Vec<_> = members.iter().map(|(name, _)| name.as_str()).collect();
+            names.sort();
+            anyhow::bail!(
+                "Crate '{}' is not a member of the workspace at {:?}. Members: {}",
+                crate_name,
+                root,
+                names.join(", ")
+            )
+        }
+        _ => {
+            let dirs: Vec<_> = matches
+                .iter()
+                .map(|dir| dir.display().to_string())
+                .collect();
+            anyhow::bail!(
+                "Crate '{}' is ambiguous in the workspace at {:?}: {}",
+                crate_name,
+                root,
+                dirs.join(", ")
+            )
+        }
+    }
+}
+
+/// アーキタイプのマニフェスト
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema)]
+pub struct Manifest {
+    /// アーキタイプ名（ディレクトリ名と一致させる）
+    pub name: String,
+    /// アーキタイプ名の代わりに指定できる短い名前（例: `["hex"]`。継承はされない）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// 継承元のアーキタイプ名
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    /// このアーキタイプを使うのに必要なaegis-architectの最小バージョン（例: `"0.3.0"`）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_version: Option<String>,
+    /// 一覧に表示する名前
+    #[serde(rename = "displayName", alias = "display_name")]
+    pub display_name: String,
+    /// アーキタイプの説明
+    pub description: String,
+    /// このアーキタイプを選ぶべき状況
+    #[serde(default)]
+    pub use_when: Vec<String>,
+    /// このアーキタイプを避けるべき状況
+    #[serde(default)]
+    pub avoid_when: Vec<String>,
+    /// 想定するプロジェクトの規模（`--project-size`と異なる場合に警告）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<ProjectSize>,
+    /// 生成するファイル
+    pub files: Vec<FileSpec>,
+    /// mod.rsへの登録内容（省略時はrust_hexagonalのみ従来の3ファイルを更新）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mod_updates: Option<Vec<ModUpdate>>,
+    /// レイヤーごとの出力先ルート（例: `{"domain": "src/domain"}`）
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub layer_roots: HashMap<String, String>,
+    /// レイヤーごとの出力先クレートのディレクトリ（生成先からの相対。例: `{"domain": "crates/core"}`）
+    ///
+    /// 宣言したレイヤーのファイルと、そのレイヤーの`mod_updates`はクレートのディレクトリを基準にする。
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub layer_crate: HashMap<String, String>,
+    /// テンプレート変数の既定値（`--var-file`・`--var`で上書きできる）
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub default_vars: HashMap<String, String>,
+    /// 生成時に必ず指定する変数（`--var`などで渡されなければ`scaffold`を中断する）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub required_vars: Vec<String>,
+    /// 生成したコードが必要とするクレート（`name`または`name@version`。例: `"thiserror@1"`）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<String>,
+    /// 生成後に表示する次の作業（Teraテンプレート）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub next_steps: Vec<String>,
+    /// 生成前後に実行するコマンド
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
+    /// `--feature-dir`で使う機能ディレクトリ（Teraテンプレート。省略時は`src/features/{{name}}`）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub feature_dir: Option<String>,
+    /// `--emit-prompt`で表示するプロンプトのテンプレート（アーキタイプディレクトリからの相対パス）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_template: Option<String>,
+    /// このアーキタイプのディレクトリ
+    #[serde(skip)]
+    pub dir: PathBuf,
+    /// 継承元アーキタイプのディレクトリ（遠い祖先から順）
+    #[serde(skip)]
+    parent_dirs: Vec<PathBuf>,
+}
+
+/// 生成ファイルの仕様
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema)]
+pub struct FileSpec {
+    /// アーキタイプディレクトリからのテンプレートの相対パス
+    /// （`../_common/x.tmpl`のような外のパス、`@shared/`で共有テンプレートも指定できる）
+    pub template: String,
+    /// 生成先からの出力パス（Teraテンプレート）
+    pub output: String,
+    /// レイヤー名（`layer_roots`のキー。Teraテンプレート）
+    pub layer: String,
+    /// 生成条件（Teraの式）。偽と評価された場合は生成しない
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<String>,
+    /// 先に生成する必要があるファイル（他の仕様の`output`）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    /// 配列のコンテキスト変数名（`entity.fields`のようにドットで辿れる）。
+    /// 指定すると要素ごとに`item`を束縛して1ファイルずつ生成する
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub for_each: Option<String>,
+    /// 生成したファイルのパーミッション（`"0755"`のような8進数。Unixのみ）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    /// HTMLエスケープを行うか（省略時は出力の拡張子で決める。`.html`/`.htm`/`.xml`のみ有効）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub autoescape: Option<bool>,
+    /// mod.rsに登録しないか（`.rs`以外の出力と`test`レイヤーは常に登録しない）
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skip_mod: bool,
+    /// 継承元から引き継いだ場合、そのアーキタイプのディレクトリ
+    #[serde(skip)]
+    origin: Option<PathBuf>,
+    /// `@shared/`のテンプレートを解決する共有テンプレートのディレクトリ
+    #[serde(skip)]
+    shared_dir: Option<PathBuf>,
+}
+
+/// 既定でHTMLエスケープを行う出力の拡張子
+const AUTOESCAPE_EXTENSIONS: &[&str] = &["html", "htm", "xml"];
+
+/// 共有テンプレートのディレクトリを参照する`template`の接頭辞
+const SHARED_TEMPLATE_PREFIX: &str = "@shared/";
+
+/// 共有テンプレートのディレクトリを指定する環境変数（CLIが`--shared-templates-dir`の
+/// 次に参照する。ライブラリは読まないので、[`Architect::with_shared_templates_dir`]などで渡す）
+pub const SHARED_TEMPLATES_DIR_ENV: &str = "AEGIS_SHARED_TEMPLATES_DIR";
+
+/// `tests/`の統合テストなど、cargoが自動で見つけるためmod.rsに登録しないレイヤー
+const TEST_LAYER: &str = "test";
+
+impl FileSpec {
+    /// レンダリング時にHTMLエスケープを行うか
+    fn autoescape(&self) -> bool {
+        self.autoescape.unwrap_or_else(|| {
+            Path::new(&self.output)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| AUTOESCAPE_EXTENSIONS.contains(&ext))
+        })
+    }
+
+    /// テンプレートファイルのパス（継承元の仕様は継承元のディレクトリから解決）
+    ///
+    /// `@shared/`で始まるものは共有テンプレートのディレクトリから解決する。
+    /// 絶対パスの`template`はエラーにする。
+    fn template_path(&self, archetype_dir: &Path) -> Result<PathBuf> {
+        if Path::new(&self.template).is_absolute() {
+            anyhow::bail!("template must be relative: {}", self.template);
+        }
+        if let (Some(rest), Some(shared_dir)) = (
+            self.template.strip_prefix(SHARED_TEMPLATE_PREFIX),
+            &self.shared_dir,
+        ) {
+            return Ok(shared_dir.join(rest));
+        }
+        Ok(self
+            .origin
+            .as_deref()
+            .unwrap_or(archetype_dir)
+            .join(&self.template))
+    }
+
+    /// Teraに登録するテンプレート名
+    ///
+    /// 継承元の仕様は子のテンプレートと衝突しないよう`<継承元>/<template>`とする。
+    fn template_name(&self) -> String {
+        match self.origin.as_deref().and_then(|dir| dir.file_name()) {
+            Some(parent) => format!("{}/{}", parent.to_string_lossy(), self.template),
+            None => self.template.clone(),
+        }
+    }
+}
+
+/// アーキタイプごとのフック（`--run-hooks`指定時のみ実行）
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct Hooks {
+    /// ファイル生成後に生成先で実行するシェルコマンド（Teraテンプレート）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_generate: Vec<String>,
+}
+
+impl Hooks {
+    /// フックが1つも定義されていないか
+    pub fn is_empty(&self) -> bool {
+        self.post_generate.is_empty()
+    }
+}
+
+/// mod.rsへの登録の仕様
+///
+/// `mod_file`・`line`ともにテンプレートとしてコンテキストでレンダリングされる。
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct ModUpdate {
+    pub mod_file: String,
+    pub line: String,
+    /// 登録対象のレイヤー（`--only-layer`での絞り込みに使用）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layer: Option<String>,
+}
+
+/// プロジェクトの規模
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl std::fmt::Display for ProjectSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ProjectSize::Small => "small",
+            ProjectSize::Medium => "medium",
+            ProjectSize::Large => "large",
+        })
+    }
+}
+
+impl Manifest {
+    /// `default_vars`の後に指定された変数を並べる（同じキーは指定された値が優先）
+    fn vars_with_defaults(
+        &self,
+        vars: &[(String, serde_json::Value)],
+    ) -> Vec<(String, serde_json::Value)> {
+        let mut defaults: Vec<(String, serde_json::Value)> = self
+            .default_vars
+            .iter()
+            .map(|(key, value)| (key.clone(), value.as_str().into()))
+            .collect();
+        defaults.sort_by(|a, b| a.0.cmp(&b.0));
+        defaults.extend(vars.iter().cloned());
+        defaults
+    }
+
+    /// `required_vars`のうち、既定値も指定もされていない変数があればエラーにする
+    fn check_required_vars(&self, vars: &[(String, serde_json::Value)]) -> Result<()> {
+        let missing: Vec<&str> = self
+            .required_vars
+            .iter()
+            .filter(|var| {
+                !self.default_vars.contains_key(*var) && !vars.iter().any(|(key, _)| key == *var)
+            })
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            anyhow::bail!(
+                "Archetype '{}' requires variable(s) that were not provided: {} (pass them with --var, --var-file or --var-json)",
+                self.name,
+                missing.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// テンプレートが参照しているが、組み込み変数・`default_vars`・`required_vars`の
+    /// いずれでも提供されない変数をテンプレートごとに返す
+    ///
+    /// `for_each`を持つ仕様では`item`も提供されるものとする。テンプレートを
+    /// 読み込めない・解析できない場合はその問題を返す。
+    fn undeclared_vars(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for file_spec in &self.files {
+            let Ok(source) = file_spec
+                .template_path(&self.dir)
+                .and_then(|path| Ok(read_template(&path)?))
+            else {
+                continue;
+            };
+            let referenced = match referenced_variables(&source) {
+                Ok(referenced) => referenced,
+                Err(err) => {
+                    problems.push(format!(
+                        "failed to parse template {}: {}",
+                        file_spec.template, err
+                    ));
+                    continue;
+                }
+            };
+            let undeclared: Vec<&str> = referenced
+                .iter()
+                .map(String::as_str)
+                .filter(|var| {
+                    let provided = RESERVED_VARS.contains(var)
+                        || ENVIRONMENT_VARS.contains(var)
+                        || self.default_vars.contains_key(*var)
+                        || self.required_vars.iter().any(|required| required == var)
+                        || (file_spec.for_each.is_some() && *var == "item");
+                    !provided
+                })
+                .collect();
+            if !undeclared.is_empty() {
+                problems.push(format!(
+                    "template {} uses variable(s) not declared in default_vars or required_vars: {}",
+                    file_spec.template,
+                    undeclared.join(", ")
+                ));
+            }
+        }
+        problems
+    }
+
+    /// テンプレートを読み込むディレクトリ（遠い祖先から順に、最後がこのアーキタイプ）
+    pub fn source_dirs(&self) -> Vec<&Path> {
+        self.parent_dirs
+            .iter()
+            .chain(std::iter::once(&self.dir))
+            .map(PathBuf::as_path)
+            .collect()
+    }
+
+    /// プロンプトテンプレートのパス（継承元で宣言されたものは子から順に探す）
+    fn prompt_template_path(&self) -> Option<PathBuf> {
+        let template = self.prompt_template.as_ref()?;
+        let dirs = self.source_dirs();
+        Some(
+            dirs.iter()
+                .rev()
+                .map(|dir| dir.join(template))
+                .find(|path| path.is_file())
+                .unwrap_or_else(|| self.dir.join(template)),
+        )
+    }
+
+    /// 宣言されたプロジェクト規模とアーキタイプの`size`が食い違うか
+    ///
+    /// `size`を持たないアーキタイプは常に食い違わないものとする。
+    pub fn size_mismatch(&self, project_size: ProjectSize) -> bool {
+        self.size.is_some_and(|size| size != project_size)
+    }
+
+    /// このアーキタイプで更新するmod.rsの一覧
+    ///
+    /// `mod_updates`を省略した古いマニフェストでは、rust_hexagonalに限り
+    /// Domain/Port/Adapterの3ファイルを更新する従来の挙動にフォールバックする。
+    fn mod_updates(&self) -> Vec<ModUpdate> {
+        self.declared_mod_updates()
+            .into_iter()
+            .filter(|update| update.layer.as_deref() != Some(TEST_LAYER))
+            .map(|mut update| {
+                if let Some(layer) = &update.layer {
+                    update.mod_file = self.in_layer_crate(layer, &update.mod_file);
+                }
+                update
+            })
+            .collect()
+    }
+
+    /// `layer_crate`を宣言したレイヤーなら、パスの前にクレートのディレクトリを付ける
+    fn in_layer_crate(&self, layer: &str, path: &str) -> String {
+        match self.layer_crate.get(layer) {
+            Some(dir) => format!("{}/{}", dir.trim_end_matches('/'), path),
+            None => path.to_string(),
+        }
+    }
+
+    /// マニフェストに宣言された（省略時は従来の）mod.rsの登録
+    fn declared_mod_updates(&self) -> Vec<ModUpdate> {
+        if let Some(updates) = &self.mod_updates {
+            return updates.clone();
+        }
+        if self.name != "rust_hexagonal" {
+            return Vec::new();
+        }
+        [
+            ("src/domain/mod.rs", "pub mod {{name}};", "domain"),
+            ("src/ports/mod.rs", "pub mod {{name}}_port;", "port"),
+            (
+                "src/adapters/mod.rs",
+                "pub mod {{name}}_adapter;",
+                "adapter",
+            ),
+        ]
+        .into_iter()
+        .map(|(mod_file, line, layer)| ModUpdate {
+            mod_file: mod_file.to_string(),
+            line: line.to_string(),
+            layer: Some(layer.to_string()),
+        })
+        .collect()
+    }
+}
+
+/// アーキタイプディレクトリを束ねた、ライブラリとしての入口
+///
+/// ディレクトリは後に指定したものが優先される（同名のアーキタイプは上書き）。
+#[derive(Debug, Clone)]
+pub struct Architect {
+    archetypes_dirs: Vec<PathBuf>,
+    shared_templates_dir: Option<PathBuf>,
+}
+
+impl Architect {
+    /// アーキタイプディレクトリを指定して作成
+    pub fn new(archetypes_dirs: Vec<PathBuf>) -> Self {
+        Self {
+            archetypes_dirs,
+            shared_templates_dir: None,
+        }
+    }
+
+    /// `@shared/`のテンプレートを解決する共有テンプレートのディレクトリを設定する
+    ///
+    /// `archetype`・`describe_files`・`validate`・`doctor`で使う（生成や削除では
+    /// 各オプションの`shared_templates_dir`を使う）。
+    pub fn with_shared_templates_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.shared_templates_dir = dir;
+        self
+    }
+
+    /// アーキタイプディレクトリの一覧
+    pub fn archetypes_dirs(&self) -> &[PathBuf] {
+        &self.archetypes_dirs
+    }
+
+    /// 全アーキタイプを名前順に読み込む
+    pub fn list(&self) -> Result<Vec<Manifest>> {
+        load_all_archetypes(&self.archetypes_dirs)
+    }
+
+    /// アーキタイプを継承を解決した状態で読み込む
+    pub fn archetype(&self, name: &str) -> Result<Manifest> {
+        load_archetype_with_shared_templates(
+            &self.archetypes_dirs,
+            name,
+            self.shared_templates_dir.as_deref(),
+        )
+    }
+
+    /// スキャフォールドを生成
+    pub fn scaffold(&self, options: &ScaffoldOptions) -> Result<ScaffoldReport> {
+        scaffold_feature(&self.archetypes_dirs, options)
+    }
+
+    /// 生成されるファイルの一覧を、何も書き込まずに解決する
+    pub fn plan(&self, options: &ScaffoldOptions) -> Result<Vec<PlannedFile>> {
+        plan_feature(&self.archetypes_dirs, options)
+    }
+
+    /// アーキタイプのプロンプトテンプレートを機能のコンテキストでレンダリングする
+    pub fn prompt(&self, options: &ScaffoldOptions) -> Result<String> {
+        emit_prompt(&self.archetypes_dirs, options)
+    }
+
+    /// アーキタイプのファイル仕様を、仮の機能名で出力パスを解決して一覧にする
+    pub fn describe_files(&self, archetype: &str, name: &str) -> Result<Vec<ArchetypeFile>> {
+        describe_archetype_files(
+            &self.archetypes_dirs,
+            archetype,
+            name,
+            self.shared_templates_dir.as_deref(),
+        )
+    }
+
+    /// スキャフォールド済みの機能を削除
+    pub fn remove(&self, options: &RemoveOptions) -> Result<()> {
+        remove_feature(&self.archetypes_dirs, options)
+    }
+
+    /// 現在のマニフェストでは生成されなくなったファイルを削除
+    pub fn prune(&self, options: &PruneOptions) -> Result<()> {
+        prune_feature(&self.archetypes_dirs, options)
+    }
+
+    /// スキャフォールド済みの機能の名前を変更
+    pub fn rename(&self, options: &RenameOptions) -> Result<()> {
+        rename_feature(&self.archetypes_dirs, options)
+    }
+
+    /// 生成済みのファイルと再レンダリング結果を比較
+    pub fn diff(&self, options: &DiffOptions) -> Result<DiffSummary> {
+        diff_feature(&self.archetypes_dirs, options)
+    }
+
+    /// アーキタイプのマニフェストを検証（`None`なら全て）
+    pub fn validate(&self, archetype: Option<&str>) -> Result<()> {
+        validate_archetypes(
+            &self.archetypes_dirs,
+            archetype,
+            self.shared_templates_dir.as_deref(),
+        )
+    }
+
+    /// 新しいアーキタイプの雛形を作成
+    pub fn new_archetype(&self, name: &str) -> Result<()> {
+        new_archetype(&self.archetypes_dirs, name)
+    }
+
+    /// 実行環境を検査して一覧を表示する（致命的な問題があればエラー）
+    pub fn doctor(&self, project_dir: &Path) -> Result<()> {
+        run_doctor(
+            &self.archetypes_dirs,
+            project_dir,
+            self.shared_templates_dir.as_deref(),
+        )
+    }
+}
+
+/// 全アーキタイプを読み込む
+pub fn load_all_archetypes(archetypes_dirs: &[PathBuf]) -> Result<Vec<Manifest>> {
+    let mut result = Vec::new();
+
+    for path in find_archetype_dirs(archetypes_dirs)? {
+        if let Some(name) = path.file_name() {
+            result.push(load_archetype(archetypes_dirs, &name.to_string_lossy())?);
+        }
+    }
+
+    // 名前でソート
+    result.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(result)
+}
+
+/// マニフェストのファイル名（優先順）
+const MANIFEST_FILE_NAMES: &[&str] = &["manifest.json", "manifest.toml"];
+
+/// アーキタイプディレクトリのマニフェストのパス（`manifest.json`を優先）
+fn manifest_path(dir: &Path) -> Option<PathBuf> {
+    MANIFEST_FILE_NAMES
+        .iter()
+        .map(|file_name| dir.join(file_name))
+        .find(|path| path.exists())
+}
+
+/// マニフェストを持つアーキタイプディレクトリを名前順に列挙する
+///
+/// 同名のアーキタイプは後に指定したディレクトリのものが優先される。
+fn find_archetype_dirs(archetypes_dirs: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut result = BTreeMap::new();
+
+    for archetypes_dir in archetypes_dirs {
+        for entry in fs::read_dir(archetypes_dir)
+            .with_context(|| format!("Failed to read archetypes directory: {:?}", archetypes_dir))?
+        {
+            let entry = entry.with_context(|| {
+                format!("Failed to read directory entry in {:?}", archetypes_dir)
+            })?;
+            let path = entry.path();
+
+            if entry_is_dir(&entry) && manifest_path(&path).is_some() {
+                result.insert(entry.file_name(), path);
+            }
+        }
+    }
+
+    Ok(result.into_values().collect())
+}
+
+/// ディレクトリのエントリがディレクトリか（シンボリックリンクはリンク先で判定する）
+fn entry_is_dir(entry: &fs::DirEntry) -> bool {
+    match entry.file_type() {
+        Ok(file_type) if file_type.is_symlink() => {
+            fs::metadata(entry.path()).is_ok_and(|metadata| metadata.is_dir())
+        }
+        Ok(file_type) => file_type.is_dir(),
+        Err(_) => false,
+    }
+}
+
+/// 名前に対応するアーキタイプディレクトリを優先順位の高い順に探す
+fn find_archetype_dir(archetypes_dirs: &[PathBuf], name: &str) -> Option<PathBuf> {
+    archetypes_dirs
+        .iter()
+        .rev()
+        .map(|dir| dir.join(name))
+        .find(|dir| manifest_path(dir).is_some())
+}
+
+/// エイリアスから、それを宣言したアーキタイプ名（ディレクトリ名）への対応を作る
+///
+/// 複数のアーキタイプが同じエイリアスを宣言している場合は、全ての名前を持つ。
+/// マニフェストを読み込めないアーキタイプは無視する。
+fn archetype_aliases(archetypes_dirs: &[PathBuf]) -> Result<BTreeMap<String, Vec<String>>> {
+    let mut aliases: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for path in find_archetype_dirs(archetypes_dirs)? {
+        let Some(name) = path.file_name() else {
+            continue;
+        };
+        let name = name.to_string_lossy();
+        let Ok(manifest) = read_manifest(archetypes_dirs, &name, None) else {
+            continue;
+        };
+        for alias in manifest.aliases {
+            aliases.entry(alias).or_default().push(name.to_string());
+        }
+    }
+    Ok(aliases)
+}
+
+/// 指定された名前をアーキタイプ名に解決する
+///
+/// 同名のアーキタイプがあればそれを使い、なければエイリアスとして解決する。
+/// 複数のアーキタイプが宣言したエイリアスはエラーにする。
+fn resolve_archetype_name(archetypes_dirs: &[PathBuf], name: &str) -> Result<String> {
+    if find_archetype_dir(archetypes_dirs, name).is_some() {
+        return Ok(name.to_string());
+    }
+    match archetype_aliases(archetypes_dirs)?.remove(name) {
+        Some(names) if names.len() > 1 => Err(ArchitectError::new(
+            ErrorKind::Validation,
+            format!(
+                "Alias '{}' is ambiguous; it is declared by archetypes: {}",
+                name,
+                names.join(", ")
+            ),
+        )
+        .into()),
+        Some(mut names) => Ok(names.remove(0)),
+        None => Ok(name.to_string()),
+    }
+}
+
+/// アーキタイプを読み込む（`extends`による継承を解決する）
+///
+/// `name`にはアーキタイプ名のほか、マニフェストの`aliases`も指定できる。
+pub fn load_archetype(archetypes_dirs: &[PathBuf], name: &str) -> Result<Manifest> {
+    load_archetype_with_shared_templates(archetypes_dirs, name, None)
+}
+
+/// `@shared/`のテンプレートを`shared_templates_dir`から解決してアーキタイプを読み込む
+pub fn load_archetype_with_shared_templates(
+    archetypes_dirs: &[PathBuf],
+    name: &str,
+    shared_templates_dir: Option<&Path>,
+) -> Result<Manifest> {
+    load_archetype_chain(archetypes_dirs, name, shared_templates_dir, &mut Vec::new())
+}
+
+/// 継承チェーンをたどってアーキタイプを読み込む。`visited`で循環を検出する
+fn load_archetype_chain(
+    archetypes_dirs: &[PathBuf],
+    name: &str,
+    shared_templates_dir: Option<&Path>,
+    visited: &mut Vec<String>,
+) -> Result<Manifest> {
+    if visited.iter().any(|v| v == name) {
+        visited.push(name.to_string());
+        anyhow::bail!("Archetype inheritance cycle: {}", visited.join(" -> "));
+    }
+    visited.push(name.to_string());
+
+    let name = &resolve_archetype_name(archetypes_dirs, name)?;
+    let manifest = read_manifest(archetypes_dirs, name, shared_templates_dir)?;
+    check_min_version(&manifest, env!("CARGO_PKG_VERSION"))?;
+    let Some(parent_name) = manifest.extends.clone() else {
+        return Ok(manifest);
+    };
+
+    let parent = load_archetype_chain(archetypes_dirs, &parent_name, shared_templates_dir, visited)
+        .with_context(|| format!("Failed to load parent archetype of '{}'", name))?;
+    Ok(merge_manifests(parent, manifest))
+}
+
+/// マニフェストの`min_version`が`current`（ツールのバージョン）以下であることを確認する
+fn check_min_version(manifest: &Manifest, current: &str) -> Result<()> {
+    let Some(min_version) = &manifest.min_version else {
+        return Ok(());
+    };
+    let required = semver::Version::parse(min_version).with_context(|| {
+        format!(
+            "Invalid min_version in archetype '{}': {}",
+            manifest.name, min_version
+        )
+    })?;
+    let current = semver::Version::parse(current)?;
+    if current < required {
+        return Err(ArchitectError::new(
+            ErrorKind::Validation,
+            format!(
+                "Archetype '{}' requires aegis-architect >= {} (this is {}); upgrade aegis-architect to >= {}",
+                manifest.name, required, current, required
+            ),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// 子マニフェストを親にマージする
+///
+/// `files`は`output`が同じものを子で上書きし、それ以外は末尾に追加する。
+/// 説明や`use_when`/`avoid_when`などは子が省略した場合のみ親から引き継ぐ。
+fn merge_manifests(parent: Manifest, child: Manifest) -> Manifest {
+    let parent_dir = parent.dir;
+    let mut files: Vec<FileSpec> = parent
+        .files
+        .into_iter()
+        .map(|mut file_spec| {
+            file_spec.origin.get_or_insert_with(|| parent_dir.clone());
+            file_spec
+        })
+        .collect();
+    for file_spec in child.files {
+        match files.iter_mut().find(|f| f.output == file_spec.output) {
+            Some(existing) => *existing = file_spec,
+            None => files.push(file_spec),
+        }
+    }
+
+    let mut layer_roots = parent.layer_roots;
+    layer_roots.extend(child.layer_roots);
+
+    let mut layer_crate = parent.layer_crate;
+    layer_crate.extend(child.layer_crate);
+
+    let mut default_vars = parent.default_vars;
+    default_vars.extend(child.default_vars);
+
+    let mut required_vars = parent.required_vars;
+    for var in child.required_vars {
+        if !required_vars.contains(&var) {
+            required_vars.push(var);
+        }
+    }
+
+    let mut dependencies = parent.dependencies;
+    for dependency in child.dependencies {
+        let name = parse_dependency(&dependency).0;
+        dependencies.retain(|existing| parse_dependency(existing).0 != name);
+        dependencies.push(dependency);
+    }
+
+    let mut parent_dirs = parent.parent_dirs;
+    parent_dirs.push(parent_dir);
+
+    let or_parent = |child: String, parent: String| if child.is_empty() { parent } else { child };
+    let or_parent_list =
+        |child: Vec<String>, parent: Vec<String>| if child.is_empty() { parent } else { child };
+
+    Manifest {
+        name: child.name,
+        aliases: child.aliases,
+        extends: child.extends,
+        min_version: child.min_version.or(parent.min_version),
+        display_name: or_parent(child.display_name, parent.display_name),
+        description: or_parent(child.description, parent.description),
+        use_when: or_parent_list(child.use_when, parent.use_when),
+        avoid_when: or_parent_list(child.avoid_when, parent.avoid_when),
+        size: child.size.or(parent.size),
+        files,
+        mod_updates: child.mod_updates.or(parent.mod_updates),
+        layer_roots,
+        layer_crate,
+        default_vars,
+        required_vars,
+        dependencies,
+        next_steps: or_parent_list(child.next_steps, parent.next_steps),
+        hooks: if child.hooks.is_empty() {
+            parent.hooks
+        } else {
+            child.hooks
+        },
+        feature_dir: child.feature_dir.or(parent.feature_dir),
+        prompt_template: child.prompt_template.or(parent.prompt_template),
+        dir: child.dir,
+        parent_dirs,
+    }
+}
+
+/// `name`に最も近い候補（編集距離が名前の長さの1/3以下、最低2まで）
+fn closest_match<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let threshold = (name.chars().count() / 3).max(2);
+    candidates
+        .map(|candidate| (strsim::levenshtein(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= threshold)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// マニフェスト（manifest.jsonまたはmanifest.toml）を継承を解決せずにそのまま読み込む
+///
+/// 両方ある場合はmanifest.jsonを使い、警告を表示する。
+/// `@shared/`のテンプレートは`shared_templates_dir`から解決する。
+fn read_manifest(
+    archetypes_dirs: &[PathBuf],
+    name: &str,
+    shared_templates_dir: Option<&Path>,
+) -> Result<Manifest> {
+    let Some(dir) = find_archetype_dir(archetypes_dirs, name) else {
+        // 候補は読み込めたマニフェストだけから作り、壊れたマニフェストがあっても
+        // 「見つからない」エラーを報告する（継承は解決しないので親の有無に影響されない）
+        let mut archetypes: Vec<Manifest> = find_archetype_dirs(archetypes_dirs)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|path| path.file_name())
+            .filter_map(|dir_name| {
+                read_manifest(archetypes_dirs, &dir_name.to_string_lossy(), None).ok()
+            })
+            .collect();
+        archetypes.sort_by(|a, b| a.name.cmp(&b.name));
+        let available: Vec<_> = archetypes.iter().map(|m| m.name.clone()).collect();
+        let candidates = archetypes
+            .iter()
+            .flat_map(|m| std::iter::once(&m.name).chain(&m.aliases))
+            .map(String::as_str);
+        let suggestion = match closest_match(name, candidates) {
+            Some(closest) => format!(" Did you mean '{}'?", closest),
+            None => String::new(),
+        };
+
+        return Err(ArchitectError::new(
+            ErrorKind::ArchetypeNotFound,
+            format!(
+                "Archetype '{}' not found.{} Available: {}",
+                name,
+                suggestion,
+                available.join(", ")
+            ),
+        )
+        .into());
+    };
+
+    let manifest_path = manifest_path(&dir).unwrap_or_else(|| dir.join(MANIFEST_FILE_NAMES[0]));
+    if dir.join("manifest.json").exists() && dir.join("manifest.toml").exists() {
+        eprintln!(
+            "{} both manifest.json and manifest.toml exist in {:?}; using manifest.json",
+            "warning:".yellow(),
+            dir
+        );
+    }
+    let content = fs::read_to_string(&manifest_path)
+        .with_context(|| format!("Failed to read manifest: {:?}", manifest_path))?;
+    let parsed = if manifest_path.extension().is_some_and(|ext| ext == "toml") {
+        toml::from_str(&content).map_err(anyhow::Error::from)
+    } else {
+        serde_json::from_str(&content).map_err(anyhow::Error::from)
+    };
+    let mut manifest: Manifest =
+        parsed.with_context(|| format!("Failed to parse manifest: {:?}", manifest_path))?;
+    manifest.dir = dir;
+    set_shared_dir(&mut manifest, shared_templates_dir);
+    Ok(manifest)
+}
+
+/// `@shared/`で始まるテンプレートに共有テンプレートのディレクトリを設定する
+///
+/// ディレクトリが指定されていなければ何もしない（`validate`で問題として報告する）。
+fn set_shared_dir(manifest: &mut Manifest, shared_dir: Option<&Path>) {
+    let Some(shared_dir) = shared_dir else {
+        return;
+    };
+    for file_spec in &mut manifest.files {
+        if file_spec.template.starts_with(SHARED_TEMPLATE_PREFIX) {
+            file_spec.shared_dir = Some(shared_dir.to_path_buf());
+        }
+    }
+}
+
+/// マニフェスト形式のJSON Schemaを標準出力に書き出す
+///
+/// manifest.jsonの`$schema`から参照すると、エディタで補完と検証が効く。
+pub fn print_manifest_schema() -> Result<()> {
+    let schema = schemars::schema_for!(Manifest);
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+    Ok(())
+}
+
+/// アーキタイプ1つを読み込んで検証し、そのディレクトリと問題の一覧を返す
+///
+/// `aliases`は[`archetype_aliases`]の結果で、エイリアスの重複の検出に使う。
+fn archetype_problems(
+    archetypes_dirs: &[PathBuf],
+    name: &str,
+    aliases: &BTreeMap<String, Vec<String>>,
+    shared_templates_dir: Option<&Path>,
+) -> (PathBuf, Vec<String>) {
+    let manifest =
+        match load_archetype_with_shared_templates(archetypes_dirs, name, shared_templates_dir) {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                return (
+                    find_archetype_dir(archetypes_dirs, name)
+                        .unwrap_or_else(|| PathBuf::from(name)),
+                    vec![format!("{:#}", err)],
+                )
+            }
+        };
+
+    let mut problems = validate_manifest(&manifest, &manifest.dir);
+    for alias in &manifest.aliases {
+        if find_archetype_dir(archetypes_dirs, alias).is_some() {
+            problems.push(format!(
+                "alias '{}' is shadowed by the archetype of the same name",
+                alias
+            ));
+        } else if aliases.get(alias).is_some_and(|names| names.len() > 1) {
+            problems.push(format!(
+                "alias '{}' is also declared by: {}",
+                alias,
+                aliases[alias]
+                    .iter()
+                    .filter(|other| *other != &manifest.name)
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+    }
+    (manifest.dir, problems)
+}
+
+/// アーキタイプを検証し、問題があれば全て報告する
+pub fn validate_archetypes(
+    archetypes_dirs: &[PathBuf],
+    archetype: Option<&str>,
+    shared_templates_dir: Option<&Path>,
+) -> Result<()> {
+    let names: Vec<String> = match archetype {
+        Some(name) => vec![name.to_string()],
+        None => find_archetype_dirs(archetypes_dirs)?
+            .iter()
+            .filter_map(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect(),
+    };
+
+    println!("{}", "Validating archetypes:".bold());
+    println!("{}", "=".repeat(60));
+
+    let aliases = archetype_aliases(archetypes_dirs)?;
+    let mut invalid = 0;
+    for name in &names {
+        let (archetype_dir, problems) =
+            archetype_problems(archetypes_dirs, name, &aliases, shared_templates_dir);
+
+        if problems.is_empty() {
+            println!("  [{}] {}", "OK".green(), name);
+        } else {
+            invalid += 1;
+            println!(
+                "  [{}] {} ({})",
+                "FAIL".red(),
+                name,
+                manifest_path(&archetype_dir)
+                    .unwrap_or_else(|| archetype_dir.join(MANIFEST_FILE_NAMES[0]))
+                    .display()
+            );
+            for problem in &problems {
+                println!("      - {}", problem);
+            }
+        }
+    }
+
+    println!("{}", "=".repeat(60));
+    if invalid > 0 {
+        return Err(ArchitectError::new(
+            ErrorKind::Validation,
+            format!("{} of {} archetypes are invalid", invalid, names.len()),
+        )
+        .into());
+    }
+    println!(
+        "{}",
+        format!("All {} archetypes are valid", names.len()).green()
+    );
+    Ok(())
+}
+
+/// `doctor`の検査結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    /// 問題なし
+    Pass,
+    /// 一部の機能が使えない
+    Warn,
+    /// アーキタイプを使うコマンドが動かない
+    Fail,
+}
+
+/// `doctor`の検査項目1つ分
+#[derive(Debug)]
+pub struct DoctorCheck {
+    /// 検査項目
+    pub name: &'static str,
+    /// 結果
+    pub status: CheckStatus,
+    /// 見つかったパスやバージョン、問題の内容
+    pub detail: String,
+}
+
+/// 実行環境を検査する
+///
+/// アーキタイプディレクトリの有無と内容の妥当性、`cargo`・`rustfmt`・`git`の有無、
+/// `project_dir`がRustプロジェクト内かを調べる。アーキタイプの問題は`Fail`、
+/// それ以外は使えなくなる機能が限られるため`Warn`とする。
+pub fn diagnose(
+    archetypes_dirs: &[PathBuf],
+    project_dir: &Path,
+    shared_templates_dir: Option<&Path>,
+) -> Vec<DoctorCheck> {
+    let mut checks = Vec::new();
+    let check = |name, status, detail: String| DoctorCheck {
+        name,
+        status,
+        detail,
+    };
+
+    let missing: Vec<String> = archetypes_dirs
+        .iter()
+        .filter(|dir| !dir.is_dir())
+        .map(|dir| dir.display().to_string())
+        .collect();
+    if missing.is_empty() {
+        let dirs: Vec<String> = archetypes_dirs
+            .iter()
+            .map(|dir| dir.display().to_string())
+            .collect();
+        checks.push(check(
+            "archetypes directory",
+            CheckStatus::Pass,
+            dirs.join(", "),
+        ));
+        checks.push(match find_archetype_dirs(archetypes_dirs) {
+            Ok(paths) if paths.is_empty() => check(
+                "archetypes",
+                CheckStatus::Fail,
+                "no archetypes found (create one with `new-archetype`)".into(),
+            ),
+            Ok(paths) => {
+                let aliases = archetype_aliases(archetypes_dirs).unwrap_or_default();
+                let invalid: Vec<String> = paths
+                    .iter()
+                    .filter_map(|path| path.file_name())
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .filter(|name| {
+                        !archetype_problems(archetypes_dirs, name, &aliases, shared_templates_dir)
+                            .1
+                            .is_empty()
+                    })
+                    .collect();
+                if invalid.is_empty() {
+                    check(
+                        "archetypes",
+                        CheckStatus::Pass,
+                        format!("{} valid", paths.len()),
+                    )
+                } else {
+                    check(
+                        "archetypes",
+                        CheckStatus::Fail,
+                        format!(
+                            "{} of {} invalid: {} (run `validate` for details)",
+                            invalid.len(),
+                            paths.len(),
+                            invalid.join(", ")
+                        ),
+                    )
+                }
+            }
+            Err(err) => check("archetypes", CheckStatus::Fail, format!("{:#}", err)),
+        });
+    } else {
+        checks.push(check(
+            "archetypes directory",
+            CheckStatus::Fail,
+            format!(
+                "not found: {} (pass --archetypes-dir or set AEGIS_ARCHETYPES_DIR)",
+                missing.join(", ")
+            ),
+        ));
+    }
+
+    for (program, needed_for) in [
+        ("cargo", "--check"),
+        ("rustfmt", "--fmt"),
+        ("git", "--require-clean and git_author_* variables"),
+    ] {
+        checks.push(match tool_version(program) {
+            Some(version) => check(program, CheckStatus::Pass, version),
+            None => check(
+                program,
+                CheckStatus::Warn,
+                format!("not found on PATH (needed for {})", needed_for),
+            ),
+        });
+    }
+
+    checks.push(match nearest_cargo_toml(project_dir) {
+        Some(path) => check(
+            "rust project",
+            CheckStatus::Pass,
+            path.display().to_string(),
+        ),
+        None => check(
+            "rust project",
+            CheckStatus::Warn,
+            format!("no Cargo.toml in {} or its parents", project_dir.display()),
+        ),
+    });
+    checks
+}
+
+/// `program --version`の出力の1行目（実行できなければ`None`）
+fn tool_version(program: &str) -> Option<String> {
+    let output = std::process::Command::new(program)
+        .arg("--version")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+    )
+}
+
+/// 実行環境を検査して結果を一覧表示し、`Fail`があればエラーにする
+pub fn run_doctor(
+    archetypes_dirs: &[PathBuf],
+    project_dir: &Path,
+    shared_templates_dir: Option<&Path>,
+) -> Result<()> {
+    let checks = diagnose(archetypes_dirs, project_dir, shared_templates_dir);
+
+    println!("{}", "Checking environment:".bold());
+    println!("{}", "=".repeat(60));
+    for check in &checks {
+        let label = match check.status {
+            CheckStatus::Pass => "PASS".green(),
+            CheckStatus::Warn => "WARN".yellow(),
+            CheckStatus::Fail => "FAIL".red(),
+        };
+        println!("  [{}] {}: {}", label, check.name, check.detail);
+    }
+    println!("{}", "=".repeat(60));
+
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == CheckStatus::Fail)
+        .count();
+    if failed > 0 {
+        return Err(ArchitectError::new(
+            ErrorKind::Validation,
+            format!("{} critical problem(s) found", failed),
+        )
+        .into());
+    }
+    println!("{}", "No critical problems found".green());
+    Ok(())
+}
+
+/// Hexagonal構成の最初のscaffoldがコンパイルできるよう、lib.rsと空のmod.rsを作成
+///
+/// 既に存在するファイルには触れない。
+pub fn init_project(target: &Path) -> Result<()> {
+    let files = [
+        (
+            "src/lib.rs",
+            "pub mod domain;\npub mod ports;\npub mod adapters;\n",
+        ),
+        ("src/domain/mod.rs", ""),
+        ("src/ports/mod.rs", ""),
+        ("src/adapters/mod.rs", ""),
+    ];
+
+    println!("Initializing hexagonal layout in {}:", target.display());
+    for (relative, content) in files {
+        let path = target.join(relative);
+        if path.exists() {
+            println!("  {} {}", "exists ".dimmed(), path.display());
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+        }
+        fs::write(&path, content).with_context(|| format!("Failed to write file: {:?}", path))?;
+        println!("  {} {}", "created".green(), path.display());
+    }
+
+    Ok(())
+}
+
+/// 新しいアーキタイプの雛形（manifest.jsonとテンプレート）を作成
+///
+/// 複数のアーキタイプディレクトリがある場合は、最も優先順位の高い（最後の）ものに作成する。
+pub fn new_archetype(archetypes_dirs: &[PathBuf], name: &str) -> Result<()> {
+    let Some(archetypes_dir) = archetypes_dirs.last() else {
+        anyhow::bail!("No archetypes directory configured");
+    };
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        anyhow::bail!(
+            "Invalid archetype name '{}': use only letters, digits, '_' and '-'",
+            name
+        );
+    }
+
+    let archetype_dir = archetypes_dir.join(name);
+    if archetype_dir.exists() {
+        anyhow::bail!("Archetype directory already exists: {:?}", archetype_dir);
+    }
+
+    let template_name = "main.rs.tera";
+    let manifest = Manifest {
+        name: name.to_string(),
+        display_name: name.to_pascal_case(),
+        description: "TODO: アーキタイプの説明".to_string(),
+        files: vec![FileSpec {
+            template: template_name.to_string(),
+            output: "src/{{name}}.rs".to_string(),
+            layer: "main".to_string(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    fs::create_dir_all(&archetype_dir)
+        .with_context(|| format!("Failed to create directory: {:?}", archetype_dir))?;
+
+    let manifest_path = archetype_dir.join("manifest.json");
+    let manifest_json = serde_json::to_string_pretty(&manifest)?;
+    fs::write(&manifest_path, format!("{}\n", manifest_json))
+        .with_context(|| format!("Failed to write manifest: {:?}", manifest_path))?;
+
+    let template_path = archetype_dir.join(template_name);
+    fs::write(
+        &template_path,
+        "//! {{pascal_name}} - {{description}}\n//!\n//! TODO: このテンプレートを編集する\n",
+    )
+    .with_context(|| format!("Failed to write template: {:?}", template_path))?;
+
+    println!("Created archetype '{}':", name.cyan());
+    println!("  {}", manifest_path.display());
+    println!("  {}", template_path.display());
+    Ok(())
+}
+
+/// マニフェストの内容を検証し、見つかった問題を返す
+fn validate_manifest(manifest: &Manifest, archetype_dir: &Path) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for file_spec in &manifest.files {
+        match file_spec.template_path(archetype_dir) {
+            Err(err) => problems.push(err.to_string()),
+            Ok(_)
+                if file_spec.template.starts_with(SHARED_TEMPLATE_PREFIX)
+                    && file_spec.shared_dir.is_none() =>
+            {
+                problems.push(format!(
+                    "template {} needs a shared templates directory (--shared-templates-dir, {} or shared_templates_dir in .aegisrc)",
+                    file_spec.template, SHARED_TEMPLATES_DIR_ENV
+                ));
+            }
+            Ok(template_path) if !template_path.is_file() => {
+                problems.push(format!("template not found: {}", template_path.display()));
+            }
+            Ok(_) => {}
+        }
+
+        let output = Path::new(&file_spec.output);
+        if output.is_absolute() {
+            problems.push(format!("output must be relative: {}", file_spec.output));
+        } else if output
+            .components()
+            .any(|c| matches!(c, std::path::Component::ParentDir))
+        {
+            problems.push(format!(
+                "output must not contain '..': {}",
+                file_spec.output
+            ));
+        }
+
+        if file_spec.layer.trim().is_empty() {
+            problems.push(format!(
+                "layer is empty for template: {}",
+                file_spec.template
+            ));
+        }
+
+        if let Some(Err(err)) = file_spec.mode.as_deref().map(parse_mode) {
+            problems.push(format!("{} (template: {})", err, file_spec.template));
+        }
+    }
+
+    for dependency in &manifest.dependencies {
+        let (name, version) = parse_dependency(dependency);
+        let Some(version) = version else {
+            problems.push(format!(
+                "dependency has no version (use name@version): {}",
+                dependency
+            ));
+            continue;
+        };
+        if name.is_empty() {
+            problems.push(format!("dependency has no crate name: {}", dependency));
+        } else if let Err(err) = semver::VersionReq::parse(version) {
+            problems.push(format!(
+                "invalid version in dependency '{}': {}",
+                dependency, err
+            ));
+        }
+    }
+
+    let mut crate_dirs: Vec<(&String, &String)> = manifest.layer_crate.iter().collect();
+    crate_dirs.sort();
+    for (layer, dir) in crate_dirs {
+        let path = Path::new(dir);
+        if path.is_absolute()
+            || path
+                .components()
+                .any(|c| matches!(c, std::path::Component::ParentDir))
+        {
+            problems.push(format!(
+                "layer_crate for '{}' must be relative without '..': {}",
+                layer, dir
+            ));
+        }
+    }
+
+    if let Some(path) = manifest.prompt_template_path() {
+        if !path.is_file() {
+            problems.push(format!("prompt_template not found: {}", path.display()));
+        }
+    }
+
+    // 生成条件のないファイル同士で出力先が同じなら必ず衝突する
+    let unconditional: Vec<&FileSpec> = manifest
+        .files
+        .iter()
+        .filter(|file_spec| file_spec.when.is_none() && file_spec.for_each.is_none())
+        .collect();
+    for (index, file_spec) in unconditional.iter().enumerate() {
+        if let Some(other) = unconditional[..index]
+            .iter()
+            .find(|other| other.output == file_spec.output)
+        {
+            problems.push(format!(
+                "duplicate output {}: generated by both {} and {}",
+                file_spec.output, other.template, file_spec.template
+            ));
+        }
+    }
+
+    let mut reserved: Vec<&String> = manifest
+        .default_vars
+        .keys()
+        .filter(|key| RESERVED_VARS.contains(&key.as_str()))
+        .collect();
+    reserved.sort();
+    for key in reserved {
+        problems.push(format!(
+            "default_vars must not override built-in variable: {}",
+            key
+        ));
+    }
+
+    if let Err(err) = order_files(&manifest.files) {
+        problems.push(err.to_string());
+    }
+
+    problems.extend(manifest.undeclared_vars());
+
+    problems
+}
+
+/// `mode`の8進数表記（`"0755"`または`"755"`）を解釈する
+fn parse_mode(mode: &str) -> Result<u32> {
+    let valid = (3..=4).contains(&mode.len()) && mode.chars().all(|c| ('0'..='7').contains(&c));
+    if !valid {
+        anyhow::bail!(
+            "Invalid mode '{}': expected an octal value like \"0755\"",
+            mode
+        );
+    }
+    Ok(u32::from_str_radix(mode, 8)?)
+}
+
+/// 生成したファイルのパーミッションを設定する（Unix以外では警告して無視）
+#[cfg(unix)]
+fn set_file_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set permissions of {:?}", path))
+}
+
+/// 生成したファイルのパーミッションを設定する（Unix以外では警告して無視）
+#[cfg(not(unix))]
+fn set_file_mode(path: &Path, mode: u32) -> Result<()> {
+    eprintln!(
+        "{} ignoring mode {:o} for {:?} (file permissions are only supported on Unix)",
+        "warning:".yellow(),
+        mode,
+        path
+    );
+    Ok(())
+}
+
+/// 生成ファイルの改行コード
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LineEnding {
+    /// `\n`
+    #[default]
+    Lf,
+    /// `\r\n`
+    Crlf,
+    /// 実行中のプラットフォームの既定（Windowsでは`\r\n`）
+    Native,
+}
+
+impl LineEnding {
+    fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+            LineEnding::Native if cfg!(windows) => "\r\n",
+            LineEnding::Native => "\n",
+        }
+    }
+}
+
+/// 改行コードを統一し、末尾の改行をちょうど1つにする
+///
+/// テンプレートに`\r\n`や単独の`\r`が混在していても、すべて`ending`に揃える。
+/// 空の内容は空のまま返す。
+pub fn normalize_line_endings(content: &str, ending: LineEnding) -> String {
+    let unified = content.replace("\r\n", "\n").replace('\r', "\n");
+    let body = unified.trim_end_matches('\n');
+    if body.is_empty() {
+        return String::new();
+    }
+    let newline = ending.as_str();
+    let mut normalized = body.replace('\n', newline);
+    normalized.push_str(newline);
+    normalized
+}
+
+/// スキャフォールド生成のオプション
+#[derive(Clone)]
+pub struct ScaffoldOptions {
+    /// 機能名（正規化前）
+    pub name: String,
+    /// 機能の説明
+    pub description: String,
+    /// 使用するアーキタイプ名
+    pub archetype: String,
+    /// 生成先ディレクトリ
+    pub target: PathBuf,
+    /// mod.rsを自動更新するか
+    pub update_mod: bool,
+    /// 書き込みを行わず計画のみ表示するか
+    pub dry_run: bool,
+    /// 既存ファイルの上書きを許可するか
+    pub force: bool,
+    /// ユーザー定義のテンプレート変数
+    pub vars: Vec<(String, serde_json::Value)>,
+    /// 生成後にrustfmtを実行するか
+    pub fmt: bool,
+    /// 生成先のgit作業ツリーがクリーンであることを要求するか
+    pub require_clean: bool,
+    /// 生成記録を保存するか
+    pub record: bool,
+    /// 生成するレイヤー（空なら全て。`adapter*`のようなglobも可）
+    pub only_layers: Vec<String>,
+    /// 生成しないレイヤー（`only_layers`から除く。globも可）
+    pub skip_layers: Vec<String>,
+    /// アーキタイプのフックを実行するか
+    pub run_hooks: bool,
+    /// 生成ファイルの改行コード
+    pub line_ending: LineEnding,
+    /// 同じ名前のモジュールが既にmod.rsにあればエラーにするか（既定は警告のみ）
+    pub no_clobber_modules: bool,
+    /// 全ファイルを機能ディレクトリ直下に生成し、そのmod.rsだけを更新するか
+    pub feature_dir: bool,
+    /// この実行だけ差し替えるテンプレート（ファイル仕様の`output`または`layer`、テンプレートのパス）
+    pub template_overrides: Vec<(String, PathBuf)>,
+    /// 生成ファイルの先頭にアーキタイプを示すコメントを付けるか（`None`なら`.rs`のみ）
+    pub banner: Option<bool>,
+    /// テンプレートが宣言されていない変数を参照していれば生成前にエラーにするか
+    pub check_vars: bool,
+    /// 編集済みの内容を引き継ぐ既存の機能名（正規化前）
+    pub seed_from: Option<String>,
+    /// 書き込まずに、再生成で変わるファイルやmod行があればエラーにするか
+    pub assert_unchanged: bool,
+    /// アーキタイプの`dependencies`のうち未登録のものを生成先のCargo.tomlに追加するか
+    pub add_deps: bool,
+    /// 既存ファイルのマーカーで囲まれた領域だけをテンプレートの内容で置き換えるか
+    pub merge_markers: bool,
+    /// 生成中に進捗バーを表示するか（標準エラー出力が端末で、ファイル数が多い場合のみ）
+    pub progress: bool,
+    /// `post_generate`フックの出力を実行中に1行ずつ標準エラー出力へ流すか
+    pub stream_hooks: bool,
+    /// アーキタイプの`mod_updates`の代わりに使うmod.rsの登録（`--mod-file`/`--mod-line`）
+    pub mod_lines: Vec<ModUpdate>,
+    /// `@shared/`のテンプレートを解決する共有テンプレートのディレクトリ
+    pub shared_templates_dir: Option<PathBuf>,
+}
+
+impl ScaffoldOptions {
+    /// `only_layers`と`skip_layers`による絞り込み
+    fn layer_filter(&self) -> LayerFilter<'_> {
+        LayerFilter {
+            only: &self.only_layers,
+            skip: &self.skip_layers,
+        }
+    }
+
+    /// CLIのデフォルトと同じ設定（mod.rsを更新し、その他のオプションは無効）で作成
+    pub fn new(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        archetype: impl Into<String>,
+        target: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            archetype: archetype.into(),
+            target: target.into(),
+            update_mod: true,
+            dry_run: false,
+            force: false,
+            vars: Vec::new(),
+            fmt: false,
+            require_clean: false,
+            record: false,
+            only_layers: Vec::new(),
+            skip_layers: Vec::new(),
+            run_hooks: false,
+            line_ending: LineEnding::default(),
+            no_clobber_modules: false,
+            feature_dir: false,
+            template_overrides: Vec::new(),
+            banner: None,
+            check_vars: false,
+            seed_from: None,
+            assert_unchanged: false,
+            add_deps: false,
+            merge_markers: false,
+            progress: false,
+            stream_hooks: false,
+            mod_lines: Vec::new(),
+            shared_templates_dir: None,
+        }
+    }
+}
+
+/// スキャフォールド生成の結果
+#[derive(Debug)]
+pub struct ScaffoldReport {
+    /// 機能名（正規化後）
+    pub name: String,
+    /// 使用したアーキタイプの名前
+    pub archetype: String,
+    /// 使用したアーキタイプの表示名
+    pub display_name: String,
+    /// 使用したアーキタイプの説明
+    pub archetype_description: String,
+    /// 書き込みを行わなかったか
+    pub dry_run: bool,
+    /// 生成した（dry-runでは生成予定の）ファイル
+    pub generated: Vec<GeneratedFile>,
+    /// 生成条件により生成しなかったファイル
+    pub skipped: Vec<SkippedFile>,
+    /// 既存の内容がレンダリング結果と同一のため書き込まなかったファイル
+    pub unchanged: Vec<PathBuf>,
+    /// mod.rsの登録行とその結果（dry-runでは予定）
+    pub mod_updates: Vec<ModLineUpdate>,
+    /// 生成前からmod.rsに登録されていた、この機能のmod行（機能が既に存在する可能性）
+    pub existing_modules: Vec<(PathBuf, String)>,
+    /// 保存した生成記録のパス
+    pub record: Option<PathBuf>,
+    /// 実行した`post_generate`フックとその出力
+    pub hooks: Vec<HookRun>,
+    /// 実行しなかった（`--run-hooks`なし、またはdry-run）`post_generate`フック
+    pub skipped_hooks: Vec<String>,
+    /// rustfmtの実行結果
+    pub formatted: Vec<(PathBuf, FormatOutcome)>,
+    /// `--seed-from`の機能から内容を引き継いだファイル
+    pub seeded: Vec<PathBuf>,
+    /// アーキタイプが必要とするクレート（マニフェストの`dependencies`）
+    pub dependencies: Vec<String>,
+    /// `--add-deps`でCargo.tomlごとに追加した（dry-runでは追加予定の）クレート名
+    /// （`--add-deps`なしでは空）
+    pub added_dependencies: Vec<(PathBuf, Vec<String>)>,
+    /// レンダリング済みの生成後の案内
+    pub next_steps: Vec<String>,
+    /// 生成全体の所要時間
+    pub elapsed: Duration,
+}
+
+impl ScaffoldReport {
+    /// `--json-report`で出力する内容
+    ///
+    /// パスは`path`に生成先からの相対パス（`/`区切り）、`absolute_path`に絶対パスを入れる。
+    pub fn to_json(&self, target: &Path) -> serde_json::Value {
+        let paths = |path: &Path| (relative_to(target, path), absolute_path(path));
+        let generated: Vec<serde_json::Value> = self
+            .generated
+            .iter()
+            .map(|file| {
+                let (path, absolute) = paths(&file.path);
+                serde_json::json!({
+                    "layer": file.layer,
+                    "path": path,
+                    "absolute_path": absolute,
+                    "bytes": file.bytes,
+                })
+            })
+            .collect();
+        let skipped: Vec<serde_json::Value> = self
+            .skipped
+            .iter()
+            .map(|file| {
+                let (path, absolute) = paths(&file.path);
+                serde_json::json!({
+                    "layer": file.layer,
+                    "path": path,
+                    "absolute_path": absolute,
+                    "condition": file.condition,
+                })
+            })
+            .collect();
+        let mod_updates: Vec<serde_json::Value> = self
+            .mod_updates
+            .iter()
+            .map(|update| {
+                let (path, absolute) = paths(&update.mod_file);
+                serde_json::json!({
+                    "mod_file": path,
+                    "absolute_path": absolute,
+                    "line": update.line,
+                    "status": update.status.label(self.dry_run),
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "name": self.name,
+            "archetype": self.archetype,
+            "display_name": self.display_name,
+            "dry_run": self.dry_run,
+            "total": self.generated.len(),
+            "generated": generated,
+            "skipped": skipped,
+            "unchanged": self.unchanged.iter().map(|path| relative_to(target, path)).collect::<Vec<_>>(),
+            "mod_updates": mod_updates,
+            "record": self.record.as_deref().map(|path| relative_to(target, path)),
+            "seeded": self.seeded.iter().map(|path| relative_to(target, path)).collect::<Vec<_>>(),
+            "dependencies": self.dependencies,
+            "added_dependencies": self
+                .added_dependencies
+                .iter()
+                .map(|(cargo_toml, names)| serde_json::json!({
+                    "cargo_toml": relative_to(target, cargo_toml),
+                    "added": names,
+                }))
+                .collect::<Vec<_>>(),
+            "hooks": self.hooks.iter().map(|hook| &hook.command).collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// 生成した（dry-runでは生成予定の）ファイル
+#[derive(Debug)]
+pub struct GeneratedFile {
+    /// レイヤー名
+    pub layer: String,
+    /// 出力先のパス
+    pub path: PathBuf,
+    /// テンプレートファイルのパス
+    pub template: PathBuf,
+    /// レンダリング結果のバイト数
+    pub bytes: usize,
+    /// レンダリングにかかった時間
+    pub render_time: Duration,
+}
+
+/// mod.rsの登録行1つ分の結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModLineUpdate {
+    /// 登録先のmod.rsのパス
+    pub mod_file: PathBuf,
+    /// 登録する行
+    pub line: String,
+    /// 登録の結果（dry-runでは予定）
+    pub status: ModLineStatus,
+}
+
+impl ModLineUpdate {
+    /// mod.rsを書き換える（dry-runでは書き換える予定の）登録か
+    pub fn is_change(&self) -> bool {
+        self.status != ModLineStatus::AlreadyPresent
+    }
+}
+
+/// mod.rsの登録行の結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModLineStatus {
+    /// 既存のmod.rsに行を追記する
+    Add,
+    /// 同じ行が既に登録されている
+    AlreadyPresent,
+    /// mod.rsを新しく作成する
+    CreateFile,
+}
+
+impl ModLineStatus {
+    /// 表示用のラベル（dry-runでは`would-`を付ける）
+    pub fn label(self, dry_run: bool) -> &'static str {
+        match (self, dry_run) {
+            (Self::Add, true) => "would-add",
+            (Self::Add, false) => "added",
+            (Self::AlreadyPresent, _) => "already-present",
+            (Self::CreateFile, true) => "would-create-file",
+            (Self::CreateFile, false) => "created-file",
+        }
+    }
+}
+
+/// 生成条件（`when`）が偽と評価されたファイル
+#[derive(Debug)]
+pub struct SkippedFile {
+    /// レイヤー名
+    pub layer: String,
+    /// 生成されるはずだった出力先のパス
+    pub path: PathBuf,
+    /// 評価した条件式
+    pub condition: String,
+}
+
+/// 実行したフック
+#[derive(Debug)]
+pub struct HookRun {
+    /// レンダリング済みのコマンド
+    pub command: String,
+    /// 標準出力と標準エラー出力
+    pub output: String,
+}
+
+/// 1ファイルに対するrustfmtの実行結果（書き込む前のレンダリング結果を整形する）
+#[derive(Debug, PartialEq)]
+pub enum FormatOutcome {
+    /// 整形した
+    Formatted,
+    /// rustfmtがエラーを返した（標準エラー出力）
+    Failed(String),
+    /// rustfmtを起動できなかった（以降のファイルは整形しない）
+    Unavailable(String),
+}
+
+/// 組み込みのテンプレート変数（`--var`で上書き不可）
+const RESERVED_VARS: &[&str] = &[
+    "name",
+    "snake_name",
+    "pascal_name",
+    "kebab_name",
+    "camel_name",
+    "shouty_name",
+    "title_name",
+    "description",
+];
+
+/// 実行環境から自動で設定するテンプレート変数（`--var`などで上書きできる）
+const ENVIRONMENT_VARS: &[&str] = &["date", "year", "git_author_name", "git_author_email"];
+
+/// テンプレート変数のファイル（`--var-file`）を読み込む
+///
+/// 拡張子で形式を判定する（`.json`または`.toml`）。値は文字列・数値・真偽値のみで、
+/// 配列やネストしたテーブルはエラーにする。
+pub fn load_var_file(path: &Path) -> Result<Vec<(String, serde_json::Value)>> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read var file: {:?}", path))?;
+    let table: serde_json::Map<String, serde_json::Value> =
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse var file: {:?}", path))?,
+            Some("toml") => {
+                let table: toml::Table = toml::from_str(&content)
+                    .with_context(|| format!("Failed to parse var file: {:?}", path))?;
+                serde_json::to_value(table)?
+                    .as_object()
+                    .cloned()
+                    .unwrap_or_default()
+            }
+            _ => anyhow::bail!(
+                "Unsupported var file format: {:?} (expected .json or .toml)",
+                path
+            ),
+        };
+
+    let unsupported: Vec<&str> = table
+        .iter()
+        .filter(|(_, value)| !(value.is_string() || value.is_number() || value.is_boolean()))
+        .map(|(key, _)| key.as_str())
+        .collect();
+    if !unsupported.is_empty() {
+        anyhow::bail!(
+            "Unsupported values in var file {:?} (only strings, numbers and booleans are allowed): {}",
+            path,
+            unsupported.join(", ")
+        );
+    }
+
+    Ok(table.into_iter().collect())
+}
+
+/// `--template-override`で指定したテンプレートにファイル仕様を差し替える
+///
+/// キーはまずレンダリング後の`output`（`src/domain/stock.rs`のような生成先からの相対パス）と
+/// 完全一致するものを探し、なければ`layer`で探す。
+/// レイヤーに複数のファイルがある場合は曖昧なのでエラーにする。
+fn apply_template_overrides(
+    manifest: &mut Manifest,
+    overrides: &[(String, PathBuf)],
+    context: &tera::Context,
+) -> Result<()> {
+    if overrides.is_empty() {
+        return Ok(());
+    }
+    // `for_each`の出力など`item`を参照するものは1つに決まらないので照合しない
+    let outputs: Vec<Option<String>> = manifest
+        .files
+        .iter()
+        .map(|file_spec| render_inline(&file_spec.output, context).ok())
+        .collect();
+    for (key, path) in overrides {
+        let key_path = Path::new(key);
+        let by_output: Vec<usize> = (0..manifest.files.len())
+            .filter(|&index| {
+                outputs[index]
+                    .as_deref()
+                    .is_some_and(|output| Path::new(output) == key_path)
+            })
+            .collect();
+        let matches = if by_output.is_empty() {
+            (0..manifest.files.len())
+                .filter(|&index| manifest.files[index].layer == *key)
+                .collect()
+        } else {
+            by_output
+        };
+        let index = match matches.as_slice() {
+            [index] => *index,
+            [] => {
+                let outputs: Vec<&str> = outputs
+                    .iter()
+                    .zip(&manifest.files)
+                    .map(|(output, file_spec)| output.as_deref().unwrap_or(&file_spec.output))
+                    .collect();
+                anyhow::bail!(
+                    "--template-override '{}' matches no file of archetype '{}'. Outputs: {}",
+                    key,
+                    manifest.name,
+                    outputs.join(", ")
+                );
+            }
+            _ => anyhow::bail!(
+                "--template-override '{}' matches {} files of layer '{}'; use the output path instead",
+                key,
+                matches.len(),
+                key
+            ),
+        };
+
+        let template = fs::canonicalize(path)
+            .with_context(|| format!("Template override not found: {:?}", path))?;
+        let file_spec = &mut manifest.files[index];
+        // 置き換えるテンプレートのディレクトリを起点にして、`template`は相対パスのままにする
+        file_spec.template = template
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        file_spec.origin = template.parent().map(Path::to_path_buf);
+    }
+    Ok(())
+}
+
+/// 生成ファイルのバナーに含める目印（テンプレートに既にあれば付け直さない）
+const BANNER_MARKER: &str = "Generated by aegis-architect";
+
+/// 出力先の種類に合ったバナーのコメント行（付けない場合は`None`）
+///
+/// `enabled`が`None`なら`.rs`のみに付ける。`Some(true)`でもコメントの書式が
+/// 分からない拡張子には付けない。
+fn banner_comment(
+    path: &Path,
+    enabled: Option<bool>,
+    archetype: &str,
+    file_spec: &FileSpec,
+) -> Option<String> {
+    let extension = path.extension()?.to_str()?;
+    if !enabled.unwrap_or(extension == "rs") {
+        return None;
+    }
+    let text = format!(
+        "{} from archetype '{}' (file: {}). Edits may be overwritten.",
+        BANNER_MARKER, archetype, file_spec.template
+    );
+    match extension {
+        "rs" => Some(format!("// {}", text)),
+        "toml" => Some(format!("# {}", text)),
+        "html" | "htm" | "xml" => Some(format!("<!-- {} -->", text)),
+        _ => None,
+    }
+}
+
+/// 内容の先頭にバナーを付ける（既に目印があれば何もしない）
+///
+/// XML宣言はファイルの先頭に置く必要があるため、その次の行に入れる。
+fn add_banner(content: &str, banner: &str) -> String {
+    if content.contains(BANNER_MARKER) {
+        return content.to_string();
+    }
+    if content.starts_with("<?xml") {
+        if let Some((declaration, rest)) = content.split_once('\n') {
+            return format!("{}\n{}\n{}", declaration, banner, rest);
+        }
+    }
+    format!("{}\n{}", banner, content)
+}
+
+/// 先頭（XML宣言の次を含む）のバナー行を取り除く
+fn strip_banner(content: &str) -> String {
+    let mut lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let position = if content.starts_with("<?xml") { 1 } else { 0 };
+    if lines
+        .get(position)
+        .is_some_and(|line| line.contains(BANNER_MARKER))
+    {
+        lines.remove(position);
+    }
+    lines.concat()
+}
+
+/// 生成途中で失敗した場合に作成済みファイルを巻き戻すガード
+///
+/// `commit()` されないままドロップされると、作成したファイルを逆順に削除し、
+/// 新規作成したディレクトリも空であれば削除する。既存ファイルの上書きは記録しない
+/// （`overwrite`で書き換えたmod.rsやCargo.tomlなどは元の内容に戻す）。
+#[derive(Default)]
+struct Rollback {
+    created: Vec<PathBuf>,
+    created_dirs: Vec<PathBuf>,
+    overwritten: Vec<(PathBuf, String)>,
+    committed: bool,
+}
+
+impl Rollback {
+    /// ディレクトリを作成し、新規に作られたものを記録する
+    fn create_dir_all(&mut self, dir: &Path) -> Result<()> {
+        let mut missing = Vec::new();
+        let mut current = Some(dir);
+        while let Some(path) = current {
+            if path.as_os_str().is_empty() || path.exists() {
+                break;
+            }
+            missing.push(path.to_path_buf());
+            current = path.parent();
+        }
+
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory: {:?}", dir))?;
+        self.created_dirs.extend(missing.into_iter().rev());
+        Ok(())
+    }
+
+    /// ファイルを書き込み、新規作成であれば記録する
+    fn write(&mut self, path: &Path, contents: &str) -> Result<()> {
+        let existed = path.exists();
+        fs::write(path, contents).with_context(|| format!("Failed to write file: {:?}", path))?;
+        if !existed {
+            self.created.push(path.to_path_buf());
+        }
+        Ok(())
+    }
+
+    /// 既存ファイルを書き換え、巻き戻すときに戻す元の内容を記録する
+    fn overwrite(&mut self, path: &Path, original: String, contents: &str) -> Result<()> {
+        fs::write(path, contents).with_context(|| format!("Failed to write {:?}", path))?;
+        self.overwritten.push((path.to_path_buf(), original));
+        Ok(())
+    }
+
+    /// 生成成功として確定し、巻き戻しを無効化する
+    fn commit(mut self) {
+        self.committed = true;
+    }
+}
+
+impl Drop for Rollback {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        for (path, original) in self.overwritten.iter().rev() {
+            let _ = fs::write(path, original);
+        }
+        for path in self.created.iter().rev() {
+            let _ = fs::remove_file(path);
+        }
+        // 空でないディレクトリの削除は失敗するので、そのまま残る
+        for dir in self.created_dirs.iter().rev() {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+}
+
+/// スキャフォールドを生成
+///
+/// 何も表示せず、生成した内容を[`ScaffoldReport`]として返す。
+pub fn scaffold_feature(
+    archetypes_dirs: &[PathBuf],
+    options: &ScaffoldOptions,
+) -> Result<ScaffoldReport> {
+    if options.assert_unchanged {
+        return assert_unchanged(archetypes_dirs, options);
+    }
+
+    let ScaffoldOptions {
+        name,
+        description,
+        archetype,
+        target,
+        ..
+    } = options;
+
+    // 名前を正規化し、識別子として使えるか検証
+    let snake_name = to_snake_case(name);
+    validate_feature_name(&snake_name)?;
+    let pascal_name = snake_name.to_pascal_case();
+
+    if options.require_clean {
+        ensure_clean_worktree(target)?;
+    }
+
+    let started = Instant::now();
+
+    // マニフェスト読み込み
+    let mut manifest = load_archetype_with_shared_templates(
+        archetypes_dirs,
+        archetype,
+        options.shared_templates_dir.as_deref(),
+    )?;
+    manifest.check_required_vars(&options.vars)?;
+
+    // Teraコンテキスト作成
+    let mut context = build_context(
+        &snake_name,
+        &pascal_name,
+        description,
+        &manifest.vars_with_defaults(&options.vars),
+    )?;
+    let environment = environment_vars(target);
+    insert_environment_vars(&mut context, &environment);
+    apply_template_overrides(&mut manifest, &options.template_overrides, &context)?;
+
+    if options.check_vars {
+        let problems = manifest.undeclared_vars();
+        if !problems.is_empty() {
+            return Err(ArchitectError::new(
+                ErrorKind::Validation,
+                format!(
+                    "--check-vars: archetype '{}' references undeclared variables:\n  {}",
+                    manifest.name,
+                    problems.join("\n  ")
+                ),
+            )
+            .into());
+        }
+    }
+
+    // 出力パスを事前に解決し、生成条件を評価
+    let mut plan = plan_files(&manifest, &context, target, &options.layer_filter())?;
+    let feature_dir = if options.feature_dir {
+        Some(relocate_to_feature_dir(
+            &manifest, &context, target, &mut plan,
+        )?)
+    } else {
+        None
+    };
+    let FilePlan { planned, skipped } = plan;
+
+    // 生成後の案内を事前にレンダリング（失敗時は何も書き込まない）
+    let next_steps = render_next_steps(&manifest, &context)?;
+    let hook_commands = render_hooks(&manifest.hooks.post_generate, &context)?;
+
+    // レイヤー構成の違反を検出（機能ディレクトリに集める場合はレイヤーのルートを使わない）
+    if feature_dir.is_none() {
+        check_layer_layout(&manifest, &planned, target)?;
+    }
+
+    // 適用するmod.rsの登録を選ぶ（明示した登録があればアーキタイプの宣言より優先）
+    let planned_paths: Vec<PathBuf> = planned.iter().map(|output| output.path.clone()).collect();
+    let module_paths = module_paths(&planned);
+    let mod_updates = match &feature_dir {
+        _ if !options.mod_lines.is_empty() => options.mod_lines.clone(),
+        Some(dir) => feature_mod_updates(target, dir, &module_paths),
+        None => {
+            let declared =
+                without_skipped_modules(manifest.mod_updates(), &planned, target, &context)?;
+            if options.layer_filter().is_empty() {
+                declared
+            } else {
+                select_mod_updates(
+                    declared,
+                    &options.layer_filter(),
+                    &module_paths,
+                    target,
+                    &context,
+                )?
+            }
+        }
+    };
+
+    // 同じ名前のモジュールが既に登録されていれば、機能が既に存在する可能性が高い
+    let existing_modules = find_existing_modules(target, &mod_updates, &context)?;
+    if options.no_clobber_modules && !existing_modules.is_empty() {
+        let modules: Vec<String> = existing_modules
+            .iter()
+            .map(|(path, line)| format!("  {}: {}", path.display(), line))
+            .collect();
+        return Err(ArchitectError::new(
+            ErrorKind::Conflict,
+            format!(
+                "Refusing to scaffold: module already registered (the feature likely exists; use `rename` to rename it):\n{}",
+                modules.join("\n")
+            ),
+        )
+        .into());
+    }
+
+    // --merge-markersでは、マーカーのある既存ファイルはその領域だけを置き換える
+    let mut merge_targets = HashMap::new();
+    if options.merge_markers {
+        for output in &planned {
+            let Ok(existing) = fs::read_to_string(&output.path) else {
+                continue;
+            };
+            if !generated_regions(&output.path.display().to_string(), &existing)?.is_empty() {
+                merge_targets.insert(output.path.clone(), existing);
+            }
+        }
+    }
+
+    // 既存ファイルとの衝突を検出
+    if !options.force {
+        let conflicts: Vec<_> = planned
+            .iter()
+            .filter(|output| output.path.exists() && !merge_targets.contains_key(&output.path))
+            .map(|output| format!("  {}", output.path.display()))
+            .collect();
+        if !conflicts.is_empty() {
+            return Err(ArchitectError::new(
+                ErrorKind::Conflict,
+                format!(
+                    "Refusing to overwrite existing files (use --force to overwrite):\n{}",
+                    conflicts.join("\n")
+                ),
+            )
+            .into());
+        }
+    }
+
+    // 依存クレートはバージョンまで指定されていなければ中断する
+    let unversioned: Vec<&str> = manifest
+        .dependencies
+        .iter()
+        .filter(|dependency| parse_dependency(dependency).1.is_none())
+        .map(String::as_str)
+        .collect();
+    if !unversioned.is_empty() {
+        return Err(ArchitectError::new(
+            ErrorKind::Validation,
+            format!(
+                "Archetype '{}' declares dependencies without a version (use name@version): {}",
+                manifest.name,
+                unversioned.join(", ")
+            ),
+        )
+        .into());
+    }
+
+    // 依存クレートを追加するCargo.toml（生成するRustファイルが属するクレート）を生成前に確認する
+    let cargo_tomls = if options.add_deps && !manifest.dependencies.is_empty() {
+        crate_manifests(target, &planned)?
+    } else {
+        Vec::new()
+    };
+
+    // テンプレートを一括で読み込み
+    let archetype_dir = &manifest.dir;
+    let tera = load_templates(
+        archetype_dir,
+        &manifest,
+        planned.iter().map(|output| output.spec),
+    )?;
+
+    // 既存の機能から編集済みの内容を引き継ぐ
+    let mut seeds = match &options.seed_from {
+        Some(seed_name) => seed_contents(&manifest, &tera, options, seed_name, &planned)?,
+        None => HashMap::new(),
+    };
+    let mut seeded = Vec::new();
+
+    // ファイル生成
+    let mut generated = Vec::new();
+    let mut unchanged = Vec::new();
+    let mut rollback = Rollback::default();
+    let mut recorded_files = Vec::new();
+    let mut formatted = Vec::new();
+    let mut rustfmt_available = true;
+    let progress = progress_bar(options.progress, planned.len());
+
+    for output in planned {
+        let file_spec = output.spec;
+        let render_started = Instant::now();
+        let mut rendered = match seeds.remove(&output.path) {
+            Some(content) => {
+                seeded.push(output.path.clone());
+                content
+            }
+            None => render_file(&tera, file_spec, output.context(&context))?,
+        };
+        if let Some(banner) = banner_comment(&output.path, options.banner, archetype, file_spec) {
+            rendered = add_banner(&rendered, &banner);
+        }
+        // 比較・書き込み・記録のハッシュがすべて整形後の内容になるよう、書き込む前に整形する
+        if options.fmt
+            && rustfmt_available
+            && output.path.extension().is_some_and(|ext| ext == "rs")
+        {
+            let outcome = match rustfmt_source(&rendered) {
+                Ok(source) => {
+                    rendered = source;
+                    FormatOutcome::Formatted
+                }
+                Err(outcome) => outcome,
+            };
+            rustfmt_available = !matches!(outcome, FormatOutcome::Unavailable(_));
+            formatted.push((output.path.clone(), outcome));
+        }
+        let rendered = normalize_line_endings(&rendered, options.line_ending);
+        let rendered = match merge_targets.remove(&output.path) {
+            Some(existing) => merge_generated_regions(&output.path, &existing, &rendered)?,
+            None => rendered,
+        };
+        let full_path = output.path;
+        let render_time = render_started.elapsed();
+        progress.inc(1);
+
+        // 既存の内容と同一なら書き込まない（再実行でmtimeを変えない）
+        if fs::read(&full_path).is_ok_and(|existing| existing == rendered.as_bytes()) {
+            if !options.dry_run {
+                if let Some(mode) = &file_spec.mode {
+                    set_file_mode(&full_path, parse_mode(mode)?)?;
+                }
+                recorded_files.push(RecordedFile {
+                    path: relative_to(target, &full_path),
+                    layer: output.layer.clone(),
+                    sha256: content_hash(&rendered),
+                });
+            }
+            unchanged.push(full_path);
+            continue;
+        }
+
+        if !options.dry_run {
+            // ディレクトリ作成
+            if let Some(parent) = full_path.parent() {
+                rollback.create_dir_all(parent)?;
+            }
+
+            // ファイル書き込み
+            rollback.write(&full_path, &rendered)?;
+            if let Some(mode) = &file_spec.mode {
+                set_file_mode(&full_path, parse_mode(mode)?)?;
+            }
+            recorded_files.push(RecordedFile {
+                path: relative_to(target, &full_path),
+                layer: output.layer.clone(),
+                sha256: content_hash(&rendered),
+            });
+        }
+
+        generated.push(GeneratedFile {
+            layer: output.layer,
+            path: full_path,
+            template: file_spec.template_path(archetype_dir)?,
+            bytes: rendered.len(),
+            render_time,
+        });
+    }
+    // 完了後の結果表示で置き換える
+    progress.finish_and_clear();
+
+    let skipped = skipped
+        .into_iter()
+        .map(|output| SkippedFile {
+            layer: output.layer,
+            path: output.path,
+            condition: output.spec.when.clone().unwrap_or_default(),
+        })
+        .collect();
+
+    // mod.rs更新
+    let mut updated = Vec::new();
+    if options.update_mod && !mod_updates.is_empty() {
+        updated = write_mod_updates(
+            target,
+            &mod_updates,
+            &context,
+            options.dry_run,
+            &mut rollback,
+        )?;
+    }
+
+    // 深い階層の出力は、途中のディレクトリにmod.rsを作って上位のモジュールにつなぐ
+    // （登録先を明示した場合は行わない）
+    if options.update_mod && options.mod_lines.is_empty() {
+        let known_modules: Vec<PathBuf> = planned_paths
+            .iter()
+            .chain(updated.iter().map(|update| &update.mod_file))
+            .cloned()
+            .collect();
+        let nested = nested_mod_updates(target, &module_paths, &known_modules);
+        updated.extend(write_mod_updates(
+            target,
+            &nested,
+            &context,
+            options.dry_run,
+            &mut rollback,
+        )?);
+    }
+
+    // Cargo.tomlに依存クレートを追加（失敗時は巻き戻す）
+    let mut added_dependencies = Vec::new();
+    for path in cargo_tomls {
+        let added = add_dependencies(
+            &path,
+            &manifest.dependencies,
+            options.dry_run,
+            &mut rollback,
+        )?;
+        added_dependencies.push((path, added));
+    }
+
+    // 生成記録を保存
+    let mut record = None;
+    if options.record && !options.dry_run {
+        let generation = GenerationRecord {
+            version: RECORD_VERSION,
+            name: snake_name.clone(),
+            archetype: archetype.clone(),
+            description: description.clone(),
+            vars: options.vars.iter().cloned().collect(),
+            files: recorded_files,
+            feature_dir: options.feature_dir,
+            fmt: options.fmt,
+            environment,
+            mod_lines: updated
+                .iter()
+                .filter(|update| update.is_change())
+                .map(|update| RecordedModLine {
+                    mod_file: relative_to(target, &update.mod_file),
+                    line: update.line.clone(),
+                })
+                .collect(),
+        };
+        let path = record_path(target, &snake_name);
+        if let Some(parent) = path.parent() {
+            rollback.create_dir_all(parent)?;
+        }
+        rollback.write(
+            &path,
+            &format!("{}\n", serde_json::to_string_pretty(&generation)?),
+        )?;
+        record = Some(path);
+    }
+
+    // フックを実行（失敗した場合は作成したファイルとmod.rsの変更を巻き戻す）
+    let mut hooks = Vec::new();
+    let mut skipped_hooks = Vec::new();
+    for command in hook_commands {
+        if options.run_hooks && !options.dry_run {
+            let output = run_hook(&command, target, options.stream_hooks)?;
+            hooks.push(HookRun { command, output });
+        } else {
+            skipped_hooks.push(command);
+        }
+    }
+
+    // ここまで到達したら生成成功として確定
+    rollback.commit();
+
+    Ok(ScaffoldReport {
+        name: snake_name,
+        archetype: manifest.name,
+        display_name: manifest.display_name,
+        archetype_description: manifest.description,
+        dry_run: options.dry_run,
+        generated,
+        skipped,
+        unchanged,
+        mod_updates: updated,
+        existing_modules,
+        record,
+        hooks,
+        skipped_hooks,
+        formatted,
+        seeded,
+        dependencies: manifest.dependencies,
+        added_dependencies,
+        next_steps,
+        elapsed: started.elapsed(),
+    })
+}
+
+/// 書き込まずに再生成し、ファイルやmod行が変わるなら差分の概要をエラーにする
+///
+/// 生成されていないファイル、内容が異なるファイル、mod.rsにない登録行を報告する。
+/// 差分がなければ全ファイルを`unchanged`にしたレポートを返す。
+fn assert_unchanged(
+    archetypes_dirs: &[PathBuf],
+    options: &ScaffoldOptions,
+) -> Result<ScaffoldReport> {
+    let check = ScaffoldOptions {
+        dry_run: true,
+        force: true,
+        require_clean: false,
+        run_hooks: false,
+        assert_unchanged: false,
+        ..options.clone()
+    };
+    let report = scaffold_feature(archetypes_dirs, &check)?;
+
+    let mut changes: Vec<String> = report
+        .generated
+        .iter()
+        .map(|file| {
+            let kind = if file.path.exists() {
+                "changed"
+            } else {
+                "missing"
+            };
+            format!("  {}: {}", kind, file.path.display())
+        })
+        .collect();
+    changes.extend(
+        report
+            .mod_updates
+            .iter()
+            .filter(|update| update.is_change())
+            .map(|update| {
+                format!(
+                    "  missing mod line: {}: {}",
+                    update.mod_file.display(),
+                    update.line
+                )
+            }),
+    );
+    if !changes.is_empty() {
+        return Err(ArchitectError::new(
+            ErrorKind::Validation,
+            format!(
+                "--assert-unchanged: regenerating '{}' would change {} item(s) (run `diff` for details):\n{}",
+                report.name,
+                changes.len(),
+                changes.join("\n")
+            ),
+        )
+        .into());
+    }
+    Ok(report)
+}
+
+/// `--merge-markers`で置き換える領域の開始マーカー（`// AEGIS:BEGIN generated`など）
+const GENERATED_BEGIN: &str = "AEGIS:BEGIN generated";
+/// `--merge-markers`で置き換える領域の終了マーカー
+const GENERATED_END: &str = "AEGIS:END generated";
+
+/// マーカーで囲まれた領域の開始行と終了行（0始まり）を返す
+///
+/// 対応しないマーカーや入れ子のマーカーはエラーにする（`source`はエラーに表示する内容の出どころ）。
+fn generated_regions(source: &str, content: &str) -> Result<Vec<(usize, usize)>> {
+    let unbalanced = |line: usize, message: &str| -> anyhow::Error {
+        ArchitectError::new(
+            ErrorKind::Validation,
+            format!(
+                "Unbalanced generated markers in {} (line {}): {}",
+                source,
+                line + 1,
+                message
+            ),
+        )
+        .into()
+    };
+
+    let mut regions = Vec::new();
+    let mut begin = None;
+    for (index, line) in content.lines().enumerate() {
+        if line.contains(GENERATED_BEGIN) {
+            if let Some(open) = begin {
+                return Err(unbalanced(
+                    index,
+                    &format!(
+                        "{} inside the region opened at line {}",
+                        GENERATED_BEGIN,
+                        open + 1
+                    ),
+                ));
+            }
+            begin = Some(index);
+        } else if line.contains(GENERATED_END) {
+            let Some(open) = begin.take() else {
+                return Err(unbalanced(
+                    index,
+                    &format!("{} without {}", GENERATED_END, GENERATED_BEGIN),
+                ));
+            };
+            regions.push((open, index));
+        }
+    }
+    if let Some(open) = begin {
+        return Err(unbalanced(
+            open,
+            &format!("{} without {}", GENERATED_BEGIN, GENERATED_END),
+        ));
+    }
+    Ok(regions)
+}
+
+/// 既存ファイルのマーカーで囲まれた領域を、レンダリング結果の対応する領域で置き換える
+///
+/// 領域の外（マーカーの行を含む）は既存の内容をそのまま残す。領域は出現順に対応させ、
+/// 数が異なる場合はエラーにする。
+fn merge_generated_regions(path: &Path, existing: &str, rendered: &str) -> Result<String> {
+    let existing_regions = generated_regions(&path.display().to_string(), existing)?;
+    let rendered_regions = generated_regions(
+        &format!("the template output for {}", path.display()),
+        rendered,
+    )?;
+    if existing_regions.len() != rendered_regions.len() {
+        return Err(ArchitectError::new(
+            ErrorKind::Conflict,
+            format!(
+                "{} has {} generated region(s) but the template renders {}",
+                path.display(),
+                existing_regions.len(),
+                rendered_regions.len()
+            ),
+        )
+        .into());
+    }
+
+    let existing_lines: Vec<&str> = existing.split_inclusive('\n').collect();
+    let rendered_lines: Vec<&str> = rendered.split_inclusive('\n').collect();
+    let mut merged = String::new();
+    let mut next = 0;
+    for ((begin, end), (rendered_begin, rendered_end)) in
+        existing_regions.into_iter().zip(rendered_regions)
+    {
+        merged.extend(existing_lines[next..=begin].iter().copied());
+        merged.extend(
+            rendered_lines[rendered_begin + 1..rendered_end]
+                .iter()
+                .copied(),
+        );
+        next = end;
+    }
+    merged.extend(existing_lines[next..].iter().copied());
+    Ok(merged)
+}
+
+/// `--seed-from`の機能のファイルのうち、生成時から編集されているものの内容を集める
+///
+/// 同じファイル仕様から生成される出力同士を対応させ、既存のファイルが自身の
+/// テンプレートの出力と異なる（テンプレートのままではない）場合だけ引き継ぐ。
+/// 引き継ぐ内容では旧名のsnake_case・PascalCaseを新しい名前に置換する。
+/// 既存の機能の生成記録があれば、その説明と変数でテンプレートを描画する。
+/// 戻り値は新しい機能の出力先のパスから内容への対応。
+fn seed_contents(
+    manifest: &Manifest,
+    tera: &Tera,
+    options: &ScaffoldOptions,
+    seed_name: &str,
+    planned: &[PlannedOutput],
+) -> Result<HashMap<PathBuf, String>> {
+    let seed_snake = to_snake_case(seed_name);
+    validate_feature_name(&seed_snake)?;
+    let seed_pascal = seed_snake.to_pascal_case();
+    let new_snake = to_snake_case(&options.name);
+    let new_pascal = new_snake.to_pascal_case();
+    let target = &options.target;
+
+    let record = read_record(&record_path(target, &seed_snake))?;
+    let environment = recorded_environment(record.as_ref(), target);
+    let (description, vars) = match record {
+        Some(record) => (record.description, record.vars.into_iter().collect()),
+        None => (options.description.clone(), options.vars.clone()),
+    };
+    let mut context = build_context(
+        &seed_snake,
+        &seed_pascal,
+        &description,
+        &manifest.vars_with_defaults(&vars),
+    )?;
+    insert_environment_vars(&mut context, &environment);
+    let mut seed_plan = plan_files(manifest, &context, target, &options.layer_filter())?;
+    if options.feature_dir {
+        relocate_to_feature_dir(manifest, &context, target, &mut seed_plan)?;
+    }
+    if !seed_plan.planned.iter().any(|output| output.path.exists()) {
+        anyhow::bail!(
+            "--seed-from: no generated files found for feature '{}' in {:?}",
+            seed_snake,
+            target
+        );
+    }
+
+    let mut remaining: Vec<PlannedOutput> = seed_plan.planned;
+    let mut seeds = HashMap::new();
+    for output in planned {
+        let Some(index) = remaining
+            .iter()
+            .position(|seed| std::ptr::eq(seed.spec, output.spec))
+        else {
+            continue;
+        };
+        let seed = remaining.remove(index);
+        if !seed.path.exists() {
+            continue;
+        }
+        let current = fs::read_to_string(&seed.path)
+            .with_context(|| format!("Failed to read file: {:?}", seed.path))?;
+        let boilerplate = normalize_line_endings(
+            &render_file(tera, seed.spec, seed.context(&context))?,
+            LineEnding::Lf,
+        );
+        // テンプレートの出力のままなら、新しい機能もテンプレートから描画すればよい
+        if normalize_line_endings(&strip_banner(&current), LineEnding::Lf) == boilerplate {
+            continue;
+        }
+        seeds.insert(
+            output.path.clone(),
+            replace_identifier(
+                &replace_identifier(&current, &seed_pascal, &new_pascal),
+                &seed_snake,
+                &new_snake,
+            ),
+        );
+    }
+    Ok(seeds)
+}
+
+/// `text`のうち識別子全体が`from`であるものだけを`to`に置き換える
+///
+/// 前後が`[A-Za-z0-9_]`の文字で続く出現（`stock_price`の中の`stock`など）は置き換えない。
+fn replace_identifier(text: &str, from: &str, to: &str) -> String {
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    for (start, _) in text.match_indices(from) {
+        let end = start + from.len();
+        if start < last
+            || text[..start].chars().next_back().is_some_and(is_ident)
+            || text[end..].chars().next().is_some_and(is_ident)
+        {
+            continue;
+        }
+        result.push_str(&text[last..start]);
+        result.push_str(to);
+        last = end;
+    }
+    result.push_str(&text[last..]);
+    result
+}
+
+/// 生成計画の1ファイル（`--plan`の出力）
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PlannedFile {
+    /// アーキタイプディレクトリからのテンプレートの相対パス
+    pub template: String,
+    /// マニフェストに書かれた出力パス（レンダリング前）
+    pub output: String,
+    /// レイヤー名
+    pub layer: String,
+    /// 変数を展開した出力先のパス
+    pub resolved_path: PathBuf,
+}
+
+/// テンプレートをレンダリングせず、生成されるファイルの一覧だけを解決する
+///
+/// 生成条件（`when`）が偽のファイルと`--only-layer`で除外したファイルは含まない。
+pub fn plan_feature(
+    archetypes_dirs: &[PathBuf],
+    options: &ScaffoldOptions,
+) -> Result<Vec<PlannedFile>> {
+    let snake_name = to_snake_case(&options.name);
+    validate_feature_name(&snake_name)?;
+    let pascal_name = snake_name.to_pascal_case();
+
+    let mut manifest = load_archetype_with_shared_templates(
+        archetypes_dirs,
+        &options.archetype,
+        options.shared_templates_dir.as_deref(),
+    )?;
+    manifest.check_required_vars(&options.vars)?;
+    let mut context = build_context(
+        &snake_name,
+        &pascal_name,
+        &options.description,
+        &manifest.vars_with_defaults(&options.vars),
+    )?;
+    insert_environment_vars(&mut context, &environment_vars(&options.target));
+    apply_template_overrides(&mut manifest, &options.template_overrides, &context)?;
+    let mut plan = plan_files(
+        &manifest,
+        &context,
+        &options.target,
+        &options.layer_filter(),
+    )?;
+    if options.feature_dir {
+        relocate_to_feature_dir(&manifest, &context, &options.target, &mut plan)?;
+    }
+    let FilePlan { planned, .. } = plan;
+
+    Ok(planned
+        .into_iter()
+        .map(|output| PlannedFile {
+            template: output.spec.template.clone(),
+            output: output.spec.output.clone(),
+            layer: output.layer,
+            resolved_path: output.path,
+        })
+        .collect())
+}
+
+/// アーキタイプの`prompt_template`を機能のコンテキストでレンダリングする（`--emit-prompt`）
+///
+/// ファイルは生成しない。テンプレート変数に加えて、生成されるファイルの一覧を
+/// `files`（`path`と`layer`の配列。`path`は生成先からの相対パス）として渡す。
+pub fn emit_prompt(archetypes_dirs: &[PathBuf], options: &ScaffoldOptions) -> Result<String> {
+    let snake_name = to_snake_case(&options.name);
+    validate_feature_name(&snake_name)?;
+    let pascal_name = snake_name.to_pascal_case();
+
+    let manifest = load_archetype_with_shared_templates(
+        archetypes_dirs,
+        &options.archetype,
+        options.shared_templates_dir.as_deref(),
+    )?;
+    let Some(template_path) = manifest.prompt_template_path() else {
+        anyhow::bail!(
+            "Archetype '{}' does not declare a prompt_template",
+            manifest.name
+        );
+    };
+    let mut context = build_context(
+        &snake_name,
+        &pascal_name,
+        &options.description,
+        &manifest.vars_with_defaults(&options.vars),
+    )?;
+    insert_environment_vars(&mut context, &environment_vars(&options.target));
+    let mut plan = plan_files(
+        &manifest,
+        &context,
+        &options.target,
+        &options.layer_filter(),
+    )?;
+    if options.feature_dir {
+        relocate_to_feature_dir(&manifest, &context, &options.target, &mut plan)?;
+    }
+    let files: Vec<serde_json::Value> = plan
+        .planned
+        .iter()
+        .map(|output| {
+            serde_json::json!({
+                "path": relative_to(&options.target, &output.path),
+                "layer": output.layer,
+            })
+        })
+        .collect();
+    context.insert("files", &files);
+
+    let content = read_template(&template_path)
+        .with_context(|| format!("Failed to read prompt template: {:?}", template_path))?;
+    let mut tera = load_templates(
+        &manifest.dir,
+        &manifest,
+        plan.planned.iter().map(|output| output.spec),
+    )?;
+    tera.render_str(&content, &context).map_err(|err| {
+        let message = match undefined_variable(&err) {
+            Some(var) => format!(
+                "Failed to render prompt template: {} (undefined variable `{}`)",
+                template_path.display(),
+                var
+            ),
+            None => format!(
+                "Failed to render prompt template: {}",
+                template_path.display()
+            ),
+        };
+        anyhow::Error::new(err).context(message)
+    })
+}
+
+/// アーキタイプのファイル仕様1件（`describe --show-files`の出力）
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ArchetypeFile {
+    /// アーキタイプディレクトリからのテンプレートの相対パス
+    pub template: String,
+    /// テンプレートファイルのパス（継承元の仕様は継承元のディレクトリ）
+    pub template_path: PathBuf,
+    /// テンプレートファイルが存在するか
+    pub template_exists: bool,
+    /// マニフェストに書かれた出力パス（レンダリング前）
+    pub output: String,
+    /// 仮の機能名で展開した出力パス（`for_each`の`item`など展開できない場合は`None`）
+    pub resolved_output: Option<String>,
+    /// レイヤー名
+    pub layer: String,
+    /// 生成条件
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub when: Option<String>,
+}
+
+/// アーキタイプのファイル仕様を、仮の機能名で出力パスを解決して一覧にする
+///
+/// テンプレートはレンダリングせず、生成条件も評価しない。変数の既定値は
+/// マニフェストの`default_vars`を使う。
+pub fn describe_archetype_files(
+    archetypes_dirs: &[PathBuf],
+    archetype: &str,
+    name: &str,
+    shared_templates_dir: Option<&Path>,
+) -> Result<Vec<ArchetypeFile>> {
+    let snake_name = to_snake_case(name);
+    validate_feature_name(&snake_name)?;
+    let pascal_name = snake_name.to_pascal_case();
+
+    let manifest =
+        load_archetype_with_shared_templates(archetypes_dirs, archetype, shared_templates_dir)?;
+    let mut context = build_context(
+        &snake_name,
+        &pascal_name,
+        "",
+        &manifest.vars_with_defaults(&[]),
+    )?;
+    insert_environment_vars(&mut context, &environment_vars(Path::new(".")));
+
+    Ok(manifest
+        .files
+        .iter()
+        .map(|file_spec| {
+            let template_path = file_spec.template_path(&manifest.dir);
+            ArchetypeFile {
+                template: file_spec.template.clone(),
+                template_exists: template_path.as_ref().is_ok_and(|path| path.is_file()),
+                template_path: template_path.unwrap_or_else(|_| PathBuf::from(&file_spec.template)),
+                output: file_spec.output.clone(),
+                resolved_output: render_inline(&file_spec.output, &context)
+                    .ok()
+                    .map(|output| {
+                        let layer = render_inline(&file_spec.layer, &context)
+                            .unwrap_or_else(|_| file_spec.layer.clone());
+                        manifest.in_layer_crate(&layer, &output)
+                    }),
+                layer: file_spec.layer.clone(),
+                when: file_spec.when.clone(),
+            }
+        })
+        .collect())
+}
+
+/// 機能削除のオプション
+pub struct RemoveOptions {
+    /// 機能名（正規化前）
+    pub name: String,
+    /// 生成時の機能の説明
+    pub description: String,
+    /// 生成時のアーキタイプ名
+    pub archetype: String,
+    /// 生成先ディレクトリ
+    pub target: PathBuf,
+    /// 生成時のテンプレート変数
+    pub vars: Vec<(String, serde_json::Value)>,
+    /// 編集済みのファイルも削除するか
+    pub force: bool,
+    /// 出力の詳細度
+    pub verbosity: Verbosity,
+    /// `@shared/`のテンプレートを解決する共有テンプレートのディレクトリ
+    pub shared_templates_dir: Option<PathBuf>,
+}
+
+/// 生成記録の形式のバージョン（形式を変えたら上げ、古い記録を移行する）
+const RECORD_VERSION: u32 = 1;
+
+/// 生成記録を置くディレクトリ（生成先からの相対）
+const RECORD_DIR: &str = ".aegis";
+
+/// 1回の生成の記録（`.aegis/<name>.json`）
+///
+/// `remove`や`diff`はこの記録があれば、パスや変数を再導出せずにそのまま使う。
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+struct GenerationRecord {
+    /// 記録形式のバージョン
+    version: u32,
+    /// 機能名（正規化後）
+    name: String,
+    /// 使用したアーキタイプ名
+    archetype: String,
+    /// 機能の説明
+    description: String,
+    /// ユーザー定義のテンプレート変数
+    vars: BTreeMap<String, serde_json::Value>,
+    /// 生成したファイル
+    files: Vec<RecordedFile>,
+    /// 追記したmod行
+    mod_lines: Vec<RecordedModLine>,
+    /// `--feature-dir`で機能ディレクトリに生成したか
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    feature_dir: bool,
+    /// `--fmt`でRustファイルを整形して生成したか（`diff`で同じく整形して比べる）
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    fmt: bool,
+    /// 生成時の実行環境の変数（`date`など。再レンダリングで同じ値を使う）
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    environment: BTreeMap<String, serde_json::Value>,
+}
+
+/// 生成記録に含めるファイル
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+struct RecordedFile {
+    /// 生成先からの相対パス（`/`区切り）
+    path: String,
+    /// レイヤー名
+    layer: String,
+    /// 生成時の内容のSHA-256
+    sha256: String,
+}
+
+/// 生成記録に含めるmod行
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+struct RecordedModLine {
+    /// 生成先からのmod.rsの相対パス（`/`区切り）
+    mod_file: String,
+    /// 追記した行
+    line: String,
+}
+
+/// 機能の生成記録のパス
+fn record_path(target: &Path, snake_name: &str) -> PathBuf {
+    target.join(RECORD_DIR).join(format!("{}.json", snake_name))
+}
+
+/// 生成記録を読み込む。記録がなければ`None`
+fn read_record(path: &Path) -> Result<Option<GenerationRecord>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read record: {:?}", path))?;
+    let record: GenerationRecord = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse record: {:?}", path))?;
+    if record.version > RECORD_VERSION {
+        anyhow::bail!(
+            "Record {:?} has version {}, but this tool only supports up to {}",
+            path,
+            record.version,
+            RECORD_VERSION
+        );
+    }
+    Ok(Some(record))
+}
+
+/// 内容のSHA-256を16進文字列で返す
+fn content_hash(content: &str) -> String {
+    use sha2::{Digest, Sha256};
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+/// 生成先からの相対パスを`/`区切りの文字列にする
+fn relative_to(target: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(target).unwrap_or(path);
+    let components: Vec<_> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    components.join("/")
+}
+
+/// 絶対パスの文字列にする（カレントディレクトリを基準に、`..`などは正規化しない）
+fn absolute_path(path: &Path) -> String {
+    std::path::absolute(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// 機能に属する生成物
+struct FeatureOutputs {
+    /// ディスク上に存在するファイル
+    files: Vec<OwnedFile>,
+    /// 追記されたmod行（mod.rsのパス、行）
+    mod_lines: Vec<(PathBuf, String)>,
+}
+
+/// 削除対象のファイル
+struct OwnedFile {
+    layer: String,
+    path: PathBuf,
+    /// 生成時から内容が変わっているか
+    modified: bool,
+}
+
+/// スキャフォールド済みの機能を削除
+///
+/// 生成記録があれば記録されたファイル・mod行を対象にし、ハッシュで編集の有無を判定する。
+/// 記録がなければ同じ名前・アーキタイプで再レンダリングした結果と比較し、
+/// `update_mod_files`が追加したmod行を取り除く。
+pub fn remove_feature(archetypes_dirs: &[PathBuf], options: &RemoveOptions) -> Result<()> {
+    let snake_name = to_snake_case(&options.name);
+    let target = &options.target;
+    let record_path = record_path(target, &snake_name);
+
+    let FeatureOutputs {
+        files: owned,
+        mod_lines,
+    } = match read_record(&record_path)? {
+        Some(record) => {
+            status!(
+                options.verbosity,
+                "Using generation record: {}",
+                record_path.display()
+            );
+            recorded_outputs(target, &record)?
+        }
+        None => derived_outputs(archetypes_dirs, options, &snake_name)?,
+    };
+
+    // 生成時から編集されたファイルを検出
+    let modified: Vec<_> = owned
+        .iter()
+        .filter(|file| file.modified)
+        .map(|file| format!("  {}", file.path.display()))
+        .collect();
+    if !modified.is_empty() && !options.force {
+        return Err(ArchitectError::new(
+            ErrorKind::Conflict,
+            format!(
+                "Refusing to remove files that differ from what was generated (use --force to remove anyway):\n{}",
+                modified.join("\n")
+            ),
+        )
+        .into());
+    }
+
+    status!(
+        options.verbosity,
+        "Removing feature '{}':",
+        snake_name.cyan()
+    );
+    for file in &owned {
+        fs::remove_file(&file.path)
+            .with_context(|| format!("Failed to remove file: {:?}", file.path))?;
+        status!(
+            options.verbosity,
+            "  [{}] {}",
+            file.layer.to_uppercase().red(),
+            file.path.display()
+        );
+    }
+
+    // mod.rsから宣言を削除
+    let mut cleaned = Vec::new();
+    for (mod_path, mod_line) in mod_lines {
+        if !mod_path.exists() {
+            continue;
+        }
+        let content = fs::read_to_string(&mod_path)
+            .with_context(|| format!("Failed to read mod file: {:?}", mod_path))?;
+        if let Some(new_content) = remove_mod_line(&content, &mod_line) {
+            fs::write(&mod_path, new_content)
+                .with_context(|| format!("Failed to write mod file: {:?}", mod_path))?;
+            cleaned.push(mod_path);
+        }
+    }
+    if !cleaned.is_empty() {
+        status!(options.verbosity, "\nUpdated mod.rs files:");
+        for path in cleaned {
+            status!(options.verbosity, "  {}", path.display());
+        }
+    }
+
+    if record_path.exists() {
+        fs::remove_file(&record_path)
+            .with_context(|| format!("Failed to remove record: {:?}", record_path))?;
+        // 他の記録が残っていなければ.aegisも削除する（空でなければ失敗して残る）
+        if let Some(dir) = record_path.parent() {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+
+    status!(options.verbosity);
+    println!(
+        "{}",
+        format!("Removed {} files for feature '{}'", owned.len(), snake_name)
+            .green()
+            .bold()
+    );
+    Ok(())
+}
+
+/// 生成記録から削除対象のファイルとmod行を求める
+fn recorded_outputs(target: &Path, record: &GenerationRecord) -> Result<FeatureOutputs> {
+    let mut owned = Vec::new();
+    for file in &record.files {
+        let path = resolve_output_path(target, &file.path)?;
+        if !path.exists() {
+            continue;
+        }
+        let current = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read file: {:?}", path))?;
+        owned.push(OwnedFile {
+            layer: file.layer.clone(),
+            modified: content_hash(&current) != file.sha256,
+            path,
+        });
+    }
+
+    let mod_lines = record
+        .mod_lines
+        .iter()
+        .map(|mod_line| {
+            Ok((
+                resolve_output_path(target, &mod_line.mod_file)?,
+                mod_line.line.clone(),
+            ))
+        })
+        .collect::<Result<_>>()?;
+    Ok(FeatureOutputs {
+        files: owned,
+        mod_lines,
+    })
+}
+
+/// 生成記録がない場合に、再レンダリングして削除対象のファイルとmod行を求める
+fn derived_outputs(
+    archetypes_dirs: &[PathBuf],
+    options: &RemoveOptions,
+    snake_name: &str,
+) -> Result<FeatureOutputs> {
+    let pascal_name = snake_name.to_pascal_case();
+    let target = &options.target;
+
+    let manifest = load_archetype_with_shared_templates(
+        archetypes_dirs,
+        &options.archetype,
+        options.shared_templates_dir.as_deref(),
+    )?;
+    let mut context = build_context(
+        snake_name,
+        &pascal_name,
+        &options.description,
+        &manifest.vars_with_defaults(&options.vars),
+    )?;
+    insert_environment_vars(&mut context, &environment_vars(target));
+    let FilePlan { planned, .. } =
+        plan_files(&manifest, &context, target, &LayerFilter::default())?;
+    let tera = load_templates(
+        &manifest.dir,
+        &manifest,
+        planned.iter().map(|output| output.spec),
+    )?;
+
+    let mut owned = Vec::new();
+    for output in planned {
+        if !output.path.exists() {
+            continue;
+        }
+        let rendered = normalize_line_endings(
+            &strip_banner(&render_file(&tera, output.spec, output.context(&context))?),
+            LineEnding::Lf,
+        );
+        let current = fs::read_to_string(&output.path)
+            .with_context(|| format!("Failed to read file: {:?}", output.path))?;
+        let current = normalize_line_endings(&strip_banner(&current), LineEnding::Lf);
+        // 改行コードの違いやバナーの有無、`--fmt`による整形だけでは変更とみなさない
+        let modified = current != rendered
+            && formatted_rust(&output.path, &rendered).is_none_or(|formatted| current != formatted);
+        owned.push(OwnedFile {
+            layer: output.layer.clone(),
+            modified,
+            path: output.path,
+        });
+    }
+
+    let mut mod_lines = Vec::new();
+    for mod_update in manifest.mod_updates() {
+        let mod_path =
+            resolve_output_path(target, &render_inline(&mod_update.mod_file, &context)?)?;
+        mod_lines.push((mod_path, render_inline(&mod_update.line, &context)?));
+    }
+    Ok(FeatureOutputs {
+        files: owned,
+        mod_lines,
+    })
+}
+
+/// 生成されなくなったファイルの削除のオプション
+pub struct PruneOptions {
+    /// 機能名（正規化前）
+    pub name: String,
+    /// 生成先ディレクトリ
+    pub target: PathBuf,
+    /// 実際に削除するか（`false`なら一覧を表示するだけ）
+    pub force: bool,
+    /// 出力の詳細度
+    pub verbosity: Verbosity,
+}
+
+/// 生成記録にあるが、現在のマニフェストでは生成されなくなったファイルとmod行を削除
+///
+/// 記録されたアーキタイプ・説明・変数で出力パスを解決し直し、記録のうち
+/// ディスク上に残っていて解決結果に含まれないファイルを対象にする。
+/// `force`が偽なら一覧を表示するだけで何も変更しない。
+pub fn prune_feature(archetypes_dirs: &[PathBuf], options: &PruneOptions) -> Result<()> {
+    let snake_name = to_snake_case(&options.name);
+    let pascal_name = snake_name.to_pascal_case();
+    let target = &options.target;
+    let record_path = record_path(target, &snake_name);
+    let Some(mut record) = read_record(&record_path)? else {
+        anyhow::bail!(
+            "No generation record for feature '{}' at {:?} (scaffold with --record to enable prune)",
+            snake_name,
+            record_path
+        );
+    };
+
+    let manifest = load_archetype(archetypes_dirs, &record.archetype)?;
+    let vars: Vec<_> = record.vars.clone().into_iter().collect();
+    let mut context = build_context(
+        &snake_name,
+        &pascal_name,
+        &record.description,
+        &manifest.vars_with_defaults(&vars),
+    )?;
+    insert_environment_vars(&mut context, &recorded_environment(Some(&record), target));
+    let (plan, feature_dir) =
+        plan_generated_files(&manifest, &context, target, record.feature_dir)?;
+    let planned_paths: Vec<PathBuf> = plan
+        .planned
+        .iter()
+        .map(|output| output.path.clone())
+        .collect();
+    let mod_updates = match &feature_dir {
+        Some(dir) => feature_mod_updates(target, dir, &module_paths(&plan.planned)),
+        None => manifest.mod_updates(),
+    };
+    let mut current_mod_lines = Vec::new();
+    for mod_update in &mod_updates {
+        let mod_path =
+            resolve_output_path(target, &render_inline(&mod_update.mod_file, &context)?)?;
+        current_mod_lines.push((mod_path, render_inline(&mod_update.line, &context)?));
+    }
+
+    let FeatureOutputs {
+        files: recorded,
+        mod_lines: recorded_mod_lines,
+    } = recorded_outputs(target, &record)?;
+    let stale: Vec<OwnedFile> = recorded
+        .into_iter()
+        .filter(|file| !planned_paths.contains(&file.path))
+        .collect();
+    // 途中のディレクトリのmod.rsへの登録は、生成するファイルを宣言していれば残す
+    let stale_mod_lines: Vec<(PathBuf, String)> = recorded_mod_lines
+        .into_iter()
+        .filter(|mod_line| !current_mod_lines.contains(mod_line))
+        .filter(|(mod_path, line)| !declares_planned_module(mod_path, line, &planned_paths))
+        .collect();
+
+    if stale.is_empty() && stale_mod_lines.is_empty() {
+        println!(
+            "{}",
+            format!("No stale files for feature '{}'", snake_name).green()
+        );
+        return Ok(());
+    }
+
+    status!(
+        options.verbosity,
+        "Stale outputs of feature '{}' (no longer generated by '{}'):",
+        snake_name.cyan(),
+        record.archetype
+    );
+    for file in &stale {
+        let edited = if file.modified { " (modified)" } else { "" };
+        status!(
+            options.verbosity,
+            "  [{}] {}{}",
+            file.layer.to_uppercase().red(),
+            file.path.display(),
+            edited.yellow()
+        );
+    }
+    for (mod_path, mod_line) in &stale_mod_lines {
+        status!(options.verbosity, "  {} ({})", mod_path.display(), mod_line);
+    }
+
+    if !options.force {
+        status!(options.verbosity);
+        println!(
+            "{}",
+            format!(
+                "Found {} stale files for feature '{}'; re-run with --force to delete them",
+                stale.len(),
+                snake_name
+            )
+            .yellow()
+        );
+        return Ok(());
+    }
+
+    for file in &stale {
+        fs::remove_file(&file.path)
+            .with_context(|| format!("Failed to remove file: {:?}", file.path))?;
+        remove_empty_dirs(target, &file.path);
+    }
+    for (mod_path, mod_line) in &stale_mod_lines {
+        if !mod_path.exists() {
+            continue;
+        }
+        let content = fs::read_to_string(mod_path)
+            .with_context(|| format!("Failed to read mod file: {:?}", mod_path))?;
+        if let Some(new_content) = remove_mod_line(&content, mod_line) {
+            fs::write(mod_path, new_content)
+                .with_context(|| format!("Failed to write mod file: {:?}", mod_path))?;
+        }
+    }
+
+    // 削除したものを記録から除く
+    let stale_paths: Vec<String> = stale
+        .iter()
+        .map(|file| relative_to(target, &file.path))
+        .collect();
+    record
+        .files
+        .retain(|file| !stale_paths.contains(&file.path));
+    record.mod_lines.retain(|mod_line| {
+        !stale_mod_lines.iter().any(|(path, line)| {
+            relative_to(target, path) == mod_line.mod_file && *line == mod_line.line
+        })
+    });
+    fs::write(
+        &record_path,
+        format!("{}\n", serde_json::to_string_pretty(&record)?),
+    )
+    .with_context(|| format!("Failed to write record: {:?}", record_path))?;
+
+    status!(options.verbosity);
+    println!(
+        "{}",
+        format!(
+            "Pruned {} stale files for feature '{}'",
+            stale.len(),
+            snake_name
+        )
+        .green()
+        .bold()
+    );
+    Ok(())
+}
+
+/// 機能名の変更のオプション
+pub struct RenameOptions {
+    /// 現在の機能名（正規化前）
+    pub old: String,
+    /// 新しい機能名（正規化前）
+    pub new: String,
+    /// 生成時のアーキタイプ名
+    pub archetype: String,
+    /// 生成先ディレクトリ
+    pub target: PathBuf,
+    /// 生成時のテンプレート変数
+    pub vars: Vec<(String, serde_json::Value)>,
+    /// 移動したファイル内の旧名（snake_case・PascalCase）を新しい名前に置換するか
+    pub rewrite_contents: bool,
+    /// `--feature-dir`で生成した機能として扱うか（生成記録があれば記録に従う）
+    pub feature_dir: bool,
+    /// 出力の詳細度
+    pub verbosity: Verbosity,
+}
+
+/// スキャフォールド済みの機能の名前を変更
+///
+/// 旧名と新名でそれぞれ出力パスを解決し、存在するファイルを新しいパスへ移動する。
+/// 旧名のmod行を取り除き、同じmod.rsへ新しい名前の行を`update_mod_files`で追加する。
+/// 機能ディレクトリに生成した機能は、ディレクトリ内のmod.rsも新しいディレクトリへ移す。
+/// 生成記録があれば記録されたアーキタイプ・変数を使い、記録も新しい名前に移す。
+pub fn rename_feature(archetypes_dirs: &[PathBuf], options: &RenameOptions) -> Result<()> {
+    let old_snake = to_snake_case(&options.old);
+    let new_snake = to_snake_case(&options.new);
+    validate_feature_name(&new_snake)?;
+    if old_snake == new_snake {
+        anyhow::bail!("Feature is already named '{}'", new_snake);
+    }
+    let old_pascal = old_snake.to_pascal_case();
+    let new_pascal = new_snake.to_pascal_case();
+    let target = &options.target;
+
+    let old_record_path = record_path(target, &old_snake);
+    let new_record_path = record_path(target, &new_snake);
+    let record = read_record(&old_record_path)?;
+    let (archetype, description, vars) = match &record {
+        Some(record) => {
+            status!(
+                options.verbosity,
+                "Using generation record: {}",
+                old_record_path.display()
+            );
+            (
+                record.archetype.clone(),
+                record.description.clone(),
+                record.vars.clone().into_iter().collect(),
+            )
+        }
+        None => (
+            options.archetype.clone(),
+            String::new(),
+            options.vars.clone(),
+        ),
+    };
+
+    let manifest = load_archetype(archetypes_dirs, &archetype)?;
+    let vars = manifest.vars_with_defaults(&vars);
+    let environment = recorded_environment(record.as_ref(), target);
+    let mut old_context = build_context(&old_snake, &old_pascal, &description, &vars)?;
+    insert_environment_vars(&mut old_context, &environment);
+    let mut new_context = build_context(&new_snake, &new_pascal, &description, &vars)?;
+    insert_environment_vars(&mut new_context, &environment);
+    let feature_dir = record
+        .as_ref()
+        .map_or(options.feature_dir, |record| record.feature_dir);
+    let (old_plan, old_dir) = plan_generated_files(&manifest, &old_context, target, feature_dir)?;
+    let (new_plan, new_dir) = plan_generated_files(&manifest, &new_context, target, feature_dir)?;
+    if old_plan.planned.len() != new_plan.planned.len() {
+        anyhow::bail!(
+            "Archetype '{}' generates a different set of files for '{}' and '{}'",
+            archetype,
+            old_snake,
+            new_snake
+        );
+    }
+
+    let feature_modules = match (&old_dir, &new_dir) {
+        (Some(old_dir), Some(new_dir)) => Some(FeatureDirModules::new(
+            old_dir,
+            new_dir,
+            old_plan
+                .planned
+                .iter()
+                .zip(&new_plan.planned)
+                .map(|(old, new)| (old.path.as_path(), new.path.as_path())),
+        )),
+        _ => None,
+    };
+    let moves: Vec<(&str, PathBuf, PathBuf)> = old_plan
+        .planned
+        .iter()
+        .zip(new_plan.planned)
+        .filter(|(old, _)| old.path.exists())
+        .map(|(old, new)| (old.layer.as_str(), old.path.clone(), new.path))
+        .collect();
+    if moves.is_empty() {
+        anyhow::bail!(
+            "No generated files found for feature '{}' in {:?}",
+            old_snake,
+            target
+        );
+    }
+
+    // 新しい名前のファイルが既にあれば何も変更しない
+    let mut conflicts: Vec<String> = moves
+        .iter()
+        .filter(|(_, from, to)| from != to && to.exists())
+        .map(|(_, _, to)| format!("  {}", to.display()))
+        .collect();
+    if let Some(modules) = &feature_modules {
+        conflicts.extend(
+            modules
+                .moved_mod_files()
+                .filter(|(from, to)| from.is_file() && to.exists())
+                .map(|(_, to)| format!("  {}", to.display())),
+        );
+    }
+    if record.is_some() && new_record_path.exists() {
+        conflicts.push(format!("  {}", new_record_path.display()));
+    }
+    if !conflicts.is_empty() {
+        return Err(ArchitectError::new(
+            ErrorKind::Conflict,
+            format!(
+                "Refusing to rename: files for '{}' already exist:\n{}",
+                new_snake,
+                conflicts.join("\n")
+            ),
+        )
+        .into());
+    }
+
+    status!(
+        options.verbosity,
+        "Renaming feature '{}' to '{}':",
+        old_snake.cyan(),
+        new_snake.cyan()
+    );
+    let mut moved_files = Vec::new();
+    for (layer, from, to) in &moves {
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+        }
+        let content =
+            fs::read_to_string(from).with_context(|| format!("Failed to read file: {:?}", from))?;
+        fs::rename(from, to).with_context(|| format!("Failed to move {:?} to {:?}", from, to))?;
+        let mut moved = content.clone();
+        if options.rewrite_contents {
+            moved = replace_identifier(
+                &replace_identifier(&content, &old_pascal, &new_pascal),
+                &old_snake,
+                &new_snake,
+            );
+            if moved != content {
+                fs::write(to, &moved).with_context(|| format!("Failed to write file: {:?}", to))?;
+            }
+        }
+        remove_empty_dirs(target, from);
+        moved_files.push(MovedFile {
+            from: relative_to(target, from),
+            to: relative_to(target, to),
+            original_sha256: content_hash(&content),
+            sha256: content_hash(&moved),
+        });
+        status!(
+            options.verbosity,
+            "  [{}] {} -> {}",
+            layer.to_uppercase().green(),
+            from.display(),
+            to.display()
+        );
+    }
+
+    // 機能ディレクトリ内のmod.rsは新しいディレクトリへ移し、記録のmod行も読み替える
+    if let Some(modules) = &feature_modules {
+        let updated = modules.apply(target)?;
+        if !updated.is_empty() {
+            status!(options.verbosity, "\nUpdated mod.rs files:");
+            for path in &updated {
+                status!(options.verbosity, "  {}", path.display());
+            }
+        }
+        if let Some(record) = record {
+            let mod_lines = record
+                .mod_lines
+                .iter()
+                .map(|mod_line| {
+                    let (mod_file, line) =
+                        modules.rename_mod_line(&target.join(&mod_line.mod_file), &mod_line.line);
+                    RecordedModLine {
+                        mod_file: relative_to(target, &mod_file),
+                        line,
+                    }
+                })
+                .collect();
+            write_renamed_record(
+                record,
+                &new_snake,
+                &moved_files,
+                mod_lines,
+                &old_record_path,
+                &new_record_path,
+            )?;
+        }
+        return finish_rename(options, &old_snake, &new_snake, moves.len());
+    }
+
+    // 旧名のmod行を取り除き、取り除けたmod.rsの更新だけを新しい名前でやり直す
+    let mut renamed_updates = Vec::new();
+    for mod_update in manifest.mod_updates() {
+        let mod_path =
+            resolve_output_path(target, &render_inline(&mod_update.mod_file, &old_context)?)?;
+        if !mod_path.exists() {
+            continue;
+        }
+        let content = fs::read_to_string(&mod_path)
+            .with_context(|| format!("Failed to read mod file: {:?}", mod_path))?;
+        let old_line = render_inline(&mod_update.line, &old_context)?;
+        if let Some(new_content) = remove_mod_line(&content, &old_line) {
+            fs::write(&mod_path, new_content)
+                .with_context(|| format!("Failed to write mod file: {:?}", mod_path))?;
+            renamed_updates.push(mod_update);
+        }
+    }
+    let updated = update_mod_files(target, &renamed_updates, &new_context, false)?;
+    if !updated.is_empty() {
+        status!(options.verbosity, "\nUpdated mod.rs files:");
+        for update in updated.iter().filter(|update| update.is_change()) {
+            status!(
+                options.verbosity,
+                "  {} ({})",
+                update.mod_file.display(),
+                update.line
+            );
+        }
+    }
+
+    // 生成記録も新しい名前に移す
+    if let Some(record) = record {
+        let mod_lines = updated
+            .iter()
+            .filter(|update| update.is_change())
+            .map(|update| RecordedModLine {
+                mod_file: relative_to(target, &update.mod_file),
+                line: update.line.clone(),
+            })
+            .collect();
+        write_renamed_record(
+            record,
+            &new_snake,
+            &moved_files,
+            mod_lines,
+            &old_record_path,
+            &new_record_path,
+        )?;
+    }
+
+    finish_rename(options, &old_snake, &new_snake, moves.len())
+}
+
+/// 改名後の生成記録を新しい名前で保存し、旧名の記録を削除する
+fn write_renamed_record(
+    record: GenerationRecord,
+    new_snake: &str,
+    moved_files: &[MovedFile],
+    mod_lines: Vec<RecordedModLine>,
+    old_record_path: &Path,
+    new_record_path: &Path,
+) -> Result<()> {
+    let files = record
+        .files
+        .into_iter()
+        .map(|file| {
+            let Some(moved) = moved_files.iter().find(|moved| moved.from == file.path) else {
+                return file;
+            };
+            // 生成時から編集されていなければ、移動後の内容を生成時の内容とみなす
+            RecordedFile {
+                path: moved.to.clone(),
+                sha256: if file.sha256 == moved.original_sha256 {
+                    moved.sha256.clone()
+                } else {
+                    file.sha256
+                },
+                layer: file.layer,
+            }
+        })
+        .collect();
+    let renamed = GenerationRecord {
+        name: new_snake.to_string(),
+        files,
+        mod_lines,
+        ..record
+    };
+    if let Some(parent) = new_record_path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+    }
+    fs::write(
+        new_record_path,
+        format!("{}\n", serde_json::to_string_pretty(&renamed)?),
+    )
+    .with_context(|| format!("Failed to write record: {:?}", new_record_path))?;
+    fs::remove_file(old_record_path)
+        .with_context(|| format!("Failed to remove record: {:?}", old_record_path))?;
+    Ok(())
+}
+
+/// 改名の完了を表示する
+fn finish_rename(
+    options: &RenameOptions,
+    old_snake: &str,
+    new_snake: &str,
+    count: usize,
+) -> Result<()> {
+    status!(options.verbosity);
+    println!(
+        "{}",
+        format!(
+            "Renamed feature '{}' to '{}' ({} files)",
+            old_snake, new_snake, count
+        )
+        .green()
+        .bold()
+    );
+    Ok(())
+}
+
+/// 機能ディレクトリに生成した機能の改名で、ディレクトリとモジュール名の対応を持つ
+///
+/// 旧名と新名の出力パスを要素ごとに対応させ、機能ディレクトリ内のmod.rsの移動先と、
+/// そこに書かれた`pub mod X;`の新しいモジュール名を求める。
+struct FeatureDirModules {
+    /// 機能ディレクトリ内の旧ディレクトリと新ディレクトリ（浅い順）
+    dirs: Vec<(PathBuf, PathBuf)>,
+    /// （旧ディレクトリ, 旧モジュール名）から新モジュール名への対応
+    modules: BTreeMap<(PathBuf, String), String>,
+}
+
+impl FeatureDirModules {
+    fn new<'p>(
+        old_dir: &Path,
+        new_dir: &Path,
+        paths: impl IntoIterator<Item = (&'p Path, &'p Path)>,
+    ) -> Self {
+        let mut dirs = vec![(old_dir.to_path_buf(), new_dir.to_path_buf())];
+        let mut modules = BTreeMap::new();
+        // 機能ディレクトリ自体は親ディレクトリのmod.rsに登録されている
+        if let (Some(parent), Some(old_name), Some(new_name)) =
+            (old_dir.parent(), old_dir.file_name(), new_dir.file_name())
+        {
+            modules.insert(
+                (
+                    parent.to_path_buf(),
+                    old_name.to_string_lossy().into_owned(),
+                ),
+                new_name.to_string_lossy().into_owned(),
+            );
+        }
+        for (old, new) in paths {
+            let (Ok(old_relative), Ok(new_relative)) =
+                (old.strip_prefix(old_dir), new.strip_prefix(new_dir))
+            else {
+                continue;
+            };
+            let old_parts: Vec<_> = old_relative.components().collect();
+            let new_parts: Vec<_> = new_relative.components().collect();
+            if old_parts.len() != new_parts.len() {
+                continue;
+            }
+            let (mut old_current, mut new_current) = (old_dir.to_path_buf(), new_dir.to_path_buf());
+            for (index, (old_part, new_part)) in old_parts.iter().zip(&new_parts).enumerate() {
+                let old_path = old_current.join(old_part);
+                let new_path = new_current.join(new_part);
+                let is_file = index + 1 == old_parts.len();
+                let name = |path: &Path| {
+                    let name = if is_file {
+                        path.file_stem()
+                    } else {
+                        path.file_name()
+                    };
+                    name.map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default()
+                };
+                modules
+                    .entry((old_current.clone(), name(&old_path)))
+                    .or_insert_with(|| name(&new_path));
+                if is_file {
+                    break;
+                }
+                if !dirs.iter().any(|(dir, _)| *dir == old_path) {
+                    dirs.push((old_path.clone(), new_path.clone()));
+                }
+                old_current = old_path;
+                new_current = new_path;
+            }
+        }
+        dirs.sort_by_key(|(dir, _)| dir.components().count());
+        Self { dirs, modules }
+    }
+
+    /// 機能ディレクトリ内のmod.rsの移動元と移動先
+    fn moved_mod_files(&self) -> impl Iterator<Item = (PathBuf, PathBuf)> + '_ {
+        self.dirs
+            .iter()
+            .filter(|(old, new)| old != new)
+            .map(|(old, new)| (old.join("mod.rs"), new.join("mod.rs")))
+    }
+
+    /// mod.rsのパスと`pub mod X;`の行を改名後のものに読み替える
+    fn rename_mod_line(&self, mod_file: &Path, line: &str) -> (PathBuf, String) {
+        let dir = mod_file.parent();
+        let renamed = dir
+            .and_then(|dir| self.dirs.iter().find(|(old, _)| old == dir))
+            .zip(mod_file.file_name())
+            .map_or_else(|| mod_file.to_path_buf(), |((_, new), name)| new.join(name));
+        (renamed, self.rename_line(dir, line))
+    }
+
+    /// `dir`のmod.rsの行を、改名されるモジュールなら新しい名前の行にする
+    fn rename_line(&self, dir: Option<&Path>, line: &str) -> String {
+        let renamed = dir
+            .zip(mod_decl_name(line))
+            .and_then(|(dir, name)| self.modules.get(&(dir.to_path_buf(), name.to_string())));
+        match renamed {
+            Some(new_name) => {
+                let indent = &line[..line.len() - line.trim_start().len()];
+                format!("{}pub mod {};", indent, new_name)
+            }
+            None => line.to_string(),
+        }
+    }
+
+    /// 機能ディレクトリ内のmod.rsを新しいディレクトリへ移し、親のmod.rsの登録を書き換える
+    ///
+    /// 書き込んだmod.rsのパスを返す。
+    fn apply(&self, target: &Path) -> Result<Vec<PathBuf>> {
+        let mut moved = Vec::new();
+        for (old_dir, new_dir) in &self.dirs {
+            let old_mod = old_dir.join("mod.rs");
+            if !old_mod.is_file() {
+                continue;
+            }
+            let content = fs::read_to_string(&old_mod)
+                .with_context(|| format!("Failed to read mod file: {:?}", old_mod))?;
+            let renamed = self.rename_content(old_dir, &content);
+            let new_mod = new_dir.join("mod.rs");
+            fs::create_dir_all(new_dir)
+                .with_context(|| format!("Failed to create directory: {:?}", new_dir))?;
+            fs::write(&new_mod, renamed)
+                .with_context(|| format!("Failed to write mod file: {:?}", new_mod))?;
+            if old_mod != new_mod {
+                fs::remove_file(&old_mod)
+                    .with_context(|| format!("Failed to remove mod file: {:?}", old_mod))?;
+            }
+            moved.push((old_mod, new_mod));
+        }
+        // 深い順に空になったディレクトリを消す
+        for (old_mod, new_mod) in moved.iter().rev() {
+            if old_mod != new_mod {
+                remove_empty_dirs(target, old_mod);
+            }
+        }
+        let mut written: Vec<PathBuf> = moved.into_iter().map(|(_, new_mod)| new_mod).collect();
+
+        // 機能ディレクトリ自体の登録（親ディレクトリのmod.rs）
+        if let Some((old_dir, _)) = self.dirs.first() {
+            if let Some(parent) = old_dir.parent() {
+                let parent_mod = parent.join("mod.rs");
+                if parent_mod.is_file() {
+                    let content = fs::read_to_string(&parent_mod)
+                        .with_context(|| format!("Failed to read mod file: {:?}", parent_mod))?;
+                    let renamed = self.rename_content(parent, &content);
+                    if renamed != content {
+                        fs::write(&parent_mod, renamed).with_context(|| {
+                            format!("Failed to write mod file: {:?}", parent_mod)
+                        })?;
+                        written.push(parent_mod);
+                    }
+                }
+            }
+        }
+        Ok(written)
+    }
+
+    /// `dir`のmod.rsの内容の、改名されるモジュールの行を書き換える
+    fn rename_content(&self, dir: &Path, content: &str) -> String {
+        let mut renamed: String = content
+            .lines()
+            .map(|line| self.rename_line(Some(dir), line))
+            .collect::<Vec<_>>()
+            .join("\n");
+        if content.ends_with('\n') {
+            renamed.push('\n');
+        }
+        renamed
+    }
+}
+
+/// `rename`で移動したファイル
+struct MovedFile {
+    /// 移動前の生成先からの相対パス
+    from: String,
+    /// 移動後の生成先からの相対パス
+    to: String,
+    /// 移動前の内容のSHA-256
+    original_sha256: String,
+    /// 移動後（置換後）の内容のSHA-256
+    sha256: String,
+}
+
+/// ファイルを移動した後、空になった親ディレクトリを生成先の手前まで削除する
+fn remove_empty_dirs(target: &Path, path: &Path) {
+    for dir in path.ancestors().skip(1) {
+        if dir == target || !dir.starts_with(target) || fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+}
+
+/// 差分表示のオプション
+pub struct DiffOptions {
+    /// 機能名（正規化前）
+    pub name: String,
+    /// 生成時の機能の説明
+    pub description: String,
+    /// 比較に使うアーキタイプ名
+    pub archetype: String,
+    /// 生成先ディレクトリ
+    pub target: PathBuf,
+    /// 生成時のテンプレート変数
+    pub vars: Vec<(String, serde_json::Value)>,
+    /// `@shared/`のテンプレートを解決する共有テンプレートのディレクトリ
+    pub shared_templates_dir: Option<PathBuf>,
+    /// `--feature-dir`で生成した機能として比べるか（生成記録があれば記録に従う）
+    pub feature_dir: bool,
+}
+
+/// 差分表示の集計
+#[derive(Debug, Default)]
+pub struct DiffSummary {
+    /// 内容が異なるファイルの数
+    pub differing: usize,
+    /// まだ存在しない（生成される）ファイル
+    pub missing: Vec<PathBuf>,
+    /// 記録にあるが現在のアーキタイプでは生成されないファイル
+    pub gone: Vec<PathBuf>,
+}
+
+/// 生成済みのファイルを再レンダリング結果と比較し、unified diffを表示する
+///
+/// 存在しないファイルは「would be created」として報告する。生成記録があれば
+/// 記録されたアーキタイプ・説明・変数で再レンダリングし、アーキタイプが
+/// 生成しなくなったファイルも報告する。
+pub fn diff_feature(archetypes_dirs: &[PathBuf], options: &DiffOptions) -> Result<DiffSummary> {
+    let snake_name = to_snake_case(&options.name);
+    let pascal_name = snake_name.to_pascal_case();
+    let target = &options.target;
+
+    let record_path = record_path(target, &snake_name);
+    let record = read_record(&record_path)?;
+    let (archetype, description, vars) = match &record {
+        Some(record) => {
+            println!("Using generation record: {}", record_path.display());
+            (
+                record.archetype.clone(),
+                record.description.clone(),
+                record.vars.clone().into_iter().collect(),
+            )
+        }
+        None => (
+            options.archetype.clone(),
+            options.description.clone(),
+            options.vars.clone(),
+        ),
+    };
+
+    let manifest = load_archetype_with_shared_templates(
+        archetypes_dirs,
+        &archetype,
+        options.shared_templates_dir.as_deref(),
+    )?;
+    let mut context = build_context(
+        &snake_name,
+        &pascal_name,
+        &description,
+        &manifest.vars_with_defaults(&vars),
+    )?;
+    insert_environment_vars(&mut context, &recorded_environment(record.as_ref(), target));
+    let feature_dir = record
+        .as_ref()
+        .map_or(options.feature_dir, |record| record.feature_dir);
+    let (FilePlan { planned, .. }, _) =
+        plan_generated_files(&manifest, &context, target, feature_dir)?;
+    let tera = load_templates(
+        &manifest.dir,
+        &manifest,
+        planned.iter().map(|output| output.spec),
+    )?;
+
+    let mut summary = DiffSummary::default();
+    let fmt = record.as_ref().is_some_and(|record| record.fmt);
+    for output in &planned {
+        let full_path = &output.path;
+        let mut rendered = render_file(&tera, output.spec, output.context(&context))?;
+        if fmt {
+            rendered = formatted_rust(full_path, &rendered).unwrap_or(rendered);
+        }
+        let rendered = normalize_line_endings(&strip_banner(&rendered), LineEnding::Lf);
+        if !full_path.exists() {
+            summary.missing.push(full_path.clone());
+            println!(
+                "{} {}",
+                "[NEW]".yellow(),
+                format!("{} (would be created)", full_path.display()).dimmed()
+            );
+            continue;
+        }
+
+        let current = fs::read_to_string(full_path)
+            .with_context(|| format!("Failed to read file: {:?}", full_path))?;
+        // 改行コードの違いとバナーの有無は差分として扱わない
+        let current = normalize_line_endings(&strip_banner(&current), LineEnding::Lf);
+        if let Some(diff) = unified_diff(&full_path.display().to_string(), &current, &rendered) {
+            summary.differing += 1;
+            print_colored_diff(&diff);
+        }
+    }
+
+    // 記録にあるが現在のアーキタイプでは生成されないファイル
+    for file in record.iter().flat_map(|record| &record.files) {
+        let path = target.join(&file.path);
+        if planned.iter().all(|output| output.path != path) {
+            println!(
+                "{} {}",
+                "[GONE]".red(),
+                format!("{} (no longer generated by the archetype)", path.display()).dimmed()
+            );
+            summary.gone.push(path);
+        }
+    }
+
+    println!(
+        "\n{}",
+        format!(
+            "{} of {} files differ, {} would be created for feature '{}'",
+            summary.differing,
+            planned.len(),
+            summary.missing.len(),
+            snake_name
+        )
+        .bold()
+    );
+    Ok(summary)
+}
+
+/// ディスク上の内容と再レンダリング結果のunified diffを返す（差分がなければ`None`）
+fn unified_diff(path: &str, current: &str, rendered: &str) -> Option<String> {
+    if current == rendered {
+        return None;
+    }
+    let diff = similar::TextDiff::from_lines(current, rendered);
+    Some(
+        diff.unified_diff()
+            .context_radius(3)
+            .header(
+                &format!("{} (on disk)", path),
+                &format!("{} (rendered)", path),
+            )
+            .to_string(),
+    )
+}
+
+/// unified diffを行の種類ごとに色付けして表示
+fn print_colored_diff(diff: &str) {
+    for line in diff.lines() {
+        if line.starts_with("---") || line.starts_with("+++") {
+            println!("{}", line.bold());
+        } else if line.starts_with("@@") {
+            println!("{}", line.cyan());
+        } else if line.starts_with('+') {
+            println!("{}", line.green());
+        } else if line.starts_with('-') {
+            println!("{}", line.red());
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+/// アーキタイプを使わずに1つのテンプレートをレンダリングする
+///
+/// `scaffold`と同じ組み込み変数・フィルタを使うため、アーキタイプの`files`に
+/// 加える前のテンプレートの試作に使える。
+pub fn render_template(
+    template: &str,
+    name: &str,
+    description: &str,
+    vars: &[(String, serde_json::Value)],
+) -> Result<String> {
+    let snake_name = to_snake_case(name);
+    validate_feature_name(&snake_name)?;
+    let pascal_name = snake_name.to_pascal_case();
+    let mut context = build_context(&snake_name, &pascal_name, description, vars)?;
+    insert_environment_vars(&mut context, &environment_vars(Path::new(".")));
+    let template = template.strip_prefix(BOM).unwrap_or(template);
+    new_tera().render_str(template, &context).map_err(|err| {
+        let message = match undefined_variable(&err) {
+            Some(var) => format!("Failed to render template (undefined variable `{}`)", var),
+            None => "Failed to render template".to_string(),
+        };
+        anyhow::Error::new(err).context(message)
+    })
+}
+
+/// 組み込み変数とユーザー定義変数からTeraコンテキストを作成
+fn build_context(
+    snake_name: &str,
+    pascal_name: &str,
+    description: &str,
+    vars: &[(String, serde_json::Value)],
+) -> Result<tera::Context> {
+    let mut context = tera::Context::new();
+    context.insert("name", snake_name);
+    context.insert("snake_name", snake_name);
+    context.insert("pascal_name", pascal_name);
+    // ファイル名やディレクトリ名向けの表記
+    context.insert("kebab_name", &snake_name.to_kebab_case());
+    context.insert("camel_name", &snake_name.to_lower_camel_case());
+    context.insert("shouty_name", &snake_name.to_shouty_snake_case());
+    context.insert("title_name", &snake_name.to_title_case());
+    context.insert("description", description);
+
+    for (key, value) in vars {
+        if RESERVED_VARS.contains(&key.as_str()) {
+            anyhow::bail!(
+                "Variable '{}' collides with a built-in variable (reserved: {})",
+                key,
+                RESERVED_VARS.join(", ")
+            );
+        }
+        context.insert(key.as_str(), value);
+    }
+
+    Ok(context)
+}
+
+/// 実行環境から得るテンプレート変数（[`ENVIRONMENT_VARS`]）
+///
+/// `date`（UTCの`YYYY-MM-DD`）と`year`は常に含める。`git_author_name`/`git_author_email`は
+/// 生成先のリポジトリの`user.name`/`user.email`から読み、gitがない・未設定の場合は含めない。
+/// 実行日で値が変わるため、生成時の値は生成記録に保存して再レンダリングで使い回す。
+fn environment_vars(target: &Path) -> BTreeMap<String, serde_json::Value> {
+    let days = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() / 86_400)
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days(days as i64);
+    let mut vars = BTreeMap::from([
+        (
+            "date".to_string(),
+            format!("{:04}-{:02}-{:02}", year, month, day).into(),
+        ),
+        ("year".to_string(), year.into()),
+    ]);
+    let author = git_author(target);
+    for (key, config) in [
+        ("git_author_name", "user.name"),
+        ("git_author_email", "user.email"),
+    ] {
+        if let Some(value) = author.get(config) {
+            vars.insert(key.to_string(), value.clone().into());
+        }
+    }
+    vars
+}
+
+/// 生成先のリポジトリの`user.name`/`user.email`（gitがない、または未設定なら空）
+///
+/// 生成先がまだなければ、存在する最も近い親ディレクトリで`git`を実行する。
+fn git_author(target: &Path) -> BTreeMap<String, String> {
+    let dir = target
+        .ancestors()
+        .find(|dir| dir.is_dir())
+        .unwrap_or(Path::new("."));
+    let Ok(output) = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["config", "--get-regexp", r"^user\.(name|email)$"])
+        .output()
+    else {
+        return BTreeMap::new();
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .filter(|(_, value)| !value.trim().is_empty())
+        .map(|(key, value)| (key.to_string(), value.trim().to_string()))
+        .collect()
+}
+
+/// 実行環境の変数をコンテキストに加える（`--var`などで定義済みの変数は上書きしない）
+fn insert_environment_vars(
+    context: &mut tera::Context,
+    environment: &BTreeMap<String, serde_json::Value>,
+) {
+    for (key, value) in environment {
+        if !context.contains_key(key) {
+            context.insert(key.as_str(), value);
+        }
+    }
+}
+
+/// 再レンダリングに使う実行環境の変数（生成記録に保存された値、なければ現在の値）
+fn recorded_environment(
+    record: Option<&GenerationRecord>,
+    target: &Path,
+) -> BTreeMap<String, serde_json::Value> {
+    match record {
+        Some(record) if !record.environment.is_empty() => record.environment.clone(),
+        _ => environment_vars(target),
+    }
+}
+
+/// 1970-01-01からの日数を`(年, 月, 日)`に変換する（グレゴリオ暦）
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // 3月始まりの月（0 = 3月）
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// 出力パスを解決し、生成条件で振り分けたファイル一覧
+struct FilePlan<'a> {
+    /// 生成するファイル
+    planned: Vec<PlannedOutput<'a>>,
+    /// `when`が偽のためスキップするファイル
+    skipped: Vec<PlannedOutput<'a>>,
+}
+
+/// 出力パスを解決した1ファイル
+struct PlannedOutput<'a> {
+    /// ファイルの仕様
+    spec: &'a FileSpec,
+    /// 出力先のパス
+    path: PathBuf,
+    /// レイヤー名（仕様の`layer`をコンテキストでレンダリングしたもの）
+    layer: String,
+    /// `for_each`の要素を`item`として加えたコンテキスト
+    item_context: Option<tera::Context>,
+}
+
+impl PlannedOutput<'_> {
+    /// このファイルのレンダリングに使うコンテキスト
+    fn context<'c>(&'c self, base: &'c tera::Context) -> &'c tera::Context {
+        self.item_context.as_ref().unwrap_or(base)
+    }
+
+    /// `tests/`の統合テストなどの`test`レイヤーか
+    fn is_test(&self) -> bool {
+        self.layer == TEST_LAYER
+    }
+
+    /// 生成したファイルをmod.rsに登録する対象にするか
+    fn registers_module(&self) -> bool {
+        !self.spec.skip_mod
+            && !self.is_test()
+            && self.path.extension().is_some_and(|ext| ext == "rs")
+    }
+}
+
+/// `--only-layer`/`--skip-layer`によるレイヤーの絞り込み
+///
+/// どちらのパターンも`adapter*`のようなglobで指定でき、`skip`は`only`から除く。
+#[derive(Default)]
+struct LayerFilter<'a> {
+    /// 生成するレイヤーのパターン（空なら全て）
+    only: &'a [String],
+    /// 生成しないレイヤーのパターン
+    skip: &'a [String],
+}
+
+impl LayerFilter<'_> {
+    /// 絞り込みを指定していないか
+    fn is_empty(&self) -> bool {
+        self.only.is_empty() && self.skip.is_empty()
+    }
+
+    /// レイヤーを生成の対象に含めるか
+    fn includes(&self, layer: &str) -> bool {
+        let matches = |patterns: &[String]| {
+            patterns
+                .iter()
+                .any(|pattern| layer_pattern_matches(pattern, layer))
+        };
+        (self.only.is_empty() || matches(self.only)) && !matches(self.skip)
+    }
+
+    /// パターンを検証し、絞り込んだ結果ファイルが残ることを確認する
+    ///
+    /// どのレイヤーにも一致しないパターンはエラーとし、利用可能なレイヤーを示す。
+    /// テンプレートのレイヤー名は`context`でレンダリングした名前と比べる
+    /// （`for_each`の`item`を参照するなどレンダリングできなければ元の名前）。
+    fn check(&self, manifest: &Manifest, context: &tera::Context) -> Result<()> {
+        let mut available: Vec<String> = manifest
+            .files
+            .iter()
+            .map(|file_spec| {
+                render_inline(&file_spec.layer, context).unwrap_or_else(|_| file_spec.layer.clone())
+            })
+            .collect();
+        available.sort();
+        available.dedup();
+
+        let mut unknown = Vec::new();
+        for pattern in self.only.iter().chain(self.skip) {
+            glob::Pattern::new(pattern)
+                .with_context(|| format!("Invalid layer pattern: {}", pattern))?;
+            if !available
+                .iter()
+                .any(|layer| layer_pattern_matches(pattern, layer))
+            {
+                unknown.push(pattern.as_str());
+            }
+        }
+        if !unknown.is_empty() {
+            anyhow::bail!(
+                "Unknown layer(s) for archetype '{}': {}. Available: {}",
+                manifest.name,
+                unknown.join(", "),
+                available.join(", ")
+            );
+        }
+
+        if !available.iter().any(|layer| self.includes(layer)) {
+            anyhow::bail!(
+                "--only-layer/--skip-layer select no files of archetype '{}'. Available: {}",
+                manifest.name,
+                available.join(", ")
+            );
+        }
+        Ok(())
+    }
+}
+
+/// レイヤー名がパターン（globまたは完全一致）に一致するか
+fn layer_pattern_matches(pattern: &str, layer: &str) -> bool {
+    match glob::Pattern::new(pattern) {
+        Ok(glob) => glob.matches(layer),
+        Err(_) => pattern == layer,
+    }
+}
+
+/// レイヤーの絞り込み時に適用するmod.rsの登録を選ぶ
+///
+/// `layer`を宣言した登録はそのレイヤーが対象のときのみ適用する。
+/// 宣言がない場合は、生成するファイルがmod.rsと同じディレクトリ配下にあるときのみ適用する。
+fn select_mod_updates(
+    mod_updates: Vec<ModUpdate>,
+    filter: &LayerFilter,
+    planned_paths: &[PathBuf],
+    target: &Path,
+    context: &tera::Context,
+) -> Result<Vec<ModUpdate>> {
+    let mut selected = Vec::new();
+    for mod_update in mod_updates {
+        let applies = match &mod_update.layer {
+            Some(layer) => filter.includes(&render_inline(layer, context)?),
+            None => {
+                let mod_path =
+                    resolve_output_path(target, &render_inline(&mod_update.mod_file, context)?)?;
+                let mod_dir = mod_path.parent().unwrap_or(target);
+                planned_paths.iter().any(|path| path.starts_with(mod_dir))
+            }
+        };
+        if applies {
+            selected.push(mod_update);
+        }
+    }
+    Ok(selected)
+}
+
+/// `skip_mod`のファイルのモジュールだけを宣言するmod.rsの登録を除く
+///
+/// 同じモジュールに登録対象のファイルもある場合（ディレクトリのモジュールなど）は残す。
+fn without_skipped_modules(
+    mod_updates: Vec<ModUpdate>,
+    planned: &[PlannedOutput],
+    target: &Path,
+    context: &tera::Context,
+) -> Result<Vec<ModUpdate>> {
+    let skipped_paths: Vec<PathBuf> = planned
+        .iter()
+        .filter(|output| output.spec.skip_mod)
+        .map(|output| output.path.clone())
+        .collect();
+    if skipped_paths.is_empty() {
+        return Ok(mod_updates);
+    }
+    let module_paths = module_paths(planned);
+    let mut kept = Vec::new();
+    for mod_update in mod_updates {
+        let mod_path = resolve_output_path(target, &render_inline(&mod_update.mod_file, context)?)?;
+        let line = render_inline(&mod_update.line, context)?;
+        if !declares_planned_module(&mod_path, &line, &skipped_paths)
+            || declares_planned_module(&mod_path, &line, &module_paths)
+        {
+            kept.push(mod_update);
+        }
+    }
+    Ok(kept)
+}
+
+/// マニフェストの`next_steps`をコンテキストでレンダリングする
+fn render_next_steps(manifest: &Manifest, context: &tera::Context) -> Result<Vec<String>> {
+    manifest
+        .next_steps
+        .iter()
+        .map(|step| {
+            render_inline(step, context)
+                .with_context(|| format!("Failed to render next step: {}", step))
+        })
+        .collect()
+}
+
+/// フックのコマンドをコンテキストでレンダリングする
+fn render_hooks(commands: &[String], context: &tera::Context) -> Result<Vec<String>> {
+    commands
+        .iter()
+        .map(|command| {
+            render_inline(command, context)
+                .with_context(|| format!("Failed to render hook: {}", command))
+        })
+        .collect()
+}
+
+/// フックのコマンドを生成先でシェル経由で実行し、出力を返す
+///
+/// 標準出力と標準エラー出力は届いた順に1行ずつ集め、`stream`なら同時に標準エラー出力へ流す
+/// （時間のかかるコード生成などでも進み具合が分かる）。
+/// 終了コードが0以外の場合は出力を含めたエラーにする。
+fn run_hook(command: &str, target: &Path, stream: bool) -> Result<String> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = std::process::Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = std::process::Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let mut child = shell
+        .arg(command)
+        .current_dir(target)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run hook: {}", command))?;
+
+    // 両方のパイプを別スレッドで読み、片方が詰まって子プロセスが止まらないようにする
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let readers: Vec<_> = [
+        child
+            .stdout
+            .take()
+            .map(|pipe| Box::new(pipe) as Box<dyn Read + Send>),
+        child
+            .stderr
+            .take()
+            .map(|pipe| Box::new(pipe) as Box<dyn Read + Send>),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|pipe| {
+        let sender = sender.clone();
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(pipe);
+            let mut line = Vec::new();
+            while reader
+                .read_until(b'\n', &mut line)
+                .is_ok_and(|read| read > 0)
+            {
+                let _ = sender.send(String::from_utf8_lossy(&line).into_owned());
+                line.clear();
+            }
+        })
+    })
+    .collect();
+    drop(sender);
+
+    if stream {
+        eprintln!("  $ {}", command);
+    }
+    let mut output = String::new();
+    for line in receiver {
+        if stream {
+            eprint!("    {}", line);
+            if !line.ends_with('\n') {
+                eprintln!();
+            }
+        }
+        output.push_str(&line);
+    }
+    for reader in readers {
+        let _ = reader.join();
+    }
+    let status = child
+        .wait()
+        .with_context(|| format!("Failed to run hook: {}", command))?;
+
+    if !status.success() {
+        anyhow::bail!(
+            "Hook failed ({}): {}\n{}",
+            status,
+            command,
+            output.trim_end()
+        );
+    }
+    Ok(output)
+}
+
+/// マニフェストの各ファイルについて出力パスを解決し、生成条件を評価する
+fn plan_files<'a>(
+    manifest: &'a Manifest,
+    context: &tera::Context,
+    target: &Path,
+    layers: &LayerFilter,
+) -> Result<FilePlan<'a>> {
+    if !layers.is_empty() {
+        layers.check(manifest, context)?;
+    }
+
+    let mut planned = Vec::new();
+    let mut skipped = Vec::new();
+
+    for file_spec in order_files(&manifest.files)? {
+        let item_contexts = match &file_spec.for_each {
+            Some(var) => for_each_contexts(file_spec, var, context)?
+                .into_iter()
+                .map(Some)
+                .collect(),
+            None => vec![None],
+        };
+
+        for item_context in item_contexts {
+            let file_context = item_context.as_ref().unwrap_or(context);
+            let layer = render_inline(&file_spec.layer, file_context)
+                .with_context(|| format!("Failed to render layer: {}", file_spec.layer))?;
+            // --only-layer/--skip-layerで指定されたレイヤーに絞り込む
+            if !layers.includes(&layer) {
+                continue;
+            }
+            let output_path = render_inline(&file_spec.output, file_context)
+                .with_context(|| format!("Failed to render output path: {}", file_spec.output))?;
+            let output_path = manifest.in_layer_crate(&layer, &output_path);
+            let path = resolve_output_path(target, &output_path)?;
+
+            let enabled = match &file_spec.when {
+                Some(condition) => {
+                    evaluate_condition(condition, file_context).with_context(|| {
+                        format!(
+                            "Failed to evaluate `when` for template: {}",
+                            file_spec.template
+                        )
+                    })?
+                }
+                None => true,
+            };
+
+            let output = PlannedOutput {
+                spec: file_spec,
+                path,
+                layer,
+                item_context,
+            };
+            if enabled {
+                // 同じパスに2回書き込むと先に生成した内容が失われる
+                if let Some(existing) = planned
+                    .iter()
+                    .find(|planned: &&PlannedOutput| planned.path == output.path)
+                {
+                    anyhow::bail!(
+                        "Duplicate output path {}: generated by both {} and {}",
+                        relative_to(target, &output.path),
+                        existing.spec.template,
+                        output.spec.template
+                    );
+                }
+                planned.push(output);
+            } else {
+                skipped.push(output);
+            }
+        }
+    }
+
+    Ok(FilePlan { planned, skipped })
+}
+
+/// `--feature-dir`の既定の機能ディレクトリ
+const DEFAULT_FEATURE_DIR: &str = "src/features/{{name}}";
+
+/// 生成するファイルを機能ディレクトリの下へ移し、そのディレクトリを返す
+///
+/// 各ファイルは`src/`からの相対パス（`src/`の外なら生成先からの相対パス）を保って移す
+/// （`src/domain/foo.rs`は`<機能ディレクトリ>/domain/foo.rs`）。移した結果が重なる場合は
+/// エラーにする。`test`レイヤーのファイルは`tests/`などの出力先のまま残す。
+fn relocate_to_feature_dir(
+    manifest: &Manifest,
+    context: &tera::Context,
+    target: &Path,
+    plan: &mut FilePlan,
+) -> Result<PathBuf> {
+    let template = manifest
+        .feature_dir
+        .as_deref()
+        .unwrap_or(DEFAULT_FEATURE_DIR);
+    let rendered = render_inline(template, context)
+        .with_context(|| format!("Failed to render feature_dir: {}", template))?;
+    let dir = resolve_output_path(target, &rendered)?;
+
+    for output in plan
+        .planned
+        .iter_mut()
+        .chain(plan.skipped.iter_mut())
+        .filter(|output| !output.is_test())
+    {
+        let relative = output.path.strip_prefix(target).unwrap_or(&output.path);
+        output.path = dir.join(relative.strip_prefix("src").unwrap_or(relative));
+    }
+
+    for (index, output) in plan.planned.iter().enumerate() {
+        if let Some(existing) = plan.planned[..index]
+            .iter()
+            .find(|planned| planned.path == output.path)
+        {
+            anyhow::bail!(
+                "Duplicate output path {} in feature directory: generated by both {} and {}",
+                relative_to(target, &output.path),
+                existing.spec.template,
+                output.spec.template
+            );
+        }
+    }
+
+    Ok(dir)
+}
+
+/// 生成済みの機能の出力パスを解決する（`--feature-dir`で生成した機能は機能ディレクトリへ移す）
+fn plan_generated_files<'a>(
+    manifest: &'a Manifest,
+    context: &tera::Context,
+    target: &Path,
+    feature_dir: bool,
+) -> Result<(FilePlan<'a>, Option<PathBuf>)> {
+    let mut plan = plan_files(manifest, context, target, &LayerFilter::default())?;
+    let dir = if feature_dir {
+        Some(relocate_to_feature_dir(
+            manifest, context, target, &mut plan,
+        )?)
+    } else {
+        None
+    };
+    Ok((plan, dir))
+}
+
+/// mod.rsに登録する対象の出力のパス（`skip_mod`・`.rs`以外・`test`レイヤーを除く）
+fn module_paths(planned: &[PlannedOutput]) -> Vec<PathBuf> {
+    planned
+        .iter()
+        .filter(|output| output.registers_module())
+        .map(|output| output.path.clone())
+        .collect()
+}
+
+/// 機能ディレクトリのmod.rsに生成したサブモジュールを登録する内容を作る
+///
+/// 直下のファイルはそのモジュールを、サブディレクトリのファイルは最初のディレクトリを
+/// 登録する（その先の途中のmod.rsは[`nested_mod_updates`]が作る）。
+/// 親ディレクトリにmod.rsがあれば、機能ディレクトリ自体もそこに登録する。
+fn feature_mod_updates(target: &Path, dir: &Path, planned_paths: &[PathBuf]) -> Vec<ModUpdate> {
+    let mod_file = relative_to(target, &dir.join("mod.rs"));
+    let mut updates: Vec<ModUpdate> = Vec::new();
+    for path in planned_paths {
+        if path.extension().is_none_or(|ext| ext != "rs") {
+            continue;
+        }
+        let Ok(relative) = path.strip_prefix(dir) else {
+            continue;
+        };
+        let mut components = relative.components();
+        let first = match (components.next(), components.next()) {
+            (Some(_), None) => path.file_stem(),
+            (Some(component), Some(_)) => Some(component.as_os_str()),
+            _ => None,
+        };
+        let Some(module) = first.and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if module == "mod" {
+            continue;
+        }
+        let line = format!("pub mod {};", module);
+        if !updates.iter().any(|update| update.line == line) {
+            updates.push(ModUpdate {
+                mod_file: mod_file.clone(),
+                line,
+                layer: None,
+            });
+        }
+    }
+
+    if let (Some(parent), Some(dir_name)) = (dir.parent(), dir.file_name()) {
+        let parent_mod = parent.join("mod.rs");
+        if parent_mod.exists() {
+            updates.push(ModUpdate {
+                mod_file: relative_to(target, &parent_mod),
+                line: format!("pub mod {};", dir_name.to_string_lossy()),
+                layer: None,
+            });
+        }
+    }
+    updates
+}
+
+/// ディレクトリのモジュールを宣言するファイル（`mod.rs`・`lib.rs`・`main.rs`・隣の`<dir>.rs`）
+///
+/// `known_modules`は、まだ書き込まれていないが作成される予定のファイル。
+fn module_file(dir: &Path, known_modules: &[PathBuf]) -> Option<PathBuf> {
+    let exists = |path: &PathBuf| path.is_file() || known_modules.contains(path);
+    ["mod.rs", "lib.rs", "main.rs"]
+        .iter()
+        .map(|file_name| dir.join(file_name))
+        .chain(
+            dir.file_name()
+                .map(|name| dir.with_file_name(format!("{}.rs", name.to_string_lossy()))),
+        )
+        .find(exists)
+}
+
+/// 生成するファイルから、最も近い既存のモジュールまでの途中のディレクトリに必要なmod.rsの登録
+///
+/// `src/domain/foo/bar.rs`で`src/domain/mod.rs`だけがある場合、`src/domain/foo/mod.rs`に
+/// `pub mod bar;`、`src/domain/mod.rs`に`pub mod foo;`を登録する。生成先の中に既存の
+/// モジュールが見つからないファイルや、既にモジュールのあるディレクトリに置くファイル
+/// （その登録は`mod_updates`が行う）は対象にしない。個別にビルドされる`src/bin/`・
+/// `tests/`・`examples/`・`benches/`の下も対象にしない。
+fn nested_mod_updates(
+    target: &Path,
+    planned_paths: &[PathBuf],
+    known_modules: &[PathBuf],
+) -> Vec<ModUpdate> {
+    const SEPARATE_TARGET_ROOTS: [&str; 4] = ["src/bin", "tests", "examples", "benches"];
+    let mut updates: Vec<ModUpdate> = Vec::new();
+    for path in planned_paths {
+        if path.extension().is_none_or(|ext| ext != "rs") {
+            continue;
+        }
+        let relative = path.strip_prefix(target).unwrap_or(path);
+        if SEPARATE_TARGET_ROOTS
+            .iter()
+            .any(|root| relative.starts_with(root))
+        {
+            continue;
+        }
+        let (Some(mut dir), Some(stem)) = (path.parent(), path.file_stem()) else {
+            continue;
+        };
+        let mut segment = stem.to_string_lossy().into_owned();
+        match segment.as_str() {
+            "lib" | "main" => continue,
+            "mod" => {
+                let (Some(parent), Some(name)) = (dir.parent(), dir.file_name()) else {
+                    continue;
+                };
+                segment = name.to_string_lossy().into_owned();
+                dir = parent;
+            }
+            _ => {}
+        }
+
+        let mut chain = Vec::new();
+        loop {
+            if dir == target || !dir.starts_with(target) {
+                chain.clear();
+                break;
+            }
+            if let Some(module) = module_file(dir, known_modules) {
+                if !chain.is_empty() {
+                    chain.push((module, segment));
+                }
+                break;
+            }
+            chain.push((dir.join("mod.rs"), segment));
+            let (Some(parent), Some(name)) = (dir.parent(), dir.file_name()) else {
+                chain.clear();
+                break;
+            };
+            segment = name.to_string_lossy().into_owned();
+            dir = parent;
+        }
+
+        for (mod_file, segment) in chain {
+            let update = ModUpdate {
+                mod_file: relative_to(target, &mod_file),
+                line: format!("pub mod {};", segment),
+                layer: None,
+            };
+            if !updates.iter().any(|existing| {
+                existing.mod_file == update.mod_file && existing.line == update.line
+            }) {
+                updates.push(update);
+            }
+        }
+    }
+    updates
+}
+
+/// `for_each`の配列の要素ごとに、`item`を加えたコンテキストを作る
+fn for_each_contexts(
+    file_spec: &FileSpec,
+    var: &str,
+    context: &tera::Context,
+) -> Result<Vec<tera::Context>> {
+    let mut segments = var.split('.');
+    let value = segments
+        .next()
+        .and_then(|first| context.get(first))
+        .and_then(|value| segments.try_fold(value, |value, key| value.get(key)));
+    let Some(value) = value else {
+        anyhow::bail!(
+            "`for_each` variable '{}' is not defined (template: {})",
+            var,
+            file_spec.template
+        );
+    };
+    let Some(items) = value.as_array() else {
+        anyhow::bail!(
+            "`for_each` variable '{}' must be an array (template: {})",
+            var,
+            file_spec.template
+        );
+    };
+
+    Ok(items
+        .iter()
+        .map(|item| {
+            let mut item_context = context.clone();
+            item_context.insert("item", item);
+            item_context
+        })
+        .collect())
+}
+
+/// レンダリング済みの出力パスを生成先に結合し、生成先の外に出ないことを確認する
+///
+/// 絶対パスや生成先より上を指す`..`は字句的に拒否する。生成先が既に存在する場合は、
+/// 既存の祖先ディレクトリを正規化してシンボリックリンク経由の脱出も拒否する。
+fn resolve_output_path(target: &Path, output_path: &str) -> Result<PathBuf> {
+    let escapes = || {
+        anyhow::anyhow!(
+            "Output path escapes the target directory {:?}: {}",
+            target,
+            output_path
+        )
+    };
+
+    let mut depth = 0usize;
+    for component in Path::new(output_path).components() {
+        match component {
+            std::path::Component::Normal(_) => depth += 1,
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => depth = depth.checked_sub(1).ok_or_else(escapes)?,
+            std::path::Component::RootDir | std::path::Component::Prefix(_) => {
+                return Err(escapes())
+            }
+        }
+    }
+
+    let full_path = target.join(output_path);
+    if let Ok(canonical_target) = target.canonicalize() {
+        let existing = full_path
+            .ancestors()
+            .find(|path| path.exists())
+            .and_then(|path| path.canonicalize().ok());
+        if existing.is_some_and(|path| !path.starts_with(&canonical_target)) {
+            return Err(escapes());
+        }
+    }
+    Ok(full_path)
+}
+
+/// `depends_on`に従ってファイル仕様をトポロジカルソートする
+///
+/// 依存関係のない仕様同士はマニフェストでの宣言順を保つ。未知の`output`への依存や
+/// 循環依存はエラーにする。
+fn order_files(files: &[FileSpec]) -> Result<Vec<&FileSpec>> {
+    for file_spec in files {
+        for dependency in &file_spec.depends_on {
+            if !files.iter().any(|f| &f.output == dependency) {
+                anyhow::bail!(
+                    "'{}' depends on unknown output '{}'",
+                    file_spec.output,
+                    dependency
+                );
+            }
+        }
+    }
+
+    let mut ordered: Vec<&FileSpec> = Vec::with_capacity(files.len());
+    let mut remaining: Vec<&FileSpec> = files.iter().collect();
+    while !remaining.is_empty() {
+        let ready = remaining.iter().position(|file_spec| {
+            file_spec
+                .depends_on
+                .iter()
+                .all(|dependency| ordered.iter().any(|f| &f.output == dependency))
+        });
+        match ready {
+            Some(index) => ordered.push(remaining.remove(index)),
+            None => {
+                let cycle: Vec<&str> = remaining.iter().map(|f| f.output.as_str()).collect();
+                anyhow::bail!("Circular depends_on among: {}", cycle.join(", "));
+            }
+        }
+    }
+
+    Ok(ordered)
+}
+
+/// 各ファイルの出力先がレイヤーに宣言されたルート配下にあることを確認する
+///
+/// `layer_roots`に宣言のないレイヤーは検査しない。`layer_crate`を宣言したレイヤーの
+/// ルートはクレートのディレクトリからの相対とする。
+fn check_layer_layout(manifest: &Manifest, planned: &[PlannedOutput], target: &Path) -> Result<()> {
+    let mut violations = Vec::new();
+
+    for PlannedOutput {
+        path: full_path,
+        layer,
+        ..
+    } in planned
+    {
+        let Some(root) = manifest.layer_roots.get(layer) else {
+            continue;
+        };
+        let base = match manifest.layer_crate.get(layer) {
+            Some(dir) => target.join(dir),
+            None => target.to_path_buf(),
+        };
+        let output = full_path.strip_prefix(&base).unwrap_or(full_path);
+        if !output.starts_with(root) {
+            violations.push(format!(
+                "  [{}] {} is outside {}",
+                layer,
+                output.display(),
+                root
+            ));
+        }
+    }
+
+    if !violations.is_empty() {
+        anyhow::bail!(
+            "Architecture violation: files do not match the declared layer layout:\n{}",
+            violations.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// 進捗バーを表示する生成ファイル数の下限
+const PROGRESS_THRESHOLD: usize = 20;
+
+/// 生成ループの進捗バー（`rendered N/M files`）
+///
+/// 標準エラー出力が端末で、ファイル数が[`PROGRESS_THRESHOLD`]を超える場合のみ表示し、
+/// それ以外は何も描画しないバーを返す。
+fn progress_bar(enabled: bool, len: usize) -> ProgressBar {
+    if !enabled || len <= PROGRESS_THRESHOLD || !std::io::stderr().is_terminal() {
+        return ProgressBar::hidden();
+    }
+    let bar = ProgressBar::new(len as u64);
+    if let Ok(style) = ProgressStyle::with_template("{bar:30} rendered {pos}/{len} files") {
+        bar.set_style(style);
+    }
+    bar
+}
+
+/// UTF-8のBOM（エディタが先頭に付けることがある）
+const BOM: char = '\u{FEFF}';
+
+/// テンプレートを読み込む
+///
+/// 先頭のBOMは取り除く（生成した`.rs`の先頭に残るとコンパイルできない）。
+fn read_template(path: &Path) -> std::io::Result<String> {
+    let content = fs::read_to_string(path)?;
+    Ok(match content.strip_prefix(BOM) {
+        Some(stripped) => stripped.to_string(),
+        None => content,
+    })
+}
+
+/// アーキタイプのテンプレートを1つのTeraインスタンスに読み込む
+///
+/// 生成するファイル（`specs`）のテンプレートに加え、アーキタイプ配下の`*.tera`を
+/// パーシャルとして登録する（出力はしない）。`when`などで生成しないファイルの
+/// テンプレートは読まないので、存在しなくてもエラーにならない。これにより`{% include %}`や
+/// マクロをファイル間で共有できる。テンプレート名はマニフェストの`template`の値、
+/// パーシャルはアーキタイプディレクトリからの相対パス（`/`区切り）になる。
+/// 継承元のパーシャルも登録し、同名のものは子アーキタイプ側が優先される。
+fn load_templates<'a>(
+    archetype_dir: &Path,
+    manifest: &Manifest,
+    specs: impl IntoIterator<Item = &'a FileSpec>,
+) -> Result<Tera> {
+    let mut sources: BTreeMap<String, PathBuf> = BTreeMap::new();
+    for dir in manifest
+        .parent_dirs
+        .iter()
+        .map(PathBuf::as_path)
+        .chain([archetype_dir])
+    {
+        for name in find_partials(dir)? {
+            let path = dir.join(&name);
+            sources.insert(name, path);
+        }
+    }
+    for file_spec in specs {
+        sources.insert(
+            file_spec.template_name(),
+            file_spec.template_path(archetype_dir)?,
+        );
+    }
+
+    let mut templates = Vec::with_capacity(sources.len());
+    for (name, template_path) in sources {
+        let content = read_template(&template_path)
+            .with_context(|| format!("Failed to read template: {:?}", template_path))?;
+        templates.push((name, content));
+    }
+
+    let mut tera = new_tera();
+    tera.add_raw_templates(templates)
+        .context("Failed to parse archetype templates")?;
+    Ok(tera)
+}
+
+/// アーキタイプ配下の`*.tera`ファイルを再帰的に探し、相対パスを返す
+///
+/// シンボリックリンクのディレクトリもたどるが、同じディレクトリは一度しか読まない
+/// （親を指すリンクで無限に再帰しない）。
+fn find_partials(archetype_dir: &Path) -> Result<Vec<String>> {
+    let mut partials = Vec::new();
+    let mut pending = vec![archetype_dir.to_path_buf()];
+    let mut visited = BTreeSet::new();
+
+    while let Some(dir) = pending.pop() {
+        if !visited.insert(fs::canonicalize(&dir).unwrap_or_else(|_| dir.clone())) {
+            continue;
+        }
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries {
+            let entry =
+                entry.with_context(|| format!("Failed to read directory entry in {:?}", dir))?;
+            let path = entry.path();
+            if entry_is_dir(&entry) {
+                pending.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "tera") {
+                if let Ok(relative) = path.strip_prefix(archetype_dir) {
+                    let name: Vec<_> = relative
+                        .components()
+                        .map(|c| c.as_os_str().to_string_lossy())
+                        .collect();
+                    partials.push(name.join("/"));
+                }
+            }
+        }
+    }
+
+    Ok(partials)
+}
+
+/// カスタムフィルタを登録したTeraインスタンスを作成
+fn new_tera() -> Tera {
+    let mut tera = Tera::default();
+    // 生成対象は主にコードなのでHTMLエスケープは行わない（必要な仕様は`render_file`で有効にする）
+    tera.autoescape_on(Vec::new());
+    tera.register_filter("pluralize", string_filter(pluralize));
+    tera.register_filter("singularize", string_filter(singularize));
+    tera.register_filter("table_name", string_filter(table_name));
+    tera
+}
+
+/// 文字列→文字列の関数をTeraフィルタに変換
+fn string_filter(
+    f: fn(&str) -> String,
+) -> impl Fn(&tera::Value, &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+    move |value, _args| {
+        let text = value
+            .as_str()
+            .ok_or_else(|| tera::Error::msg("filter expects a string value"))?;
+        Ok(tera::Value::String(f(text)))
+    }
+}
+
+/// 読み込み済みのテンプレートをコンテキストでレンダリングする
+///
+/// 未定義の変数は空文字にせずエラーとし、変数名とテンプレート名を報告する。
+/// HTMLエスケープを行う仕様は、全テンプレートでエスケープを有効にした複製で描画する。
+fn render_file(tera: &Tera, file_spec: &FileSpec, context: &tera::Context) -> Result<String> {
+    let rendered = if file_spec.autoescape() {
+        let mut escaping = tera.clone();
+        // 空の接尾辞は全てのテンプレート名に一致する
+        escaping.autoescape_on(vec![""]);
+        escaping.render(&file_spec.template_name(), context)
+    } else {
+        tera.render(&file_spec.template_name(), context)
+    };
+    rendered.map_err(|err| {
+        let message = match undefined_variable(&err) {
+            Some(var) => format!(
+                "Failed to render template: {} (undefined variable `{}`)",
+                file_spec.template, var
+            ),
+            None => format!("Failed to render template: {}", file_spec.template),
+        };
+        anyhow::Error::new(err).context(message)
+    })
+}
+
+/// Teraのエラーチェーンから未定義変数の名前を取り出す
+fn undefined_variable(err: &tera::Error) -> Option<String> {
+    let mut source: Option<&dyn std::error::Error> = Some(err);
+    while let Some(current) = source {
+        let message = current.to_string();
+        if let Some(rest) = message.strip_prefix("Variable `") {
+            if let Some((var, tail)) = rest.split_once('`') {
+                if tail.starts_with(" not found in context") {
+                    return Some(var.to_string());
+                }
+            }
+        }
+        source = current.source();
+    }
+    None
+}
+
+/// テンプレートが参照するトップレベルの変数を静的に集める（`entity.fields`なら`entity`）
+///
+/// `for`のループ変数・`set`で定義した変数・マクロの引数は含めない。`is defined`で
+/// 検査している変数と`default`フィルタを付けた変数は、未定義でもよいものとして除く。
+fn referenced_variables(source: &str) -> tera::Result<BTreeSet<String>> {
+    let template = tera::Template::new("check", None, source)?;
+    let mut scan = VariableScan::default();
+    scan.nodes(&template.ast);
+    Ok(scan.found.difference(&scan.optional).cloned().collect())
+}
+
+/// テンプレートのASTをたどって参照される変数を集める
+#[derive(Default)]
+struct VariableScan {
+    /// 現在のスコープで定義されている変数
+    locals: Vec<String>,
+    /// 参照されたトップレベルの変数
+    found: BTreeSet<String>,
+    /// 未定義でもよい変数
+    optional: BTreeSet<String>,
+}
+
+impl VariableScan {
+    fn nodes(&mut self, nodes: &[tera::ast::Node]) {
+        for node in nodes {
+            self.node(node);
+        }
+    }
+
+    fn node(&mut self, node: &tera::ast::Node) {
+        use tera::ast::Node;
+        match node {
+            Node::VariableBlock(_, expr) => self.expr(expr),
+            Node::Set(_, set) => {
+                self.expr(&set.value);
+                self.locals.push(set.key.clone());
+            }
+            Node::FilterSection(_, section, _) => {
+                self.call(&section.filter);
+                self.nodes(&section.body);
+            }
+            Node::Block(_, block, _) => self.nodes(&block.body),
+            Node::Forloop(_, forloop, _) => {
+                self.expr(&forloop.container);
+                let depth = self.locals.len();
+                self.locals.extend(forloop.key.iter().cloned());
+                self.locals.push(forloop.value.clone());
+                self.locals.push("loop".to_string());
+                self.nodes(&forloop.body);
+                self.locals.truncate(depth);
+                if let Some(body) = &forloop.empty_body {
+                    self.nodes(body);
+                }
+            }
+            Node::If(branches, _) => {
+                for (_, condition, body) in &branches.conditions {
+                    self.expr(condition);
+                    self.nodes(body);
+                }
+                if let Some((_, body)) = &branches.otherwise {
+                    self.nodes(body);
+                }
+            }
+            Node::MacroDefinition(_, definition, _) => {
+                let depth = self.locals.len();
+                self.locals.extend(definition.args.keys().cloned());
+                for default in definition.args.values().flatten() {
+                    self.expr(default);
+                }
+                self.nodes(&definition.body);
+                self.locals.truncate(depth);
+            }
+            _ => {}
+        }
+    }
+
+    fn expr(&mut self, expr: &tera::ast::Expr) {
+        if expr.has_default_filter() {
+            if let tera::ast::ExprVal::Ident(ident) = &expr.val {
+                self.optional.insert(top_level_name(ident).to_string());
+            }
+        }
+        self.value(&expr.val);
+        for filter in &expr.filters {
+            self.call(filter);
+        }
+    }
+
+    fn value(&mut self, value: &tera::ast::ExprVal) {
+        use tera::ast::ExprVal;
+        match value {
+            ExprVal::Ident(ident) => self.ident(ident),
+            ExprVal::Math(math) => {
+                self.expr(&math.lhs);
+                self.expr(&math.rhs);
+            }
+            ExprVal::Logic(logic) => {
+                self.expr(&logic.lhs);
+                self.expr(&logic.rhs);
+            }
+            ExprVal::Test(test) => {
+                if matches!(test.name.as_str(), "defined" | "undefined") {
+                    self.optional
+                        .insert(top_level_name(&test.ident).to_string());
+                }
+                self.ident(&test.ident);
+                for arg in &test.args {
+                    self.expr(arg);
+                }
+            }
+            ExprVal::MacroCall(call) => {
+                for arg in call.args.values() {
+                    self.expr(arg);
+                }
+            }
+            ExprVal::FunctionCall(call) => self.call(call),
+            ExprVal::Array(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+            }
+            ExprVal::StringConcat(concat) => {
+                for value in &concat.values {
+                    self.value(value);
+                }
+            }
+            ExprVal::In(contains) => {
+                self.expr(&contains.lhs);
+                self.expr(&contains.rhs);
+            }
+            ExprVal::String(_) | ExprVal::Int(_) | ExprVal::Float(_) | ExprVal::Bool(_) => {}
+        }
+    }
+
+    fn call(&mut self, call: &tera::ast::FunctionCall) {
+        for arg in call.args.values() {
+            self.expr(arg);
+        }
+    }
+
+    fn ident(&mut self, ident: &str) {
+        let name = top_level_name(ident);
+        if !name.is_empty() && !name.starts_with("__") && !self.locals.iter().any(|l| l == name) {
+            self.found.insert(name.to_string());
+        }
+    }
+}
+
+/// `entity.fields`や`items[0]`のような参照からトップレベルの変数名を取り出す
+fn top_level_name(ident: &str) -> &str {
+    ident.split(['.', '[']).next().unwrap_or(ident).trim()
+}
+
+/// 生成先のgit作業ツリーに未コミットの変更がないことを確認する
+fn ensure_clean_worktree(target: &Path) -> Result<()> {
+    if !target.is_dir() {
+        anyhow::bail!(
+            "--require-clean: target directory does not exist: {}",
+            target.display()
+        );
+    }
+
+    let inside = std::process::Command::new("git")
+        .arg("-C")
+        .arg(target)
+        .args(["rev-parse", "--is-inside-work-tree"])
+        .output()
+        .context("--require-clean: failed to run git")?;
+    if !inside.status.success() {
+        anyhow::bail!(
+            "--require-clean: {} is not inside a git repository",
+            target.display()
+        );
+    }
+
+    let status = std::process::Command::new("git")
+        .arg("-C")
+        .arg(target)
+        .args(["status", "--porcelain", "--", "."])
+        .output()
+        .context("--require-clean: failed to run git status")?;
+    if !status.status.success() {
+        anyhow::bail!(
+            "--require-clean: git status failed: {}",
+            String::from_utf8_lossy(&status.stderr).trim()
+        );
+    }
+
+    let changes = String::from_utf8_lossy(&status.stdout);
+    if !changes.trim().is_empty() {
+        anyhow::bail!(
+            "--require-clean: uncommitted changes in {}:\n{}",
+            target.display(),
+            changes.trim_end()
+        );
+    }
+
+    Ok(())
+}
+
+/// 生成先を含むクレートで`cargo check`を実行し、検査したクレートのディレクトリを返す
+///
+/// 生成先から親方向に最も近い`Cargo.toml`を探す。コンパイルエラーの場合は
+/// コンパイラの出力をエラーに含める（生成したファイルはそのまま残る）。
+pub fn cargo_check(target: &Path) -> Result<PathBuf> {
+    let target = target
+        .canonicalize()
+        .with_context(|| format!("--check: target directory does not exist: {:?}", target))?;
+    let Some(crate_dir) = target
+        .ancestors()
+        .find(|dir| dir.join("Cargo.toml").is_file())
+    else {
+        anyhow::bail!(
+            "--check: no Cargo.toml found in {:?} or its parents",
+            target
+        );
+    };
+
+    let output = std::process::Command::new("cargo")
+        .arg("check")
+        .arg("--quiet")
+        .current_dir(crate_dir)
+        .output()
+        .context("--check: failed to run cargo check")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "--check: cargo check failed in {}:\n{}",
+            crate_dir.display(),
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+
+    Ok(crate_dir.to_path_buf())
+}
+
+/// `dependencies`の指定（`name@version`）をクレート名とバージョンに分ける
+///
+/// バージョンを省略した場合は`None`（`*`で追加はせず、生成時にエラーにする）。
+fn parse_dependency(spec: &str) -> (&str, Option<&str>) {
+    match spec.split_once('@') {
+        Some((name, version)) => (name.trim(), Some(version.trim())),
+        None => (spec.trim(), None),
+    }
+}
+
+/// `dependencies`をCargo.tomlにそのまま貼り付けられる`[dependencies]`の表にする
+pub fn dependency_snippet(dependencies: &[String]) -> String {
+    let mut snippet = String::from("[dependencies]\n");
+    for dependency in dependencies {
+        if let (name, Some(version)) = parse_dependency(dependency) {
+            snippet.push_str(&format!("{} = \"{}\"\n", name, version));
+        }
+    }
+    snippet
+}
+
+/// 生成先から親方向に最も近い`Cargo.toml`のパス
+fn nearest_cargo_toml(target: &Path) -> Option<PathBuf> {
+    let target = std::path::absolute(target).ok()?;
+    target
+        .ancestors()
+        .map(|dir| dir.join("Cargo.toml"))
+        .find(|path| path.is_file())
+}
+
+/// 生成する`.rs`ファイルが属するクレートのCargo.toml（各ファイルから親方向に最も近いもの）
+///
+/// `layer_crate`でレイヤーごとにクレートを分けている場合は、それぞれのクレートになる。
+/// `.rs`ファイルを生成しなければ生成先のクレートを使う。
+fn crate_manifests(target: &Path, planned: &[PlannedOutput]) -> Result<Vec<PathBuf>> {
+    let mut dirs: Vec<&Path> = planned
+        .iter()
+        .filter(|output| output.path.extension().is_some_and(|ext| ext == "rs"))
+        .filter_map(|output| output.path.parent())
+        .collect();
+    if dirs.is_empty() {
+        dirs.push(target);
+    }
+
+    let mut manifests = Vec::new();
+    for dir in dirs {
+        let Some(path) = nearest_cargo_toml(dir) else {
+            anyhow::bail!(
+                "--add-deps: no Cargo.toml found in {:?} or its parents",
+                dir
+            );
+        };
+        if !manifests.contains(&path) {
+            manifests.push(path);
+        }
+    }
+    Ok(manifests)
+}
+
+/// Cargo.tomlの`[dependencies]`に未登録のクレートを追加し、追加した（`dry_run`時は
+/// 追加する予定の）クレート名を返す
+///
+/// 既存の書式やコメントはそのまま残す。`[dependencies]`がなければ作成する。
+/// 書き換えは`rollback`に記録し、生成が途中で失敗したら元の内容に戻す。
+fn add_dependencies(
+    cargo_toml: &Path,
+    dependencies: &[String],
+    dry_run: bool,
+    rollback: &mut Rollback,
+) -> Result<Vec<String>> {
+    let content = fs::read_to_string(cargo_toml)
+        .with_context(|| format!("Failed to read {:?}", cargo_toml))?;
+    let mut document: toml_edit::DocumentMut = content
+        .parse()
+        .with_context(|| format!("Failed to parse {:?}", cargo_toml))?;
+    let table = document
+        .entry("dependencies")
+        .or_insert_with(toml_edit::table)
+        .as_table_like_mut()
+        .with_context(|| format!("[dependencies] in {:?} is not a table", cargo_toml))?;
+
+    let mut added = Vec::new();
+    for dependency in dependencies {
+        let (name, Some(version)) = parse_dependency(dependency) else {
+            anyhow::bail!(
+                "Dependency '{}' has no version (use name@version)",
+                dependency
+            );
+        };
+        if table.contains_key(name) {
+            continue;
+        }
+        table.insert(name, toml_edit::value(version));
+        added.push(name.to_string());
+    }
+
+    if !dry_run && !added.is_empty() {
+        rollback.overwrite(cargo_toml, content, &document.to_string())?;
+    }
+    Ok(added)
+}
+
+/// `.rs`ファイルならrustfmtで整形した内容を返す（整形できなければ`None`）
+fn formatted_rust(path: &Path, source: &str) -> Option<String> {
+    if path.extension().is_some_and(|ext| ext == "rs") {
+        rustfmt_source(source).ok()
+    } else {
+        None
+    }
+}
+
+/// レンダリングしたRustのソースをrustfmtで整形する（標準入出力で渡し、ファイルは触らない）
+///
+/// 整形できなければ`Failed`/`Unavailable`を返す。呼び出し側は整形前の内容で生成を続ける。
+fn rustfmt_source(source: &str) -> std::result::Result<String, FormatOutcome> {
+    use std::io::Write as _;
+    use std::process::{Command, Stdio};
+
+    let unavailable = |err: std::io::Error| FormatOutcome::Unavailable(err.to_string());
+    let mut child = Command::new("rustfmt")
+        .args(["--edition", "2021", "--emit", "stdout"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(unavailable)?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(source.as_bytes()).map_err(unavailable)?;
+    }
+    let output = child.wait_with_output().map_err(unavailable)?;
+    if !output.status.success() {
+        return Err(FormatOutcome::Failed(
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|err| FormatOutcome::Failed(format!("rustfmt produced invalid UTF-8: {}", err)))
+}
+
+/// `FileSpec.when`の条件式をコンテキストで評価する
+fn evaluate_condition(condition: &str, context: &tera::Context) -> Result<bool> {
+    let template = format!("{{% if {} %}}true{{% endif %}}", condition);
+    let rendered = new_tera().render_str(&template, context)?;
+    Ok(rendered.trim() == "true")
+}
+
+/// 出力パスやmod行などの短いテンプレート文字列をコンテキストでレンダリングする
+///
+/// Teraで解釈できない文字列でも、旧形式の`{{name}}`/`{{pascal_name}}`だけで
+/// 構成されていれば単純置換にフォールバックする（非推奨）。
+fn render_inline(text: &str, context: &tera::Context) -> Result<String> {
+    match new_tera().render_str(text, context) {
+        Ok(rendered) => Ok(rendered),
+        Err(err) => {
+            let legacy = substitute_placeholders(text, context);
+            if legacy.contains("{{") || legacy.contains("{%") {
+                return Err(err.into());
+            }
+            if first_legacy_fallback(text) {
+                eprintln!(
+                    "{} falling back to legacy placeholder substitution for '{}'",
+                    "warning:".yellow(),
+                    text
+                );
+            }
+            Ok(legacy)
+        }
+    }
+}
+
+/// 旧形式へのフォールバックを警告済みの文字列
+static LEGACY_FALLBACKS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// この文字列のフォールバックを初めて警告するか（同じ文字列は1回だけ警告する）
+fn first_legacy_fallback(text: &str) -> bool {
+    LEGACY_FALLBACKS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(text.to_string())
+}
+
+/// `{{name}}`/`{{pascal_name}}`のプレースホルダーを単純置換（旧形式）
+fn substitute_placeholders(text: &str, context: &tera::Context) -> String {
+    let var = |key: &str| {
+        context
+            .get(key)
+            .and_then(|value| value.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+    text.replace("{{name}}", &var("name"))
+        .replace("{{pascal_name}}", &var("pascal_name"))
+}
+
+/// mod.rsファイルを更新
+///
+/// 登録行ごとに、追記した・既に登録済み・mod.rsを作成した（`dry_run`時はその予定）の
+/// いずれかを返す。`dry_run`時も同じmod.rsへの先行する登録を反映して判定する。
+pub fn update_mod_files(
+    target: &Path,
+    mod_updates: &[ModUpdate],
+    context: &tera::Context,
+    dry_run: bool,
+) -> Result<Vec<ModLineUpdate>> {
+    let mut rollback = Rollback::default();
+    let updated = write_mod_updates(target, mod_updates, context, dry_run, &mut rollback)?;
+    rollback.commit();
+    Ok(updated)
+}
+
+/// [`update_mod_files`]の本体（書き込みは`rollback`に記録し、失敗時に元に戻せるようにする）
+fn write_mod_updates(
+    target: &Path,
+    mod_updates: &[ModUpdate],
+    context: &tera::Context,
+    dry_run: bool,
+    rollback: &mut Rollback,
+) -> Result<Vec<ModLineUpdate>> {
+    let mut updated = Vec::new();
+    // dry-runで書き込まなかったmod.rsの内容
+    let mut pending: HashMap<PathBuf, String> = HashMap::new();
+
+    for mod_update in mod_updates {
+        let mod_path = resolve_output_path(target, &render_inline(&mod_update.mod_file, context)?)?;
+        let mod_line = render_inline(&mod_update.line, context)?;
+
+        let current = match pending.get(&mod_path) {
+            Some(content) => Some(content.clone()),
+            None if mod_path.exists() => Some(
+                fs::read_to_string(&mod_path)
+                    .with_context(|| format!("Failed to read mod file: {:?}", mod_path))?,
+            ),
+            None => None,
+        };
+        let (status, new_content) = match &current {
+            Some(content) => match insert_mod_line(content, &mod_line) {
+                Some(new_content) => (ModLineStatus::Add, new_content),
+                None => {
+                    updated.push(ModLineUpdate {
+                        mod_file: mod_path,
+                        line: mod_line,
+                        status: ModLineStatus::AlreadyPresent,
+                    });
+                    continue;
+                }
+            },
+            None => (ModLineStatus::CreateFile, format!("{}\n", mod_line)),
+        };
+
+        if dry_run {
+            pending.insert(mod_path.clone(), new_content);
+        } else if let Some(original) = current {
+            rollback.overwrite(&mod_path, original, &new_content)?;
+        } else {
+            if let Some(parent) = mod_path.parent() {
+                rollback.create_dir_all(parent)?;
+            }
+            rollback.write(&mod_path, &new_content)?;
+        }
+        updated.push(ModLineUpdate {
+            mod_file: mod_path,
+            line: mod_line,
+            status,
+        });
+    }
+
+    Ok(updated)
+}
+
+/// mod.rsに既に登録されているmod行を探す（`update_mod_files`と同じパスと行を使う）
+fn find_existing_modules(
+    target: &Path,
+    mod_updates: &[ModUpdate],
+    context: &tera::Context,
+) -> Result<Vec<(PathBuf, String)>> {
+    let mut existing = Vec::new();
+    for mod_update in mod_updates {
+        let mod_path = resolve_output_path(target, &render_inline(&mod_update.mod_file, context)?)?;
+        if !mod_path.exists() {
+            continue;
+        }
+        let mod_line = render_inline(&mod_update.line, context)?;
+        let content = fs::read_to_string(&mod_path)
+            .with_context(|| format!("Failed to read mod file: {:?}", mod_path))?;
+        if content.lines().any(|line| line.trim() == mod_line.trim()) {
+            existing.push((mod_path, mod_line));
+        }
+    }
+    Ok(existing)
+}
+
+/// mod.rsの内容に宣言行をモジュール名のアルファベット順で挿入する
+///
+/// 既存の`pub mod X;`行の中で名前順の位置に挿入し、`use`文やコメントなど
+/// それ以外の行はそのまま残す。既に同じ行がある場合は`None`を返す。
+fn insert_mod_line(content: &str, mod_line: &str) -> Option<String> {
+    let mut lines: Vec<&str> = content.lines().collect();
+    if lines.iter().any(|line| line.trim() == mod_line.trim()) {
+        return None;
+    }
+
+    let position = match mod_decl_name(mod_line) {
+        Some(new_name) => {
+            let decls: Vec<(usize, &str)> = lines
+                .iter()
+                .enumerate()
+                .filter_map(|(i, line)| mod_decl_name(line).map(|name| (i, name)))
+                .collect();
+            decls
+                .iter()
+                .find(|(_, name)| *name > new_name)
+                .map(|(i, _)| *i)
+                .or_else(|| decls.last().map(|(i, _)| i + 1))
+                .unwrap_or(lines.len())
+        }
+        // モジュール宣言以外の行は末尾に追加
+        None => lines.len(),
+    };
+
+    lines.insert(position, mod_line);
+    let mut result = lines.join("\n");
+    result.push('\n');
+    Some(result)
+}
+
+/// mod.rsの内容から宣言行を取り除く。該当行がなければ`None`を返す
+fn remove_mod_line(content: &str, mod_line: &str) -> Option<String> {
+    let lines: Vec<&str> = content.lines().collect();
+    let kept: Vec<&str> = lines
+        .iter()
+        .copied()
+        .filter(|line| line.trim() != mod_line.trim())
+        .collect();
+    if kept.len() == lines.len() {
+        return None;
+    }
+
+    let mut result = kept.join("\n");
+    if !result.is_empty() {
+        result.push('\n');
+    }
+    Some(result)
+}
+
+/// mod.rsの`pub mod X;`行が、生成するファイル（またはそれを含むディレクトリ）のモジュールか
+fn declares_planned_module(mod_path: &Path, line: &str, planned_paths: &[PathBuf]) -> bool {
+    let (Some(name), Some(parent), Some(stem)) =
+        (mod_decl_name(line), mod_path.parent(), mod_path.file_stem())
+    else {
+        return false;
+    };
+    let dir = match stem.to_str() {
+        Some("mod" | "lib" | "main") => parent.to_path_buf(),
+        _ => parent.join(stem),
+    };
+    let module = dir.join(name);
+    planned_paths
+        .iter()
+        .any(|path| *path == module.with_extension("rs") || path.starts_with(&module))
+}
+
+/// `pub mod X;`形式の行からモジュール名を取り出す
+fn mod_decl_name(line: &str) -> Option<&str> {
+    let rest = line.trim().strip_prefix("pub mod ")?;
+    let (name, _) = rest.split_once(';')?;
+    Some(name.trim())
+}
+
+/// 生成先ディレクトリの名前をsnake_caseにして機能名にする（`--name-from-dir`）
+///
+/// `.`のような相対パスは絶対パスにしてから最後の要素を使う。
+pub fn feature_name_from_dir(dir: &Path) -> Result<String> {
+    let absolute = fs::canonicalize(dir)
+        .or_else(|_| std::path::absolute(dir))
+        .with_context(|| format!("Failed to resolve directory: {:?}", dir))?;
+    match absolute
+        .file_name()
+        .map(|name| to_snake_case(&name.to_string_lossy()))
+    {
+        Some(name) if !name.is_empty() => Ok(name),
+        _ => Err(ArchitectError::new(
+            ErrorKind::Validation,
+            format!(
+                "Cannot derive a feature name from {}; pass --name",
+                absolute.display()
+            ),
+        )
+        .into()),
+    }
+}
+
+/// snake_caseに変換
+///
+/// 大文字の境界（`StockPrice` → `stock_price`、`HTTPServer` → `http_server`）と
+/// 英字→数字の境界（`Version2` → `version_2`）でアンダースコアを挿入する。
+pub fn to_snake_case(name: &str) -> String {
+    let snake = name.to_snake_case();
+    let mut result = String::with_capacity(snake.len() + 4);
+    let mut prev: Option<char> = None;
+
+    for c in snake.chars() {
+        if c.is_ascii_digit() && prev.is_some_and(|p| p.is_alphabetic()) {
+            result.push('_');
+        }
+        result.push(c);
+        prev = Some(c);
+    }
+
+    result
+}
+
+/// 識別子として使えないRustのキーワード（予約語を含む）
+const RUST_KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+    "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// 正規化済みの機能名がモジュール名として有効なRustの識別子か検証する
+///
+/// 無効な場合は、代わりに使える名前を添えてエラーにする。
+fn validate_feature_name(snake_name: &str) -> Result<()> {
+    if snake_name.is_empty() || snake_name.chars().all(|c| c == '_') {
+        anyhow::bail!("Feature name must contain at least one letter or digit");
+    }
+
+    if let Some(invalid) = snake_name
+        .chars()
+        .find(|c| !(c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '_'))
+    {
+        let suggestion: String = snake_name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_lowercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        anyhow::bail!(
+            "Invalid feature name '{}': '{}' is not allowed in a Rust module name (only a-z, 0-9 and _). Try '{}'",
+            snake_name,
+            invalid,
+            suggestion
+        );
+    }
+
+    if snake_name.starts_with(|c: char| c.is_ascii_digit()) {
+        anyhow::bail!(
+            "Invalid feature name '{}': Rust identifiers cannot start with a digit. Try 'feature_{}'",
+            snake_name,
+            snake_name
+        );
+    }
+
+    if RUST_KEYWORDS.contains(&snake_name) {
+        anyhow::bail!(
+            "Invalid feature name '{}': it is a reserved Rust keyword. Try '{}_feature'",
+            snake_name,
+            snake_name
+        );
+    }
+
+    Ok(())
+}
+
+/// 英単語を複数形にする（`y`→`ies`、`s/x/z/ch/sh`→`es`、それ以外は`s`）
+///
+/// `quiz`→`quizzes`のように、母音1つの音節が1母音+`z`で終わる語は`z`を重ねる。
+fn pluralize(word: &str) -> String {
+    let lower = word.to_lowercase();
+    let before_y = lower.chars().rev().nth(1);
+
+    if lower.ends_with('y') && before_y.is_some_and(|c| !"aeiou".contains(c)) {
+        format!("{}ies", &word[..word.len() - 1])
+    } else if doubles_final_z(&lower) {
+        format!("{}zes", word)
+    } else if ["s", "x", "z", "ch", "sh"]
+        .iter()
+        .any(|suffix| lower.ends_with(suffix))
+    {
+        format!("{}es", word)
+    } else if word.is_empty() {
+        String::new()
+    } else {
+        format!("{}s", word)
+    }
+}
+
+/// 複数形で末尾の`z`を重ねる語か（`quiz`・`whiz`・`fez`。`qu`の`u`は子音として扱う）
+fn doubles_final_z(lower: &str) -> bool {
+    let letters: Vec<char> = lower.replace("qu", "q").chars().collect();
+    let is_vowel = |c: &char| "aeiou".contains(*c);
+    let vowel_groups = letters
+        .iter()
+        .enumerate()
+        .filter(|(index, c)| is_vowel(c) && (*index == 0 || !is_vowel(&letters[index - 1])))
+        .count();
+    matches!(letters.as_slice(), [.., before, vowel, 'z'] if !is_vowel(before) && is_vowel(vowel))
+        && vowel_groups == 1
+}
+
+/// 英単語を単数形にする（`pluralize`の逆変換）
+fn singularize(word: &str) -> String {
+    let lower = word.to_lowercase();
+
+    if lower.ends_with("ies") && lower.len() > 3 {
+        format!("{}y", &word[..word.len() - 3])
+    } else if ["ses", "xes", "zes", "ches", "shes"]
+        .iter()
+        .any(|suffix| lower.ends_with(suffix))
+    {
+        word[..word.len() - 2].to_string()
+    } else if lower.ends_with('s') && !["ss", "us", "is"].iter().any(|s| lower.ends_with(s)) {
+        word[..word.len() - 1].to_string()
+    } else {
+        word.to_string()
+    }
+}
+
+/// テーブル名に変換（snake_case + 複数形、`StockPrice` → `stock_prices`）
+fn table_name(word: &str) -> String {
+    pluralize(&to_snake_case(word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_snake_case() {
+        assert_eq!(to_snake_case("MyFeature"), "my_feature");
+        assert_eq!(to_snake_case("StockPriceService"), "stock_price_service");
+        assert_eq!(to_snake_case("HTTPServer"), "http_server");
+        assert_eq!(to_snake_case("Version2"), "version_2");
+        assert_eq!(to_snake_case("my-feature"), "my_feature");
+        assert_eq!(to_snake_case("my feature"), "my_feature");
+        assert_eq!(to_snake_case("stock_price"), "stock_price");
+    }
+
+    /// テスト用のアーキタイプを作成する
+    fn write_archetype(dir: &Path, name: &str, manifest: &str, templates: &[(&str, &str)]) {
+        let archetype_dir = dir.join(name);
+        fs::create_dir_all(&archetype_dir).unwrap();
+        fs::write(archetype_dir.join("manifest.json"), manifest).unwrap();
+        for (file, content) in templates {
+            fs::write(archetype_dir.join(file), content).unwrap();
+        }
+    }
+
+    /// バナーを付けない生成オプション（生成したファイルの内容を完全一致で比べるテスト用）
+    fn plain_options(name: &str, archetype: &str, target: &Path) -> ScaffoldOptions {
+        ScaffoldOptions {
+            banner: Some(false),
+            ..test_options(name, archetype, target)
+        }
+    }
+
+    /// テスト用のデフォルトオプション
+    fn test_options(name: &str, archetype: &str, target: &Path) -> ScaffoldOptions {
+        ScaffoldOptions {
+            name: name.into(),
+            description: "desc".into(),
+            archetype: archetype.into(),
+            target: target.to_path_buf(),
+            update_mod: false,
+            dry_run: false,
+            force: false,
+            vars: Vec::new(),
+            fmt: false,
+            require_clean: false,
+            record: false,
+            only_layers: Vec::new(),
+            skip_layers: Vec::new(),
+            run_hooks: false,
+            line_ending: LineEnding::Lf,
+            no_clobber_modules: false,
+            feature_dir: false,
+            template_overrides: Vec::new(),
+            banner: None,
+            check_vars: false,
+            seed_from: None,
+            assert_unchanged: false,
+            add_deps: false,
+            merge_markers: false,
+            progress: false,
+            stream_hooks: false,
+            mod_lines: Vec::new(),
+            shared_templates_dir: None,
+        }
+    }
+
+    /// 1ファイルだけを生成する単純なアーキタイプ
+    fn write_single_file_archetype(dir: &Path) {
+        write_archetype(
+            dir,
+            "single",
+            r#"{
+                "name": "single",
+                "displayName": "Single",
+                "description": "one file",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main" }
+                ]
+            }"#,
+            &[("main.tmpl", "// {{ name }}")],
+        );
+    }
+
+    #[test]
+    fn test_scaffold_refuses_to_overwrite_without_force() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_single_file_archetype(archetypes.path());
+
+        let existing = target.path().join("src/sample.rs");
+        fs::create_dir_all(existing.parent().unwrap()).unwrap();
+        fs::write(&existing, "hand-edited").unwrap();
+
+        let mut options = plain_options("sample", "single", target.path());
+        let err = scaffold_feature(&[archetypes.path().into()], &options).unwrap_err();
+        assert!(err.to_string().contains("sample.rs"));
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "hand-edited");
+
+        options.force = true;
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "// sample\n");
+    }
+
+    #[test]
+    fn test_scaffold_rolls_back_on_failure() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "broken",
+            r#"{
+                "name": "broken",
+                "displayName": "Broken",
+                "description": "second template fails to render",
+                "files": [
+                    { "template": "ok.tmpl", "output": "src/first/{{name}}.rs", "layer": "first" },
+                    { "template": "broken.tmpl", "output": "src/second/{{name}}.rs", "layer": "second" }
+                ]
+            }"#,
+            &[
+                ("ok.tmpl", "// {{ name }}"),
+                ("broken.tmpl", "// {{ undefined_var }}"),
+            ],
+        );
+
+        let result = scaffold_feature(
+            &[archetypes.path().into()],
+            &test_options("sample", "broken", target.path()),
+        );
+
+        assert!(result.is_err());
+        assert!(!target.path().join("src/first/sample.rs").exists());
+        assert!(!target.path().join("src").exists());
+    }
+
+    #[test]
+    fn test_manifest_mod_updates() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "rust_vertical_slice",
+            r#"{
+                "name": "rust_vertical_slice",
+                "displayName": "Vertical Slice",
+                "description": "feature folders",
+                "files": [
+                    { "template": "slice.tmpl", "output": "src/features/{{name}}.rs", "layer": "feature" }
+                ],
+                "mod_updates": [
+                    { "mod_file": "src/features/mod.rs", "line": "pub mod {{name}}; // {{pascal_name}}" }
+                ]
+            }"#,
+            &[("slice.tmpl", "// {{ name }}")],
+        );
+
+        let mut options = test_options("stock_price", "rust_vertical_slice", target.path());
+        options.update_mod = true;
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+
+        let mod_rs = fs::read_to_string(target.path().join("src/features/mod.rs")).unwrap();
+        assert_eq!(mod_rs, "pub mod stock_price; // StockPrice\n");
+    }
+
+    #[test]
+    fn test_mod_updates_fallback_for_legacy_manifests() {
+        let manifest: Manifest = serde_json::from_str(
+            r#"{ "name": "rust_hexagonal", "displayName": "Hex", "description": "", "files": [] }"#,
+        )
+        .unwrap();
+        let files: Vec<_> = manifest
+            .mod_updates()
+            .into_iter()
+            .map(|u| u.mod_file)
+            .collect();
+        assert_eq!(
+            files,
+            [
+                "src/domain/mod.rs",
+                "src/ports/mod.rs",
+                "src/adapters/mod.rs"
+            ]
+        );
+
+        let other: Manifest = serde_json::from_str(
+            r#"{ "name": "rust_cli_simple", "displayName": "CLI", "description": "", "files": [] }"#,
+        )
+        .unwrap();
+        assert!(other.mod_updates().is_empty());
+    }
+
+    #[test]
+    fn test_mod_lines_are_inserted_alphabetically() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "modded",
+            r#"{
+                "name": "modded",
+                "displayName": "Modded",
+                "description": "registers modules",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/features/{{name}}.rs", "layer": "feature" }
+                ],
+                "mod_updates": [
+                    { "mod_file": "src/features/mod.rs", "line": "pub mod {{name}};" }
+                ]
+            }"#,
+            &[("main.tmpl", "// {{ name }}")],
+        );
+        let mod_path = target.path().join("src/features/mod.rs");
+        fs::create_dir_all(mod_path.parent().unwrap()).unwrap();
+        fs::write(&mod_path, "//! Features\n\nuse std::fmt;\n").unwrap();
+
+        for name in ["charlie", "alpha"] {
+            let mut options = test_options(name, "modded", target.path());
+            options.update_mod = true;
+            scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        }
+
+        // 再実行しても重複しない
+        let mut options = test_options("alpha", "modded", target.path());
+        options.update_mod = true;
+        options.force = true;
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&mod_path).unwrap(),
+            "//! Features\n\nuse std::fmt;\npub mod alpha;\npub mod charlie;\n"
+        );
+    }
+
+    #[test]
+    fn test_architect_scaffold_reports_outputs() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "modded",
+            r#"{
+                "name": "modded",
+                "displayName": "modded",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/features/{{name}}.rs", "layer": "feature" }
+                ],
+                "mod_updates": [
+                    { "mod_file": "src/features/mod.rs", "line": "pub mod {{name}};" }
+                ]
+            }"#,
+            &[("main.tmpl", "// {{ name }}")],
+        );
+        let mod_path = target.path().join("src/features/mod.rs");
+        fs::create_dir_all(mod_path.parent().unwrap()).unwrap();
+        fs::write(&mod_path, "").unwrap();
+
+        let architect = Architect::new(vec![archetypes.path().into()]);
+        assert_eq!(architect.list().unwrap()[0].name, "modded");
+
+        let options = ScaffoldOptions::new("StockPrice", "desc", "modded", target.path());
+        let report = architect.scaffold(&options).unwrap();
+        assert_eq!(report.name, "stock_price");
+        let generated: Vec<_> = report
+            .generated
+            .iter()
+            .map(|file| (file.layer.as_str(), file.path.clone()))
+            .collect();
+        assert_eq!(
+            generated,
+            vec![("feature", target.path().join("src/features/stock_price.rs"))]
+        );
+        assert!(report.skipped.is_empty());
+        assert_eq!(
+            report.mod_updates,
+            vec![ModLineUpdate {
+                mod_file: mod_path,
+                line: "pub mod stock_price;".to_string(),
+                status: ModLineStatus::Add,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_test_layer_is_not_registered_as_module() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "tested",
+            r#"{
+                "name": "tested",
+                "displayName": "tested",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/features/{{name}}.rs", "layer": "feature" },
+                    { "template": "test.tmpl", "output": "tests/{{name}}_test.rs", "layer": "test" }
+                ],
+                "mod_updates": [
+                    { "mod_file": "src/features/mod.rs", "line": "pub mod {{name}};", "layer": "feature" },
+                    { "mod_file": "tests/mod.rs", "line": "pub mod {{name}}_test;", "layer": "test" }
+                ]
+            }"#,
+            &[("main.tmpl", "// main"), ("test.tmpl", "// test")],
+        );
+        let dirs = [archetypes.path().to_path_buf()];
+        let mut options = test_options("stock", "tested", target.path());
+        options.update_mod = true;
+
+        scaffold_feature(&dirs, &options).unwrap();
+        assert!(target.path().join("tests/stock_test.rs").is_file());
+        assert!(!target.path().join("tests/mod.rs").exists());
+
+        let target = tempfile::tempdir().unwrap();
+        options.target = target.path().to_path_buf();
+        options.feature_dir = true;
+        scaffold_feature(&dirs, &options).unwrap();
+        assert!(target.path().join("tests/stock_test.rs").is_file());
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/features/stock/mod.rs")).unwrap(),
+            "pub mod features;\n"
+        );
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/features/stock/features/mod.rs")).unwrap(),
+            "pub mod stock;\n"
+        );
+    }
+
+    #[test]
+    fn test_skip_mod_excludes_files_from_registration() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "mixed",
+            r#"{
+                "name": "mixed",
+                "displayName": "mixed",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "feature" },
+                    { "template": "main.tmpl", "output": "src/{{name}}_generated.rs", "layer": "feature", "skip_mod": true },
+                    { "template": "main.tmpl", "output": "src/{{name}}.sql", "layer": "feature" },
+                    { "template": "main.tmpl", "output": "docs/README.md", "layer": "docs" }
+                ]
+            }"#,
+            &[("main.tmpl", "-- x")],
+        );
+        let mut options = test_options("stock", "mixed", target.path());
+        options.update_mod = true;
+        options.feature_dir = true;
+
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        let dir = target.path().join("src/features/stock");
+        for file in ["stock_generated.rs", "stock.sql", "docs/README.md"] {
+            assert!(dir.join(file).is_file(), "{}", file);
+        }
+        assert_eq!(
+            fs::read_to_string(dir.join("mod.rs")).unwrap(),
+            "pub mod stock;\n"
+        );
+    }
+
+    #[test]
+    fn test_skip_mod_excludes_declared_mod_updates() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "generated",
+            r#"{
+                "name": "generated",
+                "displayName": "generated",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/domain/{{name}}.rs", "layer": "domain" },
+                    { "template": "main.tmpl", "output": "src/generated/{{name}}_schema.rs", "layer": "generated", "skip_mod": true }
+                ],
+                "mod_updates": [
+                    { "mod_file": "src/domain/mod.rs", "line": "pub mod {{name}};", "layer": "domain" },
+                    { "mod_file": "src/generated/mod.rs", "line": "pub mod {{name}}_schema;", "layer": "generated" }
+                ]
+            }"#,
+            &[("main.tmpl", "// x")],
+        );
+        let mut options = test_options("stock", "generated", target.path());
+        options.update_mod = true;
+
+        let report = scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/domain/mod.rs")).unwrap(),
+            "pub mod stock;\n"
+        );
+        assert!(target
+            .path()
+            .join("src/generated/stock_schema.rs")
+            .is_file());
+        assert!(!target.path().join("src/generated/mod.rs").exists());
+        assert_eq!(report.mod_updates.len(), 1);
+    }
+
+    #[test]
+    fn test_nested_outputs_create_intermediate_modules() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "nested",
+            r#"{
+                "name": "nested",
+                "displayName": "nested",
+                "description": "",
+                "files": [
+                    { "template": "leaf.tmpl", "output": "src/domain/{{name}}/model/entity.rs", "layer": "domain" },
+                    { "template": "leaf.tmpl", "output": "src/domain/{{name}}/model/value.rs", "layer": "domain" },
+                    { "template": "leaf.tmpl", "output": "tests/{{name}}/it.rs", "layer": "test" },
+                    { "template": "leaf.tmpl", "output": "tests/common/{{name}}/helper.rs", "layer": "test" },
+                    { "template": "leaf.tmpl", "output": "src/bin/{{name}}/cli.rs", "layer": "bin" },
+                    { "template": "leaf.tmpl", "output": "examples/{{name}}/demo.rs", "layer": "example" }
+                ],
+                "mod_updates": []
+            }"#,
+            &[("leaf.tmpl", "// leaf")],
+        );
+        fs::create_dir_all(target.path().join("src/domain")).unwrap();
+        fs::create_dir_all(target.path().join("tests/common")).unwrap();
+        fs::write(target.path().join("src/lib.rs"), "pub mod domain;\n").unwrap();
+        fs::write(target.path().join("src/domain/mod.rs"), "pub mod other;\n").unwrap();
+        fs::write(target.path().join("tests/common/mod.rs"), "").unwrap();
+        let dirs = [archetypes.path().to_path_buf()];
+        let mut options = test_options("stock", "nested", target.path());
+        options.update_mod = true;
+
+        let report = scaffold_feature(&dirs, &options).unwrap();
+        let read = |path: &str| fs::read_to_string(target.path().join(path)).unwrap();
+        assert_eq!(
+            read("src/domain/mod.rs"),
+            "pub mod other;\npub mod stock;\n"
+        );
+        assert_eq!(read("src/domain/stock/mod.rs"), "pub mod model;\n");
+        assert_eq!(
+            read("src/domain/stock/model/mod.rs"),
+            "pub mod entity;\npub mod value;\n"
+        );
+        assert!(!target.path().join("tests/stock/mod.rs").exists());
+        assert!(!target.path().join("tests/common/stock/mod.rs").exists());
+        assert_eq!(read("tests/common/mod.rs"), "");
+        assert!(!target.path().join("src/bin/mod.rs").exists());
+        assert_eq!(read("src/lib.rs"), "pub mod domain;\n");
+        assert_eq!(report.mod_updates.len(), 4);
+
+        let report = scaffold_feature(
+            &dirs,
+            &ScaffoldOptions {
+                force: true,
+                dry_run: true,
+                ..options
+            },
+        )
+        .unwrap();
+        assert!(report.mod_updates.iter().all(|update| !update.is_change()));
+    }
+
+    #[test]
+    fn test_dry_run_classifies_mod_lines() {
+        let target = tempfile::tempdir().unwrap();
+        let features = target.path().join("src/features");
+        fs::create_dir_all(&features).unwrap();
+        fs::write(features.join("mod.rs"), "pub mod stock;\n").unwrap();
+        let mod_updates: Vec<ModUpdate> = serde_json::from_str(
+            r#"[
+                { "mod_file": "src/features/mod.rs", "line": "pub mod stock;" },
+                { "mod_file": "src/features/mod.rs", "line": "pub mod {{name}};" },
+                { "mod_file": "src/ports/mod.rs", "line": "pub mod {{name}};" },
+                { "mod_file": "src/ports/mod.rs", "line": "pub mod {{name}};" }
+            ]"#,
+        )
+        .unwrap();
+        let context = build_context("order", "Order", "", &[]).unwrap();
+
+        let statuses: Vec<_> = update_mod_files(target.path(), &mod_updates, &context, true)
+            .unwrap()
+            .into_iter()
+            .map(|update| update.status.label(true))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                "already-present",
+                "would-add",
+                "would-create-file",
+                "already-present"
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(features.join("mod.rs")).unwrap(),
+            "pub mod stock;\n"
+        );
+        assert!(!target.path().join("src/ports").exists());
+    }
+
+    #[test]
+    fn test_insert_mod_line_preserves_surrounding_lines() {
+        let content = "use a;\npub mod bravo;\npub mod delta;\n\n// trailing\n";
+        assert_eq!(
+            insert_mod_line(content, "pub mod charlie;").unwrap(),
+            "use a;\npub mod bravo;\npub mod charlie;\npub mod delta;\n\n// trailing\n"
+        );
+        assert_eq!(
+            insert_mod_line(content, "pub mod echo;").unwrap(),
+            "use a;\npub mod bravo;\npub mod delta;\npub mod echo;\n\n// trailing\n"
+        );
+        assert!(insert_mod_line(content, "pub mod delta;").is_none());
+    }
+
+    #[test]
+    fn test_validate_manifest_reports_all_problems() {
+        let archetypes = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "messy",
+            r#"{
+                "name": "messy",
+                "displayName": "Messy",
+                "description": "several problems",
+                "files": [
+                    { "template": "ok.tmpl", "output": "src/{{name}}.rs", "layer": "main" },
+                    { "template": "missing.tmpl", "output": "/etc/{{name}}.rs", "layer": "" },
+                    { "template": "ok.tmpl", "output": "../{{name}}.rs", "layer": "main" }
+                ]
+            }"#,
+            &[("ok.tmpl", "")],
+        );
+
+        let manifest = load_archetype(&[archetypes.path().into()], "messy").unwrap();
+        let problems = validate_manifest(&manifest, &archetypes.path().join("messy"));
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+        assert!(problems[0].contains("missing.tmpl"));
+        assert!(problems[1].contains("/etc/"));
+        assert!(problems[2].contains("layer is empty"));
+        assert!(problems[3].contains(".."));
+
+        assert!(validate_archetypes(&[archetypes.path().into()], None, None).is_err());
+    }
+
+    #[test]
+    fn test_bundled_archetypes_are_valid() {
+        let archetypes_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../archetypes");
+        validate_archetypes(&[archetypes_dir], None, None).unwrap();
+    }
+
+    #[test]
+    fn test_scaffold_with_custom_vars() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "vars",
+            r#"{
+                "name": "vars",
+                "displayName": "Vars",
+                "description": "uses custom vars",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main" }
+                ]
+            }"#,
+            &[("main.tmpl", "// {{ author }} -> {{ table }}")],
+        );
+
+        let mut options = plain_options("sample", "vars", target.path());
+        options.vars = vec![
+            ("author".into(), "Jane Doe".into()),
+            ("table".into(), "users".into()),
+        ];
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/sample.rs")).unwrap(),
+            "// Jane Doe -> users\n"
+        );
+
+        options.vars = vec![("name".into(), "other".into())];
+        options.force = true;
+        let err = scaffold_feature(&[archetypes.path().into()], &options).unwrap_err();
+        assert!(err.to_string().contains("built-in"));
+    }
+
+    #[test]
+    fn test_default_vars_can_be_overridden() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "defaults",
+            r#"{
+                "name": "defaults",
+                "displayName": "defaults",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main" }
+                ],
+                "default_vars": { "license": "MIT", "async_runtime": "tokio" }
+            }"#,
+            &[("main.tmpl", "// {{ license }} / {{ async_runtime }}")],
+        );
+
+        let mut options = plain_options("sample", "defaults", target.path());
+        options.vars = vec![("async_runtime".into(), "async-std".into())];
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/sample.rs")).unwrap(),
+            "// MIT / async-std\n"
+        );
+
+        write_archetype(
+            archetypes.path(),
+            "reserved",
+            r#"{ "name": "reserved", "displayName": "reserved", "description": "", "files": [], "default_vars": { "name": "x" } }"#,
+            &[],
+        );
+        let manifest = load_archetype(&[archetypes.path().into()], "reserved").unwrap();
+        assert_eq!(
+            validate_manifest(&manifest, &manifest.dir),
+            vec!["default_vars must not override built-in variable: name"]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_output_paths_are_rejected() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "colliding",
+            r#"{
+                "name": "colliding",
+                "displayName": "colliding",
+                "description": "",
+                "files": [
+                    { "template": "a.tmpl", "output": "src/{{name}}.rs", "layer": "main" },
+                    { "template": "b.tmpl", "output": "src/{{ module }}.rs", "layer": "main" }
+                ]
+            }"#,
+            &[("a.tmpl", "// a"), ("b.tmpl", "// b")],
+        );
+
+        let mut options = test_options("sample", "colliding", target.path());
+        options.vars = vec![("module".into(), "sample".into())];
+        let err = scaffold_feature(&[archetypes.path().into()], &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Duplicate output path src/sample.rs: generated by both a.tmpl and b.tmpl"
+        );
+        assert!(!target.path().join("src/sample.rs").exists());
+
+        // 別の名前に解決されれば衝突しない
+        options.vars = vec![("module".into(), "other".into())];
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+
+        write_archetype(
+            archetypes.path(),
+            "same_output",
+            r#"{
+                "name": "same_output",
+                "displayName": "same_output",
+                "description": "",
+                "files": [
+                    { "template": "a.tmpl", "output": "src/{{name}}.rs", "layer": "main" },
+                    { "template": "b.tmpl", "output": "src/{{name}}.rs", "layer": "main" },
+                    { "template": "a.tmpl", "output": "src/{{name}}.rs", "layer": "main", "when": "flag" }
+                ]
+            }"#,
+            &[("a.tmpl", "// a"), ("b.tmpl", "// b")],
+        );
+        let manifest = load_archetype(&[archetypes.path().into()], "same_output").unwrap();
+        assert_eq!(
+            validate_manifest(&manifest, &manifest.dir),
+            vec!["duplicate output src/{{name}}.rs: generated by both a.tmpl and b.tmpl"]
+        );
+    }
+
+    #[test]
+    fn test_scaffold_rejects_outputs_outside_target() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let target = root.path().join("project");
+        fs::create_dir_all(&target).unwrap();
+        write_archetype(
+            archetypes.path(),
+            "evil",
+            r#"{
+                "name": "evil",
+                "displayName": "evil",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main" },
+                    { "template": "main.tmpl", "output": "../../etc/cron.d/evil", "layer": "main" }
+                ]
+            }"#,
+            &[("main.tmpl", "// {{ name }}")],
+        );
+        write_archetype(
+            archetypes.path(),
+            "injected",
+            r#"{
+                "name": "injected",
+                "displayName": "injected",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{ module }}/{{name}}.rs", "layer": "main" }
+                ]
+            }"#,
+            &[("main.tmpl", "// {{ name }}")],
+        );
+
+        let err = scaffold_feature(
+            &[archetypes.path().into()],
+            &test_options("sample", "evil", &target),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("escapes the target directory"));
+        assert!(!target.join("src/sample.rs").exists());
+
+        let mut options = test_options("sample", "injected", &target);
+        options.vars = vec![("module".into(), "../..".into())];
+        let err = scaffold_feature(&[archetypes.path().into()], &options).unwrap_err();
+        assert!(err.to_string().contains("escapes the target directory"));
+        assert!(!root.path().join("sample.rs").exists());
+
+        // 生成先の内側に戻る`..`は許可する
+        options.vars = vec![("module".into(), "a/../b".into())];
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        assert!(target.join("src/b/sample.rs").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scaffold_rejects_outputs_through_symlinks() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        write_single_file_archetype(archetypes.path());
+        std::os::unix::fs::symlink(outside.path(), target.path().join("src")).unwrap();
+
+        let err = scaffold_feature(
+            &[archetypes.path().into()],
+            &test_options("sample", "single", target.path()),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("escapes the target directory"));
+        assert!(!outside.path().join("sample.rs").exists());
+    }
+
+    #[test]
+    fn test_crlf_template_generates_lf_only() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "windows",
+            r#"{
+                "name": "windows",
+                "displayName": "windows",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "{{name}}.rs", "layer": "main" }
+                ]
+            }"#,
+            &[("main.tmpl", "// {{ name }}\r\nfn main() {}\r\n\r\n\r\n")],
+        );
+
+        let options = plain_options("sample", "windows", target.path());
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        let content = fs::read_to_string(target.path().join("sample.rs")).unwrap();
+        assert_eq!(content, "// sample\nfn main() {}\n");
+
+        // 改行コードを変えて再生成しても差分扱いにならない
+        let mut options = plain_options("sample", "windows", target.path());
+        options.line_ending = LineEnding::Crlf;
+        options.force = true;
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        let content = fs::read_to_string(target.path().join("sample.rs")).unwrap();
+        assert_eq!(content, "// sample\r\nfn main() {}\r\n");
+    }
+
+    #[test]
+    fn test_normalize_line_endings() {
+        assert_eq!(
+            normalize_line_endings("a\r\nb\rc\n", LineEnding::Lf),
+            "a\nb\nc\n"
+        );
+        assert_eq!(normalize_line_endings("a", LineEnding::Lf), "a\n");
+        assert_eq!(
+            normalize_line_endings("a\nb\n\n", LineEnding::Crlf),
+            "a\r\nb\r\n"
+        );
+        assert_eq!(normalize_line_endings("\n\n", LineEnding::Lf), "");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_post_generate_hooks() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "hooked",
+            r#"{
+                "name": "hooked",
+                "displayName": "hooked",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main" }
+                ],
+                "mod_updates": [
+                    { "mod_file": "src/lib.rs", "line": "pub mod {{name}};" },
+                    { "mod_file": "src/registry/mod.rs", "line": "pub mod {{name}};" }
+                ],
+                "hooks": {
+                    "post_generate": ["echo {{ name }} >> registry.txt", "{{ command }}"]
+                }
+            }"#,
+            &[("main.tmpl", "// {{ name }}")],
+        );
+
+        // --run-hooksなしでは実行しない
+        let mut options = test_options("sample", "hooked", target.path());
+        options.vars = vec![("command".into(), "cat registry.txt".into())];
+        let report = scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        assert!(report.hooks.is_empty());
+        assert_eq!(report.skipped_hooks[0], "echo sample >> registry.txt");
+        assert!(!target.path().join("registry.txt").exists());
+
+        options.run_hooks = true;
+        options.force = true;
+        let report = scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        assert_eq!(report.hooks.len(), 2);
+        assert_eq!(report.hooks[1].output, "sample\n");
+
+        // 失敗したフックは生成したファイルとmod.rsの変更を巻き戻す
+        let fresh = tempfile::tempdir().unwrap();
+        fs::create_dir_all(fresh.path().join("src")).unwrap();
+        fs::write(fresh.path().join("src/lib.rs"), "pub mod existing;\n").unwrap();
+        let mut options = test_options("other", "hooked", fresh.path());
+        options.vars = vec![("command".into(), "echo broken >&2; exit 3".into())];
+        options.run_hooks = true;
+        options.update_mod = true;
+        let err = scaffold_feature(&[archetypes.path().into()], &options).unwrap_err();
+        assert!(err.to_string().contains("Hook failed"));
+        assert!(err.to_string().contains("broken"));
+        assert!(!fresh.path().join("src/other.rs").exists());
+        assert_eq!(
+            fs::read_to_string(fresh.path().join("src/lib.rs")).unwrap(),
+            "pub mod existing;\n"
+        );
+        assert!(!fresh.path().join("src/registry").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_hook_captures_both_streams() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = run_hook(
+            "echo out; sleep 0.1; echo err >&2; sleep 0.1; printf tail",
+            dir.path(),
+            false,
+        )
+        .unwrap();
+        assert_eq!(output, "out\nerr\ntail");
+
+        let err = run_hook("echo partial; exit 2", dir.path(), false).unwrap_err();
+        assert!(err.to_string().contains("partial"), "{err:#}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "scripts",
+            r#"{
+                "name": "scripts",
+                "displayName": "scripts",
+                "description": "",
+                "files": [
+                    { "template": "run.tmpl", "output": "scripts/{{name}}.sh", "layer": "script", "mode": "0755" },
+                    { "template": "run.tmpl", "output": "scripts/{{name}}.txt", "layer": "script" }
+                ]
+            }"#,
+            &[("run.tmpl", "#!/bin/sh\necho {{ name }}")],
+        );
+        scaffold_feature(
+            &[archetypes.path().into()],
+            &test_options("deploy", "scripts", target.path()),
+        )
+        .unwrap();
+        let mode = |path: &str| {
+            fs::metadata(target.path().join(path))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o777
+        };
+        assert_eq!(mode("scripts/deploy.sh"), 0o755);
+        assert_ne!(mode("scripts/deploy.txt") & 0o111, 0o111);
+
+        assert_eq!(parse_mode("644").unwrap(), 0o644);
+        assert!(parse_mode("0789").is_err());
+        assert!(parse_mode("rwx").is_err());
+    }
+
+    #[test]
+    fn test_toml_manifest() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let dir = archetypes.path().join("toml_based");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("manifest.toml"),
+            r#"
+name = "toml_based"
+display_name = "TOML Based"
+description = "written in TOML"
+
+[[files]]
+template = "main.tmpl"
+output = "src/{{name}}.rs"
+layer = "main"
+"#,
+        )
+        .unwrap();
+        fs::write(dir.join("main.tmpl"), "// {{ name }}").unwrap();
+
+        let archetypes_dirs = [archetypes.path().to_path_buf()];
+        let manifest = load_archetype(&archetypes_dirs, "toml_based").unwrap();
+        assert_eq!(manifest.display_name, "TOML Based");
+        assert_eq!(load_all_archetypes(&archetypes_dirs).unwrap().len(), 1);
+
+        scaffold_feature(
+            &archetypes_dirs,
+            &plain_options("sample", "toml_based", target.path()),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/sample.rs")).unwrap(),
+            "// sample\n"
+        );
+
+        // 両方ある場合はmanifest.jsonが優先される
+        fs::write(
+            dir.join("manifest.json"),
+            r#"{ "name": "toml_based", "displayName": "JSON Based", "description": "", "files": [] }"#,
+        )
+        .unwrap();
+        let manifest = load_archetype(&archetypes_dirs, "toml_based").unwrap();
+        assert_eq!(manifest.display_name, "JSON Based");
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        let manifest: Manifest =
+            serde_json::from_str(r#"{ "name": "heavy", "displayName": "heavy", "description": "", "size": "large", "files": [] }"#).unwrap();
+        assert!(manifest.size_mismatch(ProjectSize::Small));
+        assert!(!manifest.size_mismatch(ProjectSize::Large));
+
+        let untagged: Manifest = serde_json::from_str(
+            r#"{ "name": "any", "displayName": "any", "description": "", "files": [] }"#,
+        )
+        .unwrap();
+        assert!(!untagged.size_mismatch(ProjectSize::Small));
+
+        let invalid = serde_json::from_str::<Manifest>(
+            r#"{ "name": "x", "displayName": "x", "description": "", "size": "huge", "files": [] }"#,
+        );
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn test_load_var_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let json = dir.path().join("vars.json");
+        fs::write(
+            &json,
+            r#"{ "author": "jane", "enable_cache": true, "port": 8080 }"#,
+        )
+        .unwrap();
+        let toml = dir.path().join("vars.toml");
+        fs::write(
+            &toml,
+            "author = \"jane\"\nenable_cache = true\nport = 8080\n",
+        )
+        .unwrap();
+
+        let expected = vec![
+            ("author".to_string(), serde_json::json!("jane")),
+            ("enable_cache".to_string(), serde_json::json!(true)),
+            ("port".to_string(), serde_json::json!(8080)),
+        ];
+        assert_eq!(load_var_file(&json).unwrap(), expected);
+        assert_eq!(load_var_file(&toml).unwrap(), expected);
+
+        let nested = dir.path().join("nested.toml");
+        fs::write(&nested, "[db]\nhost = \"localhost\"\n").unwrap();
+        let err = load_var_file(&nested).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("only strings, numbers and booleans"));
+        assert!(err.to_string().contains("db"));
+
+        let yaml = dir.path().join("vars.yaml");
+        fs::write(&yaml, "author: jane").unwrap();
+        assert!(load_var_file(&yaml).is_err());
+    }
+
+    #[test]
+    fn test_boolean_vars_drive_conditions() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "cached",
+            r#"{
+                "name": "cached",
+                "displayName": "cached",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main" }
+                ]
+            }"#,
+            &[(
+                "main.tmpl",
+                "{% if enable_cache %}cached{% else %}plain{% endif %} {{ port + 1 }}",
+            )],
+        );
+
+        let mut options = plain_options("sample", "cached", target.path());
+        options.vars = vec![
+            ("enable_cache".into(), serde_json::json!(false)),
+            ("port".into(), serde_json::json!(8080)),
+        ];
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/sample.rs")).unwrap(),
+            "plain 8081\n"
+        );
+    }
+
+    #[test]
+    fn test_nested_vars_can_be_iterated() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "entity",
+            r#"{
+                "name": "entity",
+                "displayName": "entity",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main" }
+                ]
+            }"#,
+            &[(
+                "main.tmpl",
+                "{% for f in entity.fields %}{{ f.name }}: {{ f.ty }},{% endfor %}",
+            )],
+        );
+
+        let mut options = plain_options("user", "entity", target.path());
+        options.vars = vec![(
+            "entity".into(),
+            serde_json::json!({ "fields": [{ "name": "id", "ty": "i64" }, { "name": "email", "ty": "String" }] }),
+        )];
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/user.rs")).unwrap(),
+            "id: i64,email: String,\n"
+        );
+    }
+
+    #[test]
+    fn test_for_each_generates_one_file_per_item() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "repos",
+            r#"{
+                "name": "repos",
+                "displayName": "repos",
+                "description": "",
+                "files": [
+                    { "template": "mod.tmpl", "output": "src/{{name}}.rs", "layer": "main" },
+                    {
+                        "template": "repo.tmpl",
+                        "output": "src/repos/{{ item.name }}_repository.rs",
+                        "layer": "adapter",
+                        "for_each": "schema.entities",
+                        "when": "not item.skip"
+                    }
+                ]
+            }"#,
+            &[
+                ("mod.tmpl", "// {{ name }}"),
+                ("repo.tmpl", "// {{ name }}: {{ item.name | capitalize }}"),
+            ],
+        );
+
+        let mut options = plain_options("store", "repos", target.path());
+        options.vars = vec![(
+            "schema".into(),
+            serde_json::json!({ "entities": [
+                { "name": "user" },
+                { "name": "order" },
+                { "name": "legacy", "skip": true }
+            ] }),
+        )];
+        let report = scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        assert_eq!(report.generated.len(), 3);
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/repos/order_repository.rs")).unwrap(),
+            "// store: Order\n"
+        );
+        assert!(target.path().join("src/repos/user_repository.rs").exists());
+        assert_eq!(
+            report.skipped[0].path,
+            target.path().join("src/repos/legacy_repository.rs")
+        );
+
+        options.vars = vec![("schema".into(), serde_json::json!({ "entities": "user" }))];
+        options.force = true;
+        let err = scaffold_feature(&[archetypes.path().into()], &options).unwrap_err();
+        assert!(err.to_string().contains("must be an array"));
+
+        options.vars = Vec::new();
+        let err = scaffold_feature(&[archetypes.path().into()], &options).unwrap_err();
+        assert!(err.to_string().contains("'schema.entities' is not defined"));
+    }
+
+    #[test]
+    fn test_new_archetype_is_valid() {
+        let archetypes = tempfile::tempdir().unwrap();
+        new_archetype(&[archetypes.path().into()], "my_archetype").unwrap();
+
+        let manifest = load_archetype(&[archetypes.path().into()], "my_archetype").unwrap();
+        assert_eq!(manifest.name, "my_archetype");
+        assert_eq!(manifest.display_name, "MyArchetype");
+        validate_archetypes(&[archetypes.path().into()], Some("my_archetype"), None).unwrap();
+
+        let err = new_archetype(&[archetypes.path().into()], "my_archetype").unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+        assert!(new_archetype(&[archetypes.path().into()], "../escape").is_err());
+    }
+
+    #[test]
+    fn test_manifest_json_round_trip() {
+        let archetypes_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../archetypes");
+        let archetypes = load_all_archetypes(&[archetypes_dir]).unwrap();
+
+        let json = serde_json::to_string(&archetypes).unwrap();
+        assert!(json.contains("\"displayName\""));
+
+        let parsed: Vec<Manifest> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.len(), archetypes.len());
+        assert_eq!(parsed[0].display_name, archetypes[0].display_name);
+    }
+
+    #[test]
+    fn test_skipped_files_do_not_need_templates() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "optional",
+            r#"{
+                "name": "optional",
+                "displayName": "Optional",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main" },
+                    { "template": "missing.tmpl", "output": "src/{{name}}_extra.rs", "layer": "main", "when": "false" }
+                ]
+            }"#,
+            &[("main.tmpl", "// {{ name }}")],
+        );
+
+        let report = scaffold_feature(
+            &[archetypes.path().into()],
+            &test_options("sample", "optional", target.path()),
+        )
+        .unwrap();
+        assert_eq!(report.generated.len(), 1);
+        assert_eq!(report.skipped.len(), 1);
+    }
+
+    #[test]
+    fn test_conditional_file_generation() {
+        let archetypes = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "conditional",
+            r#"{
+                "name": "conditional",
+                "displayName": "Conditional",
+                "description": "migration only on demand",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main" },
+                    {
+                        "template": "migration.tmpl",
+                        "output": "migrations/{{name}}.sql",
+                        "layer": "migration",
+                        "when": "needs_migration"
+                    }
+                ]
+            }"#,
+            &[
+                ("main.tmpl", "// {{ name }}"),
+                ("migration.tmpl", "-- {{ name }}"),
+            ],
+        );
+
+        let without = tempfile::tempdir().unwrap();
+        let report = scaffold_feature(
+            &[archetypes.path().into()],
+            &test_options("sample", "conditional", without.path()),
+        )
+        .unwrap();
+        assert!(without.path().join("src/sample.rs").exists());
+        assert!(!without.path().join("migrations/sample.sql").exists());
+        assert_eq!(report.generated.len(), 1);
+        assert_eq!(
+            report.skipped[0].path,
+            without.path().join("migrations/sample.sql")
+        );
+        assert_eq!(report.skipped[0].condition, "needs_migration");
+
+        let planned = plan_feature(
+            &[archetypes.path().into()],
+            &test_options("plan_only", "conditional", without.path()),
+        )
+        .unwrap();
+        assert_eq!(
+            planned,
+            vec![PlannedFile {
+                template: "main.tmpl".into(),
+                output: "src/{{name}}.rs".into(),
+                layer: "main".into(),
+                resolved_path: without.path().join("src/plan_only.rs"),
+            }]
+        );
+        assert!(!without.path().join("src/plan_only.rs").exists());
+
+        let with = tempfile::tempdir().unwrap();
+        let mut options = test_options("sample", "conditional", with.path());
+        options.vars = vec![("needs_migration".into(), "true".into())];
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        assert!(with.path().join("migrations/sample.sql").exists());
+    }
+
+    #[test]
+    fn test_plan_honors_layer_filters() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "layered",
+            r#"{
+                "name": "layered",
+                "displayName": "layered",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/domain/{{name}}.rs", "layer": "domain" },
+                    { "template": "main.tmpl", "output": "src/ports/{{name}}_port.rs", "layer": "port" },
+                    { "template": "main.tmpl", "output": "src/adapters/{{name}}_adapter.rs", "layer": "adapter" },
+                    { "template": "main.tmpl", "output": "src/domain/{{name}}_extra.rs", "layer": "domain", "when": "false" }
+                ]
+            }"#,
+            &[("main.tmpl", "// {{ name }}")],
+        );
+        let dirs = [archetypes.path().into()];
+
+        // 生成条件を満たさないファイルは数えない
+        let mut options = test_options("stock", "layered", target.path());
+        assert_eq!(plan_feature(&dirs, &options).unwrap().len(), 3);
+        options.skip_layers = vec!["adapter".into()];
+        assert_eq!(plan_feature(&dirs, &options).unwrap().len(), 2);
+        options.skip_layers.clear();
+        options.only_layers = vec!["domain".into()];
+        let planned = plan_feature(&dirs, &options).unwrap();
+        assert_eq!(planned.len(), 1);
+        assert_eq!(planned[0].layer, "domain");
+    }
+
+    #[test]
+    fn test_explicit_mod_lines_replace_archetype_mod_updates() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "standard",
+            r#"{
+                "name": "standard",
+                "displayName": "standard",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/domain/{{name}}.rs", "layer": "domain" }
+                ],
+                "mod_updates": [
+                    { "mod_file": "src/domain/mod.rs", "line": "pub mod {{name}};", "layer": "domain" }
+                ]
+            }"#,
+            &[("main.tmpl", "// {{ name }}")],
+        );
+        fs::create_dir_all(target.path().join("src")).unwrap();
+        fs::write(target.path().join("src/lib.rs"), "pub mod app;\n").unwrap();
+        let mut options = test_options("stock", "standard", target.path());
+        options.update_mod = true;
+        options.mod_lines = vec![ModUpdate {
+            mod_file: "src/lib.rs".into(),
+            line: "#[path = \"domain/{{name}}.rs\"] pub mod {{name}};".into(),
+            layer: None,
+        }];
+
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        options.force = true;
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/lib.rs")).unwrap(),
+            "pub mod app;\n#[path = \"domain/stock.rs\"] pub mod stock;\n"
+        );
+        assert!(!target.path().join("src/domain/mod.rs").exists());
+    }
+
+    #[test]
+    fn test_templated_layer_names() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "templated",
+            r#"{
+                "name": "templated",
+                "displayName": "templated",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/domain/{{name}}.rs", "layer": "domain::{{name}}" },
+                    { "template": "main.tmpl", "output": "src/ports/{{name}}_port.rs", "layer": "port" },
+                    { "template": "main.tmpl", "output": "tests/{{name}}_test.rs", "layer": "{{ test_layer }}" }
+                ],
+                "layer_roots": { "domain::stock": "src/domain" },
+                "default_vars": { "test_layer": "test" }
+            }"#,
+            &[("main.tmpl", "// {{ name }}")],
+        );
+        let dirs = [archetypes.path().into()];
+
+        let report =
+            scaffold_feature(&dirs, &test_options("stock", "templated", target.path())).unwrap();
+        let layers: Vec<&str> = report.generated.iter().map(|f| f.layer.as_str()).collect();
+        assert_eq!(layers, ["domain::stock", "port", "test"]);
+
+        let mut options = test_options("stock", "templated", target.path());
+        options.only_layers = vec!["domain::stock".into()];
+        let planned = plan_feature(&dirs, &options).unwrap();
+        assert_eq!(planned.len(), 1);
+        assert_eq!(planned[0].layer, "domain::stock");
+    }
+
+    #[test]
+    fn test_feature_name_from_dir() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("src/features/StockPrice");
+        fs::create_dir_all(&dir).unwrap();
+        assert_eq!(feature_name_from_dir(&dir).unwrap(), "stock_price");
+        assert_eq!(feature_name_from_dir(&dir.join("..")).unwrap(), "features");
+        assert_eq!(
+            feature_name_from_dir(&root.path().join("market-data")).unwrap(),
+            "market_data"
+        );
+        assert!(feature_name_from_dir(Path::new("/")).is_err());
+    }
+
+    #[test]
+    fn test_templates_outside_archetype_dir() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let shared = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "reuse",
+            r#"{
+                "name": "reuse",
+                "displayName": "reuse",
+                "description": "",
+                "files": [
+                    { "template": "../_common/header.tmpl", "output": "src/{{name}}.rs", "layer": "main" },
+                    { "template": "@shared/port.tmpl", "output": "src/{{name}}_port.rs", "layer": "port" }
+                ]
+            }"#,
+            &[],
+        );
+        fs::create_dir_all(archetypes.path().join("_common")).unwrap();
+        fs::write(
+            archetypes.path().join("_common/header.tmpl"),
+            "// common {{ name }}",
+        )
+        .unwrap();
+        fs::write(shared.path().join("port.tmpl"), "// shared {{ name }}").unwrap();
+        let dirs = [archetypes.path().into()];
+
+        let mut manifest = load_archetype(&dirs, "reuse").unwrap();
+        let problems = validate_manifest(&manifest, &manifest.dir);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("needs a shared templates directory"));
+
+        set_shared_dir(&mut manifest, Some(shared.path()));
+        assert!(validate_manifest(&manifest, &manifest.dir).is_empty());
+        let tera = load_templates(&manifest.dir, &manifest, &manifest.files).unwrap();
+        let context = build_context("stock", "Stock", "", &[]).unwrap();
+        for (file_spec, expected) in manifest
+            .files
+            .iter()
+            .zip(["// common stock", "// shared stock"])
+        {
+            assert_eq!(render_file(&tera, file_spec, &context).unwrap(), expected);
+        }
+
+        fs::remove_file(shared.path().join("port.tmpl")).unwrap();
+        let problems = validate_manifest(&manifest, &manifest.dir);
+        assert!(
+            problems[0].starts_with("template not found"),
+            "{:?}",
+            problems
+        );
+    }
+
+    #[test]
+    fn test_shared_templates_dir_from_options() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let shared = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "reuse",
+            r#"{
+                "name": "reuse",
+                "displayName": "reuse",
+                "description": "",
+                "files": [
+                    { "template": "@shared/port.tmpl", "output": "src/{{name}}_port.rs", "layer": "port" }
+                ]
+            }"#,
+            &[],
+        );
+        fs::write(shared.path().join("port.tmpl"), "// shared {{ name }}").unwrap();
+        let dirs = [archetypes.path().into()];
+
+        let mut options = plain_options("stock", "reuse", target.path());
+        assert!(scaffold_feature(&dirs, &options).is_err());
+        options.shared_templates_dir = Some(shared.path().to_path_buf());
+        scaffold_feature(&dirs, &options).unwrap();
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/stock_port.rs")).unwrap(),
+            "// shared stock\n"
+        );
+
+        let architect = Architect::new(dirs.to_vec())
+            .with_shared_templates_dir(Some(shared.path().to_path_buf()));
+        assert!(architect.validate(Some("reuse")).is_ok());
+    }
+
+    #[test]
+    fn test_absolute_template_is_rejected() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let template = outside.path().join("secret.tmpl");
+        fs::write(&template, "// {{ name }}").unwrap();
+        write_archetype(
+            archetypes.path(),
+            "absolute",
+            &format!(
+                r#"{{
+                    "name": "absolute",
+                    "displayName": "absolute",
+                    "description": "",
+                    "files": [
+                        {{ "template": {:?}, "output": "src/{{{{name}}}}.rs", "layer": "main" }}
+                    ]
+                }}"#,
+                template.display().to_string()
+            ),
+            &[],
+        );
+        let dirs = [archetypes.path().into()];
+
+        let options = test_options("stock", "absolute", target.path());
+        let err = scaffold_feature(&dirs, &options).unwrap_err();
+        assert!(
+            err.to_string().contains("template must be relative"),
+            "{err:#}"
+        );
+        assert!(!target.path().join("src/stock.rs").exists());
+    }
+
+    #[test]
+    fn test_progress_bar_only_for_many_files() {
+        assert!(progress_bar(false, PROGRESS_THRESHOLD + 10).is_hidden());
+        assert!(progress_bar(true, PROGRESS_THRESHOLD).is_hidden());
+    }
+
+    #[test]
+    fn test_template_bom_is_stripped() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "bom",
+            r#"{
+                "name": "bom",
+                "displayName": "bom",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main" }
+                ]
+            }"#,
+            &[("main.tmpl", "\u{FEFF}// {{ name }}")],
+        );
+
+        scaffold_feature(
+            &[archetypes.path().into()],
+            &plain_options("stock", "bom", target.path()),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/stock.rs")).unwrap(),
+            "// stock\n"
+        );
+        assert_eq!(
+            render_template("\u{FEFF}{{ name }}", "stock", "", &[]).unwrap(),
+            "stock"
+        );
+    }
+
+    #[test]
+    fn test_cargo_check_requires_a_crate() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = cargo_check(dir.path()).unwrap_err();
+        assert!(err.to_string().contains("no Cargo.toml found"));
+
+        let err = cargo_check(&dir.path().join("missing")).unwrap_err();
+        assert!(err.to_string().contains("does not exist"));
+    }
+
+    #[test]
+    fn test_ensure_clean_worktree() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = ensure_clean_worktree(dir.path()).unwrap_err();
+        assert!(err.to_string().contains("not inside a git repository"));
+
+        let status = std::process::Command::new("git")
+            .arg("-C")
+            .arg(dir.path())
+            .args(["init", "-q"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        ensure_clean_worktree(dir.path()).unwrap();
+
+        fs::write(dir.path().join("wip.rs"), "// in progress").unwrap();
+        let err = ensure_clean_worktree(dir.path()).unwrap_err();
+        assert!(err.to_string().contains("wip.rs"));
+    }
+
+    #[test]
+    fn test_output_path_uses_custom_vars() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "modules",
+            r#"{
+                "name": "modules",
+                "displayName": "Modules",
+                "description": "module from var",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{ module }}/{{ pascal_name | lower }}.rs", "layer": "main" }
+                ]
+            }"#,
+            &[("main.tmpl", "// {{ name }}")],
+        );
+
+        let mut options = test_options("stock_price", "modules", target.path());
+        options.vars = vec![("module".into(), "market".into())];
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        assert!(target.path().join("src/market/stockprice.rs").exists());
+    }
+
+    #[test]
+    fn test_render_inline_legacy_fallback() {
+        let mut context = tera::Context::new();
+        context.insert("name", "stock_price");
+        context.insert("pascal_name", "StockPrice");
+
+        assert_eq!(
+            render_inline("src/{{name}}_{{pascal_name}}.rs", &context).unwrap(),
+            "src/stock_price_StockPrice.rs"
+        );
+        // Teraが解釈できない文字列でも旧形式のプレースホルダーは置換される
+        assert_eq!(
+            render_inline("src/{{name}}{#.rs", &context).unwrap(),
+            "src/stock_price{#.rs"
+        );
+        // 警告は文字列ごとに1回だけ
+        assert!(!first_legacy_fallback("src/{{name}}{#.rs"));
+        assert!(first_legacy_fallback("src/{{pascal_name}}{#.rs"));
+        assert!(render_inline("src/{{ missing }}.rs", &context).is_err());
+    }
+
+    #[test]
+    fn test_render_template() {
+        let rendered = render_template(
+            "pub struct {{ pascal_name }}; // {{ description }} by {{ author }}, {{ name | table_name }}",
+            "stock price",
+            "prices",
+            &[("author".into(), "jane".into())],
+        )
+        .unwrap();
+        assert_eq!(
+            rendered,
+            "pub struct StockPrice; // prices by jane, stock_prices"
+        );
+
+        let err = render_template("{{ missing }}", "sample", "", &[]).unwrap_err();
+        assert!(err.to_string().contains("undefined variable `missing`"));
+    }
+
+    #[test]
+    fn test_name_case_variables() {
+        let snake_name = to_snake_case("stock price service");
+        let pascal_name = snake_name.to_pascal_case();
+        let context = build_context(&snake_name, &pascal_name, "desc", &[]).unwrap();
+        let get = |key: &str| context.get(key).unwrap().as_str().unwrap().to_string();
+        assert_eq!(get("name"), "stock_price_service");
+        assert_eq!(get("snake_name"), "stock_price_service");
+        assert_eq!(get("pascal_name"), "StockPriceService");
+        assert_eq!(get("kebab_name"), "stock-price-service");
+        assert_eq!(get("camel_name"), "stockPriceService");
+        assert_eq!(get("shouty_name"), "STOCK_PRICE_SERVICE");
+        assert_eq!(get("title_name"), "Stock Price Service");
+
+        let err = build_context(
+            &snake_name,
+            &pascal_name,
+            "desc",
+            &[("kebab_name".into(), "x".into())],
+        )
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("collides with a built-in variable"));
+    }
+
+    #[test]
+    fn test_render_next_steps() {
+        let context = build_context("stock_price", "StockPrice", "desc", &[]).unwrap();
+        let mut manifest = Manifest {
+            next_steps: vec![
+                "Register {{ pascal_name }}Adapter in your container".into(),
+                "Add `pub mod {{ name }};` to lib.rs".into(),
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            render_next_steps(&manifest, &context).unwrap(),
+            vec![
+                "Register StockPriceAdapter in your container",
+                "Add `pub mod stock_price;` to lib.rs",
+            ]
+        );
+
+        manifest.next_steps = vec!["Wire {{ missing }}".into()];
+        assert!(render_next_steps(&manifest, &context).is_err());
+    }
+
+    #[test]
+    fn test_remove_feature() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "modded",
+            r#"{
+                "name": "modded",
+                "displayName": "Modded",
+                "description": "registers modules",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/features/{{name}}.rs", "layer": "feature" }
+                ],
+                "mod_updates": [
+                    { "mod_file": "src/features/mod.rs", "line": "pub mod {{name}};" }
+                ]
+            }"#,
+            &[("main.tmpl", "// {{ name }}: {{ description }}")],
+        );
+        for name in ["alpha", "bravo"] {
+            let mut options = test_options(name, "modded", target.path());
+            options.update_mod = true;
+            scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        }
+
+        let remove_options = |force| RemoveOptions {
+            name: "alpha".into(),
+            description: "desc".into(),
+            archetype: "modded".into(),
+            target: target.path().to_path_buf(),
+            vars: Vec::new(),
+            force,
+            verbosity: Verbosity::Normal,
+            shared_templates_dir: None,
+        };
+
+        // 編集されたファイルは--forceなしでは削除しない
+        let alpha = target.path().join("src/features/alpha.rs");
+        fs::write(&alpha, "// hand-edited").unwrap();
+        let err = remove_feature(&[archetypes.path().into()], &remove_options(false)).unwrap_err();
+        assert!(err.to_string().contains("alpha.rs"));
+        assert!(alpha.exists());
+
+        remove_feature(&[archetypes.path().into()], &remove_options(true)).unwrap();
+        assert!(!alpha.exists());
+        assert!(target.path().join("src/features/bravo.rs").exists());
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/features/mod.rs")).unwrap(),
+            "pub mod bravo;\n"
+        );
+    }
+
+    #[test]
+    fn test_rename_feature() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "modded",
+            r#"{
+                "name": "modded",
+                "displayName": "modded",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/features/{{name}}.rs", "layer": "feature" },
+                    { "template": "helper.tmpl", "output": "src/helpers/{{name}}/helper.rs", "layer": "helper" }
+                ],
+                "mod_updates": [
+                    { "mod_file": "src/features/mod.rs", "line": "pub mod {{name}};" }
+                ]
+            }"#,
+            &[
+                (
+                    "main.tmpl",
+                    "pub struct {{ pascal_name }}; // {{ name }} {{ name }}_log",
+                ),
+                ("helper.tmpl", "// helper"),
+            ],
+        );
+        let mut options = plain_options("stok_price", "modded", target.path());
+        options.update_mod = true;
+        options.record = true;
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        let mut options = plain_options("other", "modded", target.path());
+        options.update_mod = true;
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+
+        let mut rename_options = RenameOptions {
+            old: "stok_price".into(),
+            new: "other".into(),
+            archetype: "modded".into(),
+            target: target.path().into(),
+            vars: Vec::new(),
+            rewrite_contents: true,
+            feature_dir: false,
+            verbosity: Verbosity::Quiet,
+        };
+        // 新しい名前のファイルが既にあれば何も変更しない
+        let err = rename_feature(&[archetypes.path().into()], &rename_options).unwrap_err();
+        assert!(err.to_string().contains("files for 'other' already exist"));
+        assert!(target.path().join("src/features/stok_price.rs").exists());
+
+        rename_options.new = "stock_price".into();
+        rename_feature(&[archetypes.path().into()], &rename_options).unwrap();
+        let read = |path: &str| fs::read_to_string(target.path().join(path)).unwrap();
+        // 旧名を先頭に含むだけの識別子（`stok_price_log`）は置き換えない
+        assert_eq!(
+            read("src/features/stock_price.rs"),
+            "pub struct StockPrice; // stock_price stok_price_log\n"
+        );
+        assert_eq!(read("src/helpers/stock_price/helper.rs"), "// helper\n");
+        assert!(!target.path().join("src/features/stok_price.rs").exists());
+        assert!(!target.path().join("src/helpers/stok_price").exists());
+        assert_eq!(
+            read("src/features/mod.rs"),
+            "pub mod other;\npub mod stock_price;\n"
+        );
+
+        // 生成記録も移り、移動後の内容が生成時の内容になる
+        assert!(!record_path(target.path(), "stok_price").exists());
+        let record = read_record(&record_path(target.path(), "stock_price"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.name, "stock_price");
+        assert_eq!(record.files[0].path, "src/features/stock_price.rs");
+        assert_eq!(
+            record.files[0].sha256,
+            content_hash("pub struct StockPrice; // stock_price stok_price_log\n")
+        );
+        assert_eq!(record.mod_lines[0].line, "pub mod stock_price;");
+    }
+
+    #[test]
+    fn test_remove_feature_uses_generation_record() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "modded",
+            r#"{
+                "name": "modded",
+                "displayName": "Modded",
+                "description": "registers modules",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/features/{{name}}.rs", "layer": "feature" }
+                ],
+                "mod_updates": [
+                    { "mod_file": "src/features/mod.rs", "line": "pub mod {{name}};" }
+                ]
+            }"#,
+            &[("main.tmpl", "// {{ name }}: {{ author }}")],
+        );
+        let mut options = plain_options("alpha", "modded", target.path());
+        options.update_mod = true;
+        options.record = true;
+        options.vars = vec![("author".into(), "jane".into())];
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+
+        let path = record_path(target.path(), "alpha");
+        let record = read_record(&path).unwrap().unwrap();
+        assert!(record.environment.contains_key("date"));
+        assert_eq!(
+            record,
+            GenerationRecord {
+                version: RECORD_VERSION,
+                name: "alpha".into(),
+                archetype: "modded".into(),
+                description: "desc".into(),
+                vars: BTreeMap::from([("author".into(), "jane".into())]),
+                files: vec![RecordedFile {
+                    path: "src/features/alpha.rs".into(),
+                    layer: "feature".into(),
+                    sha256: content_hash("// alpha: jane\n"),
+                }],
+                mod_lines: vec![RecordedModLine {
+                    mod_file: "src/features/mod.rs".into(),
+                    line: "pub mod alpha;".into(),
+                }],
+                feature_dir: false,
+                fmt: false,
+                environment: record.environment.clone(),
+            }
+        );
+
+        // 記録があれば生成時の説明や変数を渡さなくても削除できる
+        let remove_options = RemoveOptions {
+            name: "alpha".into(),
+            description: String::new(),
+            archetype: "unknown".into(),
+            target: target.path().to_path_buf(),
+            vars: Vec::new(),
+            force: false,
+            verbosity: Verbosity::Normal,
+            shared_templates_dir: None,
+        };
+        remove_feature(&[archetypes.path().into()], &remove_options).unwrap();
+        assert!(!target.path().join("src/features/alpha.rs").exists());
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/features/mod.rs")).unwrap(),
+            ""
+        );
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_newer_record_version_is_rejected() {
+        let target = tempfile::tempdir().unwrap();
+        let path = record_path(target.path(), "alpha");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(
+            &path,
+            r#"{ "version": 99, "name": "alpha", "archetype": "a", "description": "",
+                 "vars": {}, "files": [], "mod_lines": [] }"#,
+        )
+        .unwrap();
+        let err = read_record(&path).unwrap_err();
+        assert!(err.to_string().contains("version 99"));
+    }
+
+    #[test]
+    fn test_scaffold_only_layer() {
+        let archetypes = [Path::new(env!("CARGO_MANIFEST_DIR")).join("../archetypes")];
+        let target = tempfile::tempdir().unwrap();
+        init_project(target.path()).unwrap();
+
+        let mut options = test_options("stock_price", "rust_hexagonal", target.path());
+        options.update_mod = true;
+        options.only_layers = vec!["adapter".into()];
+        scaffold_feature(&archetypes, &options).unwrap();
+
+        let read = |path: &str| fs::read_to_string(target.path().join(path)).unwrap();
+        assert!(target
+            .path()
+            .join("src/adapters/stock_price_adapter.rs")
+            .exists());
+        assert!(!target.path().join("src/domain/stock_price.rs").exists());
+        assert!(!target.path().join("src/ports/stock_price_port.rs").exists());
+        assert_eq!(
+            read("src/adapters/mod.rs"),
+            "pub mod stock_price_adapter;\n"
+        );
+        assert_eq!(read("src/domain/mod.rs"), "");
+
+        options.only_layers = vec!["adapter".into(), "infra".into()];
+        let err = scaffold_feature(&archetypes, &options).unwrap_err();
+        assert!(err.to_string().contains("infra"));
+        assert!(err.to_string().contains("Available: adapter, domain, port"));
+    }
+
+    #[test]
+    fn test_existing_module_is_reported() {
+        let archetypes = [Path::new(env!("CARGO_MANIFEST_DIR")).join("../archetypes")];
+        let target = tempfile::tempdir().unwrap();
+        init_project(target.path()).unwrap();
+        fs::write(
+            target.path().join("src/domain/mod.rs"),
+            "pub mod stock_price;\n",
+        )
+        .unwrap();
+
+        let mut options = test_options("stock_price", "rust_hexagonal", target.path());
+        options.update_mod = true;
+        options.dry_run = true;
+        let report = scaffold_feature(&archetypes, &options).unwrap();
+        assert_eq!(
+            report.existing_modules,
+            vec![(
+                target.path().join("src/domain/mod.rs"),
+                "pub mod stock_price;".to_string()
+            )]
+        );
+
+        options.no_clobber_modules = true;
+        let err = scaffold_feature(&archetypes, &options).unwrap_err();
+        assert!(err.to_string().contains("module already registered"));
+        assert!(err.to_string().contains("pub mod stock_price;"));
+    }
+
+    #[test]
+    fn test_banner_by_file_type() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "bannered",
+            r#"{
+                "name": "bannered",
+                "displayName": "bannered",
+                "description": "",
+                "files": [
+                    {"template": "t.tmpl", "output": "src/{{name}}.rs", "layer": "main"},
+                    {"template": "c.tmpl", "output": "{{name}}.toml", "layer": "config"},
+                    {"template": "x.tmpl", "output": "{{name}}.xml", "layer": "view"},
+                    {"template": "own.tmpl", "output": "src/{{name}}_own.rs", "layer": "main"}
+                ]
+            }"#,
+            &[
+                ("t.tmpl", "pub struct {{ pascal_name }};"),
+                ("c.tmpl", "key = 1"),
+                ("x.tmpl", "<?xml version=\"1.0\"?>\n<root/>"),
+                ("own.tmpl", "// Generated by aegis-architect, do not edit"),
+            ],
+        );
+        let dirs = [archetypes.path().to_path_buf()];
+        let read = |path: &str| fs::read_to_string(target.path().join(path)).unwrap();
+
+        // 既定では.rsのみ
+        let mut options = test_options("alpha", "bannered", target.path());
+        scaffold_feature(&dirs, &options).unwrap();
+        assert_eq!(
+            read("src/alpha.rs"),
+            "// Generated by aegis-architect from archetype 'bannered' (file: t.tmpl). Edits may be overwritten.\npub struct Alpha;\n"
+        );
+        assert_eq!(read("alpha.toml"), "key = 1\n");
+        assert_eq!(
+            read("src/alpha_own.rs"),
+            "// Generated by aegis-architect, do not edit\n"
+        );
+
+        options.force = true;
+        options.banner = Some(true);
+        scaffold_feature(&dirs, &options).unwrap();
+        assert!(read("alpha.toml").starts_with("# Generated by aegis-architect"));
+        let xml = read("alpha.xml");
+        assert!(xml.starts_with("<?xml version=\"1.0\"?>\n<!-- Generated by aegis-architect"));
+        assert_eq!(strip_banner(&xml), "<?xml version=\"1.0\"?>\n<root/>\n");
+    }
+
+    #[test]
+    fn test_template_override() {
+        let archetypes = [Path::new(env!("CARGO_MANIFEST_DIR")).join("../archetypes")];
+        let target = tempfile::tempdir().unwrap();
+        let override_path = target.path().join("port.tmpl");
+        fs::write(&override_path, "// port of {{ pascal_name }}").unwrap();
+
+        let mut options = plain_options("stock", "rust_hexagonal", target.path());
+        options.template_overrides = vec![("port".into(), override_path.clone())];
+        scaffold_feature(&archetypes, &options).unwrap();
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/ports/stock_port.rs")).unwrap(),
+            "// port of Stock\n"
+        );
+        assert_ne!(
+            fs::read_to_string(target.path().join("src/domain/stock.rs")).unwrap(),
+            "// port of Stock\n"
+        );
+
+        // 出力はレンダリング後のパスで指定し、バナーには差し替えたテンプレートを相対パスで書く
+        options.force = true;
+        options.banner = None;
+        let domain_path = target.path().join("domain.tmpl");
+        fs::write(&domain_path, "pub struct {{ pascal_name }};").unwrap();
+        options.template_overrides = vec![("src/domain/stock.rs".into(), domain_path)];
+        scaffold_feature(&archetypes, &options).unwrap();
+        let domain = fs::read_to_string(target.path().join("src/domain/stock.rs")).unwrap();
+        assert!(domain.ends_with("pub struct Stock;\n"), "{}", domain);
+        assert!(domain.contains("(file: domain.tmpl)"), "{}", domain);
+        assert!(
+            !domain.contains(&*target.path().to_string_lossy()),
+            "{}",
+            domain
+        );
+        options.template_overrides = vec![("src/domain/{{name}}.rs".into(), override_path.clone())];
+        let err = scaffold_feature(&archetypes, &options).unwrap_err();
+        assert!(err.to_string().contains("matches no file"));
+        assert!(err.to_string().contains("src/domain/stock.rs"));
+
+        options.template_overrides = vec![("unknown".into(), override_path)];
+        let err = scaffold_feature(&archetypes, &options).unwrap_err();
+        assert!(err.to_string().contains("matches no file"));
+        options.template_overrides = vec![("domain".into(), target.path().join("gone.tmpl"))];
+        let err = scaffold_feature(&archetypes, &options).unwrap_err();
+        assert!(err.to_string().contains("Template override not found"));
+    }
+
+    #[test]
+    fn test_scaffold_report_json() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_single_file_archetype(archetypes.path());
+
+        let report = scaffold_feature(
+            &[archetypes.path().into()],
+            &test_options("sample", "single", target.path()),
+        )
+        .unwrap();
+        let json = report.to_json(target.path());
+        assert_eq!(json["name"], "sample");
+        assert_eq!(json["archetype"], "single");
+        assert_eq!(json["display_name"], "Single");
+        assert_eq!(json["total"], 1);
+        assert_eq!(json["generated"][0]["layer"], "main");
+        assert_eq!(json["generated"][0]["path"], "src/sample.rs");
+        let absolute = json["generated"][0]["absolute_path"].as_str().unwrap();
+        assert!(Path::new(absolute).is_absolute());
+        assert!(Path::new(absolute).ends_with("src/sample.rs"));
+        assert_eq!(json["mod_updates"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_add_deps_appends_missing_dependencies() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "deps",
+            r#"{
+                "name": "deps",
+                "displayName": "deps",
+                "description": "",
+                "dependencies": ["thiserror@1", "async-trait@0.1", "anyhow@1"],
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main" }
+                ]
+            }"#,
+            &[("main.tmpl", "// {{ name }}")],
+        );
+        let cargo_toml = target.path().join("Cargo.toml");
+        fs::write(
+            &cargo_toml,
+            "[package]\nname = \"app\"\n\n[dependencies]\n# errors\nthiserror = \"2\"\n",
+        )
+        .unwrap();
+        let dirs = [archetypes.path().to_path_buf()];
+        let mut options = test_options("stock", "deps", target.path());
+        options.add_deps = true;
+
+        let report = scaffold_feature(&dirs, &options).unwrap();
+        assert_eq!(
+            report.added_dependencies,
+            vec![(
+                cargo_toml.clone(),
+                vec!["async-trait".into(), "anyhow".into()]
+            )]
+        );
+        assert_eq!(
+            fs::read_to_string(&cargo_toml).unwrap(),
+            "[package]\nname = \"app\"\n\n[dependencies]\n# errors\nthiserror = \"2\"\nasync-trait = \"0.1\"\nanyhow = \"1\"\n"
+        );
+        assert_eq!(
+            dependency_snippet(&report.dependencies),
+            "[dependencies]\nthiserror = \"1\"\nasync-trait = \"0.1\"\nanyhow = \"1\"\n"
+        );
+
+        fs::remove_file(&cargo_toml).unwrap();
+        options.force = true;
+        let err = scaffold_feature(&dirs, &options).unwrap_err().to_string();
+        assert!(err.contains("no Cargo.toml found"), "{}", err);
+    }
+
+    #[test]
+    fn test_add_deps_targets_layer_crates_and_rolls_back() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "split",
+            r#"{
+                "name": "split",
+                "displayName": "split",
+                "description": "",
+                "dependencies": ["thiserror@1"],
+                "layer_crate": { "domain": "crates/core" },
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "domain" }
+                ]
+            }"#,
+            &[("main.tmpl", "// {{ name }}")],
+        );
+        let root_toml = target.path().join("Cargo.toml");
+        let core_toml = target.path().join("crates/core/Cargo.toml");
+        fs::create_dir_all(core_toml.parent().unwrap()).unwrap();
+        fs::write(&root_toml, "[workspace]\nmembers = [\"crates/core\"]\n").unwrap();
+        fs::write(&core_toml, "[package]\nname = \"core\"\n").unwrap();
+        let dirs = [archetypes.path().to_path_buf()];
+        let mut options = test_options("stock", "split", target.path());
+        options.add_deps = true;
+
+        // 生成記録を書けずに失敗したら、Cargo.tomlも元に戻す
+        fs::write(target.path().join(RECORD_DIR), "").unwrap();
+        options.record = true;
+        assert!(scaffold_feature(&dirs, &options).is_err());
+        assert_eq!(
+            fs::read_to_string(&core_toml).unwrap(),
+            "[package]\nname = \"core\"\n"
+        );
+        assert!(!target.path().join("crates/core/src/stock.rs").exists());
+
+        // レイヤーのクレートのCargo.tomlに追加し、ワークスペースのCargo.tomlは変えない
+        options.record = false;
+        let report = scaffold_feature(&dirs, &options).unwrap();
+        assert_eq!(
+            report.added_dependencies,
+            vec![(core_toml.clone(), vec!["thiserror".into()])]
+        );
+        assert_eq!(
+            fs::read_to_string(&core_toml).unwrap(),
+            "[package]\nname = \"core\"\n\n[dependencies]\nthiserror = \"1\"\n"
+        );
+        assert_eq!(
+            fs::read_to_string(&root_toml).unwrap(),
+            "[workspace]\nmembers = [\"crates/core\"]\n"
+        );
+    }
+
+    #[test]
+    fn test_dependencies_require_a_version() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "bare",
+            r#"{
+                "name": "bare",
+                "displayName": "bare",
+                "description": "",
+                "dependencies": ["anyhow"],
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main" }
+                ]
+            }"#,
+            &[("main.tmpl", "// {{ name }}")],
+        );
+        let cargo_toml = target.path().join("Cargo.toml");
+        fs::write(&cargo_toml, "[package]\nname = \"app\"\n").unwrap();
+        let dirs = [archetypes.path().to_path_buf()];
+        let mut options = test_options("stock", "bare", target.path());
+        options.add_deps = true;
+
+        let err = scaffold_feature(&dirs, &options).unwrap_err();
+        assert_eq!(ErrorKind::of(&err), ErrorKind::Validation);
+        assert!(err.to_string().contains("without a version"), "{}", err);
+        assert_eq!(
+            fs::read_to_string(&cargo_toml).unwrap(),
+            "[package]\nname = \"app\"\n"
+        );
+        let manifest = load_archetype(&dirs, "bare").unwrap();
+        assert!(validate_manifest(&manifest, &manifest.dir)
+            .iter()
+            .any(|problem| problem.contains("has no version")));
+    }
+
+    #[test]
+    fn test_merge_markers_replace_only_generated_regions() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "marked",
+            r#"{
+                "name": "marked",
+                "displayName": "marked",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main" }
+                ]
+            }"#,
+            &[(
+                "main.tmpl",
+                "// header\n// AEGIS:BEGIN generated\npub struct {{ pascal_name }};\n// AEGIS:END generated\n",
+            )],
+        );
+        let dirs = [archetypes.path().to_path_buf()];
+        let mut options = test_options("stock", "marked", target.path());
+        scaffold_feature(&dirs, &options).unwrap();
+
+        let path = target.path().join("src/stock.rs");
+        fs::write(
+            &path,
+            "use std::fmt;\n// AEGIS:BEGIN generated\nstale\n// AEGIS:END generated\nimpl Stock {}\n",
+        )
+        .unwrap();
+        assert!(scaffold_feature(&dirs, &options).is_err());
+
+        options.merge_markers = true;
+        let report = scaffold_feature(&dirs, &options).unwrap();
+        assert_eq!(report.generated.len(), 1);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "use std::fmt;\n// AEGIS:BEGIN generated\npub struct Stock;\n// AEGIS:END generated\nimpl Stock {}\n"
+        );
+
+        fs::write(&path, "// AEGIS:BEGIN generated\nstale\n").unwrap();
+        let err = scaffold_feature(&dirs, &options).unwrap_err().to_string();
+        assert!(err.contains("Unbalanced generated markers"), "{}", err);
+        assert!(err.contains("(line 1)"), "{}", err);
+    }
+
+    #[test]
+    fn test_rerun_skips_unchanged_files() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_single_file_archetype(archetypes.path());
+
+        let mut options = plain_options("stock", "single", target.path());
+        options.force = true;
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+
+        let path = target.path().join("src/stock.rs");
+        let report = scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        assert!(report.generated.is_empty());
+        assert_eq!(report.unchanged, vec![path.clone()]);
+
+        fs::write(&path, "// edited").unwrap();
+        let report = scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        assert_eq!(report.generated.len(), 1);
+        assert!(report.unchanged.is_empty());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "// stock\n");
+    }
+
+    /// rustfmtを起動できるか（ない環境では`--fmt`のテストを省略する）
+    fn rustfmt_installed() -> bool {
+        std::process::Command::new("rustfmt")
+            .arg("--version")
+            .output()
+            .is_ok_and(|output| output.status.success())
+    }
+
+    /// 整形で内容が変わるテンプレートを持つアーキタイプ
+    fn write_unformatted_archetype(dir: &Path) {
+        write_archetype(
+            dir,
+            "messy",
+            r#"{
+                "name": "messy",
+                "displayName": "messy",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main" }
+                ]
+            }"#,
+            &[("main.tmpl", "pub fn {{ name }}( )->u32{1}")],
+        );
+    }
+
+    #[test]
+    fn test_fmt_rerun_keeps_formatted_files_unchanged() {
+        if !rustfmt_installed() {
+            return;
+        }
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_unformatted_archetype(archetypes.path());
+        let dirs = [archetypes.path().to_path_buf()];
+        let mut options = plain_options("stock", "messy", target.path());
+        options.fmt = true;
+        options.force = true;
+
+        let report = scaffold_feature(&dirs, &options).unwrap();
+        assert_eq!(report.formatted[0].1, FormatOutcome::Formatted);
+        let path = target.path().join("src/stock.rs");
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "pub fn stock() -> u32 {\n    1\n}\n"
+        );
+
+        let report = scaffold_feature(&dirs, &options).unwrap();
+        assert!(report.generated.is_empty());
+        assert_eq!(report.unchanged, vec![path]);
+    }
+
+    #[test]
+    fn test_fmt_assert_unchanged_accepts_formatted_files() {
+        if !rustfmt_installed() {
+            return;
+        }
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_unformatted_archetype(archetypes.path());
+        let dirs = [archetypes.path().to_path_buf()];
+        let mut options = test_options("stock", "messy", target.path());
+        options.fmt = true;
+        scaffold_feature(&dirs, &options).unwrap();
+
+        options.assert_unchanged = true;
+        assert!(scaffold_feature(&dirs, &options).is_ok());
+    }
+
+    #[test]
+    fn test_fmt_record_round_trips_through_remove() {
+        if !rustfmt_installed() {
+            return;
+        }
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_unformatted_archetype(archetypes.path());
+        let dirs = [archetypes.path().to_path_buf()];
+        let mut options = test_options("stock", "messy", target.path());
+        options.fmt = true;
+        options.record = true;
+        scaffold_feature(&dirs, &options).unwrap();
+
+        let path = target.path().join("src/stock.rs");
+        let record = read_record(&record_path(target.path(), "stock"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            record.files[0].sha256,
+            content_hash(&fs::read_to_string(&path).unwrap())
+        );
+
+        // 整形した内容は記録と一致するので、--forceなしで削除できる
+        remove_feature(
+            &dirs,
+            &RemoveOptions {
+                name: "stock".into(),
+                description: String::new(),
+                archetype: "messy".into(),
+                target: target.path().to_path_buf(),
+                vars: Vec::new(),
+                force: false,
+                verbosity: Verbosity::Quiet,
+                shared_templates_dir: None,
+            },
+        )
+        .unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_assert_unchanged_reports_drift() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "single",
+            r#"{
+                "name": "single",
+                "displayName": "single",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/features/{{name}}.rs", "layer": "feature" }
+                ],
+                "mod_updates": [
+                    { "mod_file": "src/features/mod.rs", "line": "pub mod {{name}};" }
+                ]
+            }"#,
+            &[("main.tmpl", "// {{ name }}")],
+        );
+        let dirs = [archetypes.path().to_path_buf()];
+        let mut options = test_options("stock", "single", target.path());
+        options.update_mod = true;
+        options.assert_unchanged = true;
+
+        let err = scaffold_feature(&dirs, &options).unwrap_err().to_string();
+        assert!(err.contains("missing: "), "{}", err);
+        assert!(err.contains("missing mod line: "), "{}", err);
+        assert!(!target.path().join("src").exists());
+
+        options.assert_unchanged = false;
+        scaffold_feature(&dirs, &options).unwrap();
+        options.assert_unchanged = true;
+        let report = scaffold_feature(&dirs, &options).unwrap();
+        assert_eq!(report.unchanged.len(), 1);
+
+        fs::write(target.path().join("src/features/stock.rs"), "// edited").unwrap();
+        let err = scaffold_feature(&dirs, &options).unwrap_err().to_string();
+        assert!(err.contains("changed: "), "{}", err);
+        assert!(!err.contains("mod line"), "{}", err);
+    }
+
+    #[test]
+    fn test_seed_from_existing_feature() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "pair",
+            r#"{
+                "name": "pair",
+                "displayName": "pair",
+                "description": "",
+                "files": [
+                    { "template": "a.tmpl", "output": "src/{{name}}_a.rs", "layer": "a" },
+                    { "template": "b.tmpl", "output": "src/{{name}}_b.rs", "layer": "b" }
+                ]
+            }"#,
+            &[
+                ("a.tmpl", "pub struct {{ pascal_name }};"),
+                ("b.tmpl", "// {{ name }}"),
+            ],
+        );
+        let dirs = [archetypes.path().to_path_buf()];
+        scaffold_feature(&dirs, &plain_options("stock", "pair", target.path())).unwrap();
+        fs::write(
+            target.path().join("src/stock_a.rs"),
+            "pub struct Stock {\n    pub stock_price: u64,\n}\n\nfn stock() -> Stock {\n    Stock { stock_price: 0 }\n}\n",
+        )
+        .unwrap();
+
+        let mut options = plain_options("bond", "pair", target.path());
+        options.seed_from = Some("stock".into());
+        let report = scaffold_feature(&dirs, &options).unwrap();
+        assert_eq!(report.seeded, vec![target.path().join("src/bond_a.rs")]);
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/bond_a.rs")).unwrap(),
+            // 旧名を先頭に含むだけの識別子（`stock_price`）は置き換えない
+            "pub struct Bond {\n    pub stock_price: u64,\n}\n\nfn bond() -> Bond {\n    Bond { stock_price: 0 }\n}\n"
+        );
+        // 編集されていないファイルはテンプレートから生成する
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/bond_b.rs")).unwrap(),
+            "// bond\n"
+        );
+
+        options.name = "cash".into();
+        options.seed_from = Some("missing".into());
+        let err = scaffold_feature(&dirs, &options).unwrap_err();
+        assert!(err.to_string().contains("no generated files"), "{}", err);
+    }
+
+    #[test]
+    fn test_replace_identifier() {
+        assert_eq!(
+            replace_identifier(
+                "stock stock_price restock stock::x (stock)",
+                "stock",
+                "bond"
+            ),
+            "bond stock_price restock bond::x (bond)"
+        );
+        assert_eq!(
+            replace_identifier("StockPrice Stock", "Stock", "Bond"),
+            "StockPrice Bond"
+        );
+        assert_eq!(replace_identifier("", "stock", "bond"), "");
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "kinds",
+            r#"{"name": "kinds", "displayName": "kinds", "description": "", "files": [{"template": "t.tmpl", "output": "src/{{name}}.rs", "layer": "main"}]}"#,
+            &[("t.tmpl", "{{ missing }}")],
+        );
+        let dirs = [archetypes.path().to_path_buf()];
+        let kind = |options: &ScaffoldOptions| {
+            ErrorKind::of(&scaffold_feature(&dirs, options).unwrap_err())
+        };
+
+        assert_eq!(
+            kind(&test_options("alpha", "unknown", target.path())),
+            ErrorKind::ArchetypeNotFound
+        );
+        assert_eq!(
+            kind(&test_options("alpha", "kinds", target.path())),
+            ErrorKind::Render
+        );
+
+        fs::create_dir_all(target.path().join("src")).unwrap();
+        fs::write(target.path().join("src/alpha.rs"), "").unwrap();
+        assert_eq!(
+            kind(&test_options("alpha", "kinds", target.path())),
+            ErrorKind::Conflict
+        );
+
+        write_archetype(
+            archetypes.path(),
+            "broken",
+            r#"{"name": "broken", "displayName": "broken", "description": "", "files": [{"template": "gone.tmpl", "output": "src/x.rs", "layer": "main"}]}"#,
+            &[],
+        );
+        let err = validate_archetypes(&dirs, Some("broken"), None).unwrap_err();
+        assert_eq!(ErrorKind::of(&err), ErrorKind::Validation);
+        assert_eq!(ErrorKind::Conflict.exit_code(), 3);
+    }
+
+    #[test]
+    fn test_emit_prompt() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "prompted",
+            r#"{
+                "name": "prompted",
+                "displayName": "prompted",
+                "description": "",
+                "prompt_template": "prompt.md",
+                "files": [
+                    {"template": "t.tmpl", "output": "src/domain/{{name}}.rs", "layer": "domain"},
+                    {"template": "t.tmpl", "output": "src/ports/{{name}}_port.rs", "layer": "port"}
+                ]
+            }"#,
+            &[
+                ("t.tmpl", ""),
+                (
+                    "prompt.md",
+                    "Build {{ pascal_name }}: {{ description }}\n{% for file in files %}- {{ file.layer }}: {{ file.path }}\n{% endfor %}",
+                ),
+            ],
+        );
+
+        let options = test_options("stock", "prompted", target.path());
+        let prompt = emit_prompt(&[archetypes.path().into()], &options).unwrap();
+        assert_eq!(
+            prompt,
+            "Build Stock: desc\n- domain: src/domain/stock.rs\n- port: src/ports/stock_port.rs\n"
+        );
+        assert!(!target.path().join("src").exists());
+
+        let options = test_options("stock", "rust_hexagonal", target.path());
+        let archetypes = [Path::new(env!("CARGO_MANIFEST_DIR")).join("../archetypes")];
+        let err = emit_prompt(&archetypes, &options).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("does not declare a prompt_template"));
+    }
+
+    #[test]
+    fn test_autoescape_by_extension_and_override() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "web",
+            r#"{
+                "name": "web",
+                "displayName": "web",
+                "description": "",
+                "files": [
+                    {"template": "t.tmpl", "output": "src/{{name}}.rs", "layer": "main"},
+                    {"template": "t.tmpl", "output": "web/{{name}}.html", "layer": "view"},
+                    {"template": "t.tmpl", "output": "web/{{name}}.raw.xml", "layer": "view", "autoescape": false}
+                ]
+            }"#,
+            &[("t.tmpl", "{{ description }}")],
+        );
+        let mut options = plain_options("page", "web", target.path());
+        options.description = "<a & b>".into();
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+
+        let read = |path: &str| fs::read_to_string(target.path().join(path)).unwrap();
+        assert_eq!(read("src/page.rs"), "<a & b>\n");
+        assert_eq!(read("web/page.html"), "&lt;a &amp; b&gt;\n");
+        assert_eq!(read("web/page.raw.xml"), "<a & b>\n");
+    }
+
+    #[test]
+    fn test_prune_removes_files_dropped_from_manifest() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let manifest = |files: &str| {
+            format!(
+                r#"{{
+                    "name": "pruned",
+                    "displayName": "pruned",
+                    "description": "",
+                    "files": [{}],
+                    "mod_updates": [
+                        {{ "mod_file": "src/mod.rs", "line": "pub mod {{{{name}}}};" }},
+                        {{ "mod_file": "src/mod.rs", "line": "pub mod {{{{name}}}}_extra;", "layer": "extra" }}
+                    ]
+                }}"#,
+                files
+            )
+        };
+        let main = r#"{"template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main"}"#;
+        let extra =
+            r#"{"template": "main.tmpl", "output": "src/{{name}}_extra.rs", "layer": "extra"}"#;
+        write_archetype(
+            archetypes.path(),
+            "pruned",
+            &manifest(&format!("{}, {}", main, extra)),
+            &[("main.tmpl", "// {{ name }}")],
+        );
+        let mut options = test_options("alpha", "pruned", target.path());
+        options.update_mod = true;
+        options.record = true;
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+
+        // 新しい版のアーキタイプではextraのファイルとmod行を生成しない
+        write_archetype(
+            archetypes.path(),
+            "pruned",
+            &manifest(main).replace(
+                r#",
+                        { "mod_file": "src/mod.rs", "line": "pub mod {{name}}_extra;", "layer": "extra" }"#,
+                "",
+            ),
+            &[],
+        );
+        let mut prune_options = PruneOptions {
+            name: "alpha".into(),
+            target: target.path().to_path_buf(),
+            force: false,
+            verbosity: Verbosity::Quiet,
+        };
+        prune_feature(&[archetypes.path().into()], &prune_options).unwrap();
+        assert!(target.path().join("src/alpha_extra.rs").exists());
+
+        prune_options.force = true;
+        prune_feature(&[archetypes.path().into()], &prune_options).unwrap();
+        assert!(target.path().join("src/alpha.rs").exists());
+        assert!(!target.path().join("src/alpha_extra.rs").exists());
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/mod.rs")).unwrap(),
+            "pub mod alpha;\n"
+        );
+        let record = read_record(&record_path(target.path(), "alpha"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.files.len(), 1);
+        assert_eq!(record.mod_lines.len(), 1);
+    }
+
+    #[test]
+    fn test_describe_archetype_files() {
+        let archetypes = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "listing",
+            r#"{
+                "name": "listing",
+                "displayName": "listing",
+                "description": "",
+                "files": [
+                    {"template": "a.tmpl", "output": "src/{{name}}.rs", "layer": "domain"},
+                    {"template": "b.tmpl", "output": "src/{{item}}.rs", "layer": "port", "for_each": "items"}
+                ]
+            }"#,
+            &[("a.tmpl", "")],
+        );
+
+        let files = describe_archetype_files(
+            &[archetypes.path().to_path_buf()],
+            "listing",
+            "Example",
+            None,
+        )
+        .unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].resolved_output.as_deref(), Some("src/example.rs"));
+        assert!(files[0].template_exists);
+        assert_eq!(files[1].resolved_output, None);
+        assert!(!files[1].template_exists);
+        assert_eq!(files[1].layer, "port");
+    }
+
+    #[test]
+    fn test_scaffold_into_feature_dir() {
+        let archetypes = [Path::new(env!("CARGO_MANIFEST_DIR")).join("../archetypes")];
+        let target = tempfile::tempdir().unwrap();
+        init_project(target.path()).unwrap();
+        fs::create_dir_all(target.path().join("src/features")).unwrap();
+        fs::write(
+            target.path().join("src/features/mod.rs"),
+            "pub mod billing;\n",
+        )
+        .unwrap();
+
+        let mut options = test_options("stock_price", "rust_hexagonal", target.path());
+        options.update_mod = true;
+        options.feature_dir = true;
+        let report = scaffold_feature(&archetypes, &options).unwrap();
+
+        // src/からの相対パスを保って機能ディレクトリの下に生成する
+        let dir = target.path().join("src/features/stock_price");
+        for file in [
+            "domain/stock_price.rs",
+            "ports/stock_price_port.rs",
+            "adapters/stock_price_adapter.rs",
+        ] {
+            assert!(dir.join(file).exists(), "{} not generated", file);
+        }
+        assert!(!target.path().join("src/domain/stock_price.rs").exists());
+        assert_eq!(
+            fs::read_to_string(dir.join("mod.rs")).unwrap(),
+            "pub mod adapters;\npub mod domain;\npub mod ports;\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("domain/mod.rs")).unwrap(),
+            "pub mod stock_price;\n"
+        );
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/features/mod.rs")).unwrap(),
+            "pub mod billing;\npub mod stock_price;\n"
+        );
+        // 階層構成のmod.rsは更新しない
+        assert!(report.mod_updates.iter().all(|update| update
+            .mod_file
+            .starts_with(target.path().join("src/features"))));
+    }
+
+    #[test]
+    fn test_diff_feature_dir_feature() {
+        let archetypes = [Path::new(env!("CARGO_MANIFEST_DIR")).join("../archetypes")];
+        let target = tempfile::tempdir().unwrap();
+        init_project(target.path()).unwrap();
+        let mut options = test_options("stock_price", "rust_hexagonal", target.path());
+        options.update_mod = true;
+        options.feature_dir = true;
+        options.record = true;
+        scaffold_feature(&archetypes, &options).unwrap();
+
+        let diff_options = |name: &str, feature_dir: bool| DiffOptions {
+            name: name.into(),
+            description: "desc".into(),
+            archetype: "rust_hexagonal".into(),
+            target: target.path().to_path_buf(),
+            vars: Vec::new(),
+            shared_templates_dir: None,
+            feature_dir,
+        };
+        // 生成記録の`feature_dir`に従って機能ディレクトリの下と比べる
+        let summary = diff_feature(&archetypes, &diff_options("stock_price", false)).unwrap();
+        assert_eq!(summary.differing, 0);
+        assert!(summary.missing.is_empty(), "{:?}", summary.missing);
+        assert!(summary.gone.is_empty(), "{:?}", summary.gone);
+
+        // 記録がなければオプションで指定する
+        options.name = "bond_price".into();
+        options.record = false;
+        scaffold_feature(&archetypes, &options).unwrap();
+        let summary = diff_feature(&archetypes, &diff_options("bond_price", true)).unwrap();
+        assert!(summary.missing.is_empty(), "{:?}", summary.missing);
+        let summary = diff_feature(&archetypes, &diff_options("bond_price", false)).unwrap();
+        assert_eq!(summary.missing.len(), 3);
+    }
+
+    #[test]
+    fn test_rename_feature_dir_feature() {
+        let archetypes = [Path::new(env!("CARGO_MANIFEST_DIR")).join("../archetypes")];
+        let target = tempfile::tempdir().unwrap();
+        init_project(target.path()).unwrap();
+        fs::create_dir_all(target.path().join("src/features")).unwrap();
+        fs::write(
+            target.path().join("src/features/mod.rs"),
+            "pub mod billing;\n",
+        )
+        .unwrap();
+        let mut options = test_options("stock_price", "rust_hexagonal", target.path());
+        options.update_mod = true;
+        options.feature_dir = true;
+        options.record = true;
+        scaffold_feature(&archetypes, &options).unwrap();
+
+        rename_feature(
+            &archetypes,
+            &RenameOptions {
+                old: "stock_price".into(),
+                new: "stock_quote".into(),
+                archetype: "rust_hexagonal".into(),
+                target: target.path().to_path_buf(),
+                vars: Vec::new(),
+                rewrite_contents: true,
+                feature_dir: false,
+                verbosity: Verbosity::Quiet,
+            },
+        )
+        .unwrap();
+
+        let read = |path: &str| fs::read_to_string(target.path().join(path)).unwrap();
+        let dir = "src/features/stock_quote";
+        assert!(!target.path().join("src/features/stock_price").exists());
+        assert!(target
+            .path()
+            .join(dir)
+            .join("adapters/stock_quote_adapter.rs")
+            .is_file());
+        assert_eq!(
+            read(&format!("{dir}/mod.rs")),
+            "pub mod adapters;\npub mod domain;\npub mod ports;\n"
+        );
+        assert_eq!(
+            read(&format!("{dir}/domain/mod.rs")),
+            "pub mod stock_quote;\n"
+        );
+        assert_eq!(
+            read(&format!("{dir}/ports/mod.rs")),
+            "pub mod stock_quote_port;\n"
+        );
+        assert_eq!(
+            read("src/features/mod.rs"),
+            "pub mod billing;\npub mod stock_quote;\n"
+        );
+
+        // 記録も新しい機能ディレクトリを指し、差分も古い記録も残らない
+        let record = read_record(&record_path(target.path(), "stock_quote"))
+            .unwrap()
+            .unwrap();
+        assert!(record.feature_dir);
+        assert!(record.mod_lines.iter().any(|mod_line| mod_line.mod_file
+            == format!("{dir}/domain/mod.rs")
+            && mod_line.line == "pub mod stock_quote;"));
+        assert!(record
+            .mod_lines
+            .iter()
+            .all(|mod_line| !mod_line.mod_file.contains("stock_price")
+                && !mod_line.line.contains("stock_price")));
+        let summary = diff_feature(
+            &archetypes,
+            &DiffOptions {
+                name: "stock_quote".into(),
+                description: String::new(),
+                archetype: "rust_hexagonal".into(),
+                target: target.path().to_path_buf(),
+                vars: Vec::new(),
+                shared_templates_dir: None,
+                feature_dir: false,
+            },
+        )
+        .unwrap();
+        assert!(summary.missing.is_empty() && summary.gone.is_empty());
+    }
+
+    #[test]
+    fn test_prune_keeps_feature_dir_modules() {
+        let archetypes = [Path::new(env!("CARGO_MANIFEST_DIR")).join("../archetypes")];
+        let target = tempfile::tempdir().unwrap();
+        init_project(target.path()).unwrap();
+        let mut options = test_options("stock_price", "rust_hexagonal", target.path());
+        options.update_mod = true;
+        options.feature_dir = true;
+        options.record = true;
+        scaffold_feature(&archetypes, &options).unwrap();
+
+        prune_feature(
+            &archetypes,
+            &PruneOptions {
+                name: "stock_price".into(),
+                target: target.path().to_path_buf(),
+                force: true,
+                verbosity: Verbosity::Quiet,
+            },
+        )
+        .unwrap();
+        let dir = target.path().join("src/features/stock_price");
+        assert!(dir.join("domain/stock_price.rs").exists());
+        assert_eq!(
+            fs::read_to_string(dir.join("domain/mod.rs")).unwrap(),
+            "pub mod stock_price;\n"
+        );
+    }
+
+    #[test]
+    fn test_scaffold_skip_layer_and_patterns() {
+        let archetypes = [Path::new(env!("CARGO_MANIFEST_DIR")).join("../archetypes")];
+        let target = tempfile::tempdir().unwrap();
+        init_project(target.path()).unwrap();
+
+        let mut options = test_options("stock_price", "rust_hexagonal", target.path());
+        options.update_mod = true;
+        options.skip_layers = vec!["adapt*".into()];
+        scaffold_feature(&archetypes, &options).unwrap();
+        assert!(target.path().join("src/domain/stock_price.rs").exists());
+        assert!(target.path().join("src/ports/stock_price_port.rs").exists());
+        assert!(!target
+            .path()
+            .join("src/adapters/stock_price_adapter.rs")
+            .exists());
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/adapters/mod.rs")).unwrap(),
+            ""
+        );
+
+        // skipはonlyから除く
+        let filter = LayerFilter {
+            only: &["*".into()],
+            skip: &["port".into()],
+        };
+        assert!(filter.includes("domain"));
+        assert!(!filter.includes("port"));
+
+        // 何も選ばれない組み合わせはエラー
+        options.only_layers = vec!["domain".into()];
+        options.skip_layers = vec!["dom*".into()];
+        let err = scaffold_feature(&archetypes, &options).unwrap_err();
+        assert!(err.to_string().contains("select no files"));
+
+        options.only_layers = vec!["infra*".into()];
+        options.skip_layers = Vec::new();
+        let err = scaffold_feature(&archetypes, &options).unwrap_err();
+        assert!(err.to_string().contains("Unknown layer(s)"));
+    }
+
+    #[test]
+    fn test_select_mod_updates_without_layer() {
+        let context = build_context("alpha", "Alpha", "desc", &[]).unwrap();
+        let target = Path::new("/project");
+        let mod_update = |mod_file: &str| ModUpdate {
+            mod_file: mod_file.into(),
+            line: "pub mod {{name}};".into(),
+            layer: None,
+        };
+        let selected = select_mod_updates(
+            vec![
+                mod_update("src/domain/mod.rs"),
+                mod_update("src/adapters/mod.rs"),
+            ],
+            &LayerFilter {
+                only: &["adapter".into()],
+                skip: &[],
+            },
+            &[target.join("src/adapters/alpha_adapter.rs")],
+            target,
+            &context,
+        )
+        .unwrap();
+        let files: Vec<_> = selected.iter().map(|u| u.mod_file.as_str()).collect();
+        assert_eq!(files, ["src/adapters/mod.rs"]);
+    }
+
+    #[test]
+    fn test_manifest_read_error_names_the_path() {
+        let archetypes = tempfile::tempdir().unwrap();
+        // 読み込めないmanifest.json（ディレクトリ）
+        fs::create_dir_all(archetypes.path().join("broken/manifest.json")).unwrap();
+
+        let err = load_all_archetypes(&[archetypes.path().into()]).unwrap_err();
+        assert!(err.to_string().contains("Failed to read manifest"));
+        assert!(err.to_string().contains("broken"));
+    }
+
+    #[test]
+    fn test_order_files_by_depends_on() {
+        let spec = |output: &str, depends_on: &[&str]| FileSpec {
+            output: output.into(),
+            depends_on: depends_on.iter().map(|d| d.to_string()).collect(),
+            ..Default::default()
+        };
+        let outputs = |files: &[FileSpec]| -> Vec<String> {
+            order_files(files)
+                .unwrap()
+                .iter()
+                .map(|f| f.output.clone())
+                .collect()
+        };
+
+        let files = [
+            spec("adapter.rs", &["port.rs"]),
+            spec("domain.rs", &[]),
+            spec("port.rs", &["domain.rs"]),
+            spec("readme.md", &[]),
+        ];
+        assert_eq!(
+            outputs(&files),
+            ["domain.rs", "port.rs", "adapter.rs", "readme.md"]
+        );
+
+        let cycle = [
+            spec("a.rs", &["b.rs"]),
+            spec("b.rs", &["a.rs"]),
+            spec("c.rs", &[]),
+        ];
+        let err = order_files(&cycle).unwrap_err();
+        assert_eq!(err.to_string(), "Circular depends_on among: a.rs, b.rs");
+
+        let unknown = [spec("a.rs", &["missing.rs"])];
+        assert!(order_files(&unknown)
+            .unwrap_err()
+            .to_string()
+            .contains("unknown output 'missing.rs'"));
+    }
+
+    #[test]
+    fn test_load_config_from_ancestor() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(
+            root.path().join(CONFIG_FILE_NAME),
+            "default_archetype = \"rust_cli_simple\"\ndefault_target = \"crates/foo\"\n",
+        )
+        .unwrap();
+        let nested = root.path().join("a/b");
+        fs::create_dir_all(&nested).unwrap();
+
+        let config = load_config_from(&nested).unwrap();
+        assert_eq!(config.archetype(None), "rust_cli_simple");
+        assert_eq!(config.target(None), root.path().join("crates/foo"));
+        assert!(config.archetypes_dir.is_none());
+
+        // CLI指定が優先
+        assert_eq!(
+            config.archetype(Some("rust_hexagonal".into())),
+            "rust_hexagonal"
+        );
+        assert_eq!(config.target(Some(PathBuf::from("x"))), PathBuf::from("x"));
+
+        let defaults = Config::default();
+        assert_eq!(defaults.archetype(None), DEFAULT_ARCHETYPE);
+        assert_eq!(defaults.target(None), PathBuf::from("."));
+    }
+
+    #[test]
+    fn test_load_explicit_config_file() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("ci/aegis.toml");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(
+            &path,
+            "archetypes_dir = \"archetypes\"\ncolor = false\nverbosity = \"quiet\"\n",
+        )
+        .unwrap();
+
+        let config = load_config_file(&path).unwrap();
+        assert_eq!(
+            config.archetypes_dir,
+            Some(root.path().join("ci/archetypes"))
+        );
+        assert_eq!(config.color, Some(false));
+        assert_eq!(config.verbosity(false, false), Verbosity::Quiet);
+        assert_eq!(config.verbosity(false, true), Verbosity::Verbose);
+        assert_eq!(Config::default().verbosity(false, false), Verbosity::Normal);
+
+        let err = load_config_file(&root.path().join("missing.toml")).unwrap_err();
+        assert!(err.to_string().contains("Config file not found"));
+    }
+
+    #[test]
+    fn test_layer_layout_is_enforced() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "layered",
+            r#"{
+                "name": "layered",
+                "displayName": "Layered",
+                "description": "adapter in the wrong place",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/domain/{{name}}.rs", "layer": "domain" },
+                    { "template": "main.tmpl", "output": "src/domain_extra/{{name}}_adapter.rs", "layer": "adapter" }
+                ],
+                "layer_roots": { "domain": "src/domain", "adapter": "src/adapters" }
+            }"#,
+            &[("main.tmpl", "// {{ name }}")],
+        );
+
+        let err = scaffold_feature(
+            &[archetypes.path().into()],
+            &test_options("sample", "layered", target.path()),
+        )
+        .unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("src/domain_extra/sample_adapter.rs"));
+        assert!(!message.contains("[domain]"));
+        assert!(!target.path().join("src").exists());
+    }
+
+    #[test]
+    fn test_diagnose_environment() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let project = tempfile::tempdir().unwrap();
+        write_single_file_archetype(archetypes.path());
+        let status = |checks: &[DoctorCheck], name: &str| {
+            checks
+                .iter()
+                .find(|check| check.name == name)
+                .map(|check| check.status)
+        };
+
+        let checks = diagnose(&[archetypes.path().into()], project.path(), None);
+        assert_eq!(status(&checks, "archetypes"), Some(CheckStatus::Pass));
+        assert_eq!(status(&checks, "rust project"), Some(CheckStatus::Warn));
+
+        fs::write(project.path().join("Cargo.toml"), "[package]\n").unwrap();
+        fs::write(archetypes.path().join("single/manifest.json"), "{").unwrap();
+        let checks = diagnose(&[archetypes.path().into()], project.path(), None);
+        assert_eq!(status(&checks, "archetypes"), Some(CheckStatus::Fail));
+        assert_eq!(status(&checks, "rust project"), Some(CheckStatus::Pass));
+
+        let missing = archetypes.path().join("missing");
+        let checks = diagnose(&[missing], project.path(), None);
+        assert_eq!(
+            status(&checks, "archetypes directory"),
+            Some(CheckStatus::Fail)
+        );
+        assert_eq!(status(&checks, "archetypes"), None);
+    }
+
+    #[test]
+    fn test_unknown_archetype_suggests_closest_name() {
+        let archetypes = [Path::new(env!("CARGO_MANIFEST_DIR")).join("../archetypes")];
+        let err = load_archetype(&archetypes, "rust_hexagnal")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Did you mean 'rust_hexagonal'?"), "{}", err);
+        assert!(
+            err.contains("Available: rust_cli_simple, rust_hexagonal"),
+            "{}",
+            err
+        );
+
+        let err = load_archetype(&archetypes, "hx").unwrap_err().to_string();
+        assert!(err.contains("Did you mean 'hex'?"), "{}", err);
+
+        let err = load_archetype(&archetypes, "python_django")
+            .unwrap_err()
+            .to_string();
+        assert!(!err.contains("Did you mean"), "{}", err);
+    }
+
+    #[test]
+    fn test_unknown_archetype_is_reported_despite_broken_manifests() {
+        let archetypes = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "rust_hexagonal",
+            r#"{ "name": "rust_hexagonal", "displayName": "Hex", "description": "", "files": [] }"#,
+            &[],
+        );
+        write_archetype(archetypes.path(), "broken", r#"{ "name": "broken" "#, &[]);
+        let dirs = [archetypes.path().to_path_buf()];
+
+        let err = load_archetype(&dirs, "rust_hexagnal").unwrap_err();
+        assert_eq!(ErrorKind::of(&err), ErrorKind::ArchetypeNotFound);
+        let message = err.to_string();
+        assert!(
+            message.contains("Did you mean 'rust_hexagonal'?"),
+            "{}",
+            message
+        );
+        assert!(message.contains("Available: rust_hexagonal"), "{}", message);
+    }
+
+    #[test]
+    fn test_missing_required_manifest_fields_are_reported() {
+        let archetypes = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "nameless",
+            r#"{ "name": "nameless", "description": "", "files": [] }"#,
+            &[],
+        );
+        let err = load_archetype(&[archetypes.path().to_path_buf()], "nameless").unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("missing field `displayName`"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_archetype_aliases() {
+        let archetypes = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "rust_hexagonal_lite",
+            r#"{ "name": "rust_hexagonal_lite", "displayName": "rust_hexagonal_lite", "description": "", "aliases": ["hex", "shared"], "files": [] }"#,
+            &[],
+        );
+        write_archetype(
+            archetypes.path(),
+            "rust_cli_simple",
+            r#"{ "name": "rust_cli_simple", "displayName": "rust_cli_simple", "description": "", "aliases": ["shared"], "files": [] }"#,
+            &[],
+        );
+        write_archetype(
+            archetypes.path(),
+            "child",
+            r#"{ "name": "child", "displayName": "child", "description": "", "extends": "hex", "files": [] }"#,
+            &[],
+        );
+        let dirs = [archetypes.path().to_path_buf()];
+
+        assert_eq!(
+            load_archetype(&dirs, "hex").unwrap().name,
+            "rust_hexagonal_lite"
+        );
+        let child = load_archetype(&dirs, "child").unwrap();
+        assert!(child.aliases.is_empty());
+        let err = load_archetype(&dirs, "shared").unwrap_err().to_string();
+        assert!(err.contains("ambiguous"), "{}", err);
+        assert!(
+            err.contains("rust_cli_simple, rust_hexagonal_lite"),
+            "{}",
+            err
+        );
+        assert!(validate_archetypes(&dirs, None, None).is_err());
+    }
+
+    #[test]
+    fn test_min_version_refuses_newer_requirement() {
+        let archetypes = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "future",
+            r#"{ "name": "future", "displayName": "future", "description": "", "min_version": "999.0.0", "files": [] }"#,
+            &[],
+        );
+        write_archetype(
+            archetypes.path(),
+            "child",
+            r#"{ "name": "child", "displayName": "child", "description": "", "extends": "future", "files": [] }"#,
+            &[],
+        );
+        write_archetype(
+            archetypes.path(),
+            "current",
+            &format!(
+                r#"{{ "name": "current", "displayName": "current", "description": "", "min_version": "{}", "files": [] }}"#,
+                env!("CARGO_PKG_VERSION")
+            ),
+            &[],
+        );
+        let dirs = [archetypes.path().to_path_buf()];
+
+        let err = load_archetype(&dirs, "future").unwrap_err();
+        assert!(format!("{:#}", err).contains("upgrade aegis-architect to >= 999.0.0"));
+        assert!(load_archetype(&dirs, "child").is_err());
+        assert!(load_archetype(&dirs, "current").is_ok());
+
+        let manifest = Manifest {
+            min_version: Some("0.x".into()),
+            ..load_archetype(&dirs, "current").unwrap()
+        };
+        assert!(check_min_version(&manifest, "0.1.0").is_err());
+    }
+
+    #[test]
+    fn test_layers_fan_out_to_crates() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "workspace",
+            r#"{
+                "name": "workspace",
+                "displayName": "workspace",
+                "description": "",
+                "files": [
+                    { "template": "domain.tmpl", "output": "src/domain/{{name}}.rs", "layer": "domain" },
+                    { "template": "adapter.tmpl", "output": "src/adapters/{{name}}.rs", "layer": "adapter" }
+                ],
+                "layer_roots": { "domain": "src/domain", "adapter": "src/adapters" },
+                "layer_crate": { "domain": "crates/core", "adapter": "crates/infra/" },
+                "mod_updates": [
+                    { "mod_file": "src/domain/mod.rs", "line": "pub mod {{name}};", "layer": "domain" },
+                    { "mod_file": "src/adapters/mod.rs", "line": "pub mod {{name}};", "layer": "adapter" }
+                ]
+            }"#,
+            &[("domain.tmpl", "// domain"), ("adapter.tmpl", "// adapter")],
+        );
+
+        let mut options = test_options("stock", "workspace", target.path());
+        options.update_mod = true;
+        scaffold_feature(&[archetypes.path().into()], &options).unwrap();
+        for path in [
+            "crates/core/src/domain/stock.rs",
+            "crates/infra/src/adapters/stock.rs",
+        ] {
+            assert!(target.path().join(path).is_file(), "{}", path);
+        }
+        for mod_file in [
+            "crates/core/src/domain/mod.rs",
+            "crates/infra/src/adapters/mod.rs",
+        ] {
+            assert_eq!(
+                fs::read_to_string(target.path().join(mod_file)).unwrap(),
+                "pub mod stock;\n"
+            );
+        }
+        assert!(!target.path().join("src").exists());
+    }
+
+    #[test]
+    fn test_templates_share_partials_and_macros() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "shared",
+            r#"{
+                "name": "shared",
+                "displayName": "Shared",
+                "description": "uses a partial and a macro",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main" }
+                ]
+            }"#,
+            &[
+                (
+                    "main.tmpl",
+                    "{% import \"macros.tera\" as m %}{% include \"header.tera\" %}{{ m::greet(who=pascal_name) }}",
+                ),
+                ("header.tera", "// header\n"),
+                ("macros.tera", "{% macro greet(who) %}// hello {{ who }}{% endmacro %}"),
+            ],
+        );
+
+        scaffold_feature(
+            &[archetypes.path().into()],
+            &plain_options("stock_price", "shared", target.path()),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/stock_price.rs")).unwrap(),
+            "// header\n// hello StockPrice\n"
+        );
+        // パーシャルは出力されない
+        assert!(!target.path().join("src/header.tera").exists());
+    }
+
+    #[test]
+    fn test_nested_partials_are_registered_but_not_emitted() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "licensed",
+            r#"{
+                "name": "licensed",
+                "displayName": "Licensed",
+                "description": "license banner partial",
+                "files": [
+                    { "template": "main.rs.tera", "output": "src/{{name}}.rs", "layer": "main" }
+                ]
+            }"#,
+            &[(
+                "main.rs.tera",
+                "{% include \"partials/_header.tera\" %}pub struct {{ pascal_name }};\n",
+            )],
+        );
+        let partials_dir = archetypes.path().join("licensed/partials");
+        fs::create_dir_all(&partials_dir).unwrap();
+        fs::write(
+            partials_dir.join("_header.tera"),
+            "// SPDX-License-Identifier: MIT\n",
+        )
+        .unwrap();
+
+        let mut partials = find_partials(&archetypes.path().join("licensed")).unwrap();
+        partials.sort();
+        assert_eq!(partials, ["main.rs.tera", "partials/_header.tera"]);
+
+        scaffold_feature(
+            &[archetypes.path().into()],
+            &plain_options("stock_price", "licensed", target.path()),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/stock_price.rs")).unwrap(),
+            "// SPDX-License-Identifier: MIT\npub struct StockPrice;\n"
+        );
+        assert_eq!(fs::read_dir(target.path().join("src")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_pluralize() {
+        assert_eq!(pluralize("user"), "users");
+        assert_eq!(pluralize("Category"), "Categories");
+        assert_eq!(pluralize("day"), "days");
+        assert_eq!(pluralize("status"), "statuses");
+        assert_eq!(pluralize("box"), "boxes");
+        assert_eq!(pluralize("quiz"), "quizzes");
+        assert_eq!(pluralize("Quiz"), "Quizzes");
+        assert_eq!(pluralize("fez"), "fezzes");
+        assert_eq!(pluralize("buzz"), "buzzes");
+        assert_eq!(pluralize("waltz"), "waltzes");
+        assert_eq!(pluralize("topaz"), "topazes");
+        assert_eq!(pluralize("match"), "matches");
+        assert_eq!(pluralize("wish"), "wishes");
+    }
+
+    #[test]
+    fn test_singularize() {
+        assert_eq!(singularize("users"), "user");
+        assert_eq!(singularize("Categories"), "Category");
+        assert_eq!(singularize("days"), "day");
+        assert_eq!(singularize("boxes"), "box");
+        assert_eq!(singularize("matches"), "match");
+        assert_eq!(singularize("wishes"), "wish");
+        assert_eq!(singularize("status"), "status");
+        assert_eq!(singularize("class"), "class");
+    }
+
+    #[test]
+    fn test_table_name_filter() {
+        assert_eq!(table_name("StockPrice"), "stock_prices");
+        assert_eq!(table_name("Category"), "categories");
+
+        let mut context = tera::Context::new();
+        context.insert("pascal_name", "StockPrice");
+        let rendered = new_tera()
+            .render_str(
+                "{{ pascal_name | table_name }} {{ pascal_name | pluralize }} {{ 'Users' | singularize }}",
+                &context,
+            )
+            .unwrap();
+        assert_eq!(rendered, "stock_prices StockPrices User");
+    }
+
+    #[test]
+    fn test_undefined_variable_is_reported() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "typo",
+            r#"{
+                "name": "typo",
+                "displayName": "Typo",
+                "description": "misspelled variable",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main" }
+                ]
+            }"#,
+            &[("main.tmpl", "pub mod {{ nam }};")],
+        );
+
+        let err = scaffold_feature(
+            &[archetypes.path().into()],
+            &test_options("sample", "typo", target.path()),
+        )
+        .unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("main.tmpl"), "{}", message);
+        assert!(message.contains("`nam`"), "{}", message);
+        assert!(!target.path().join("src/sample.rs").exists());
+    }
+
+    #[test]
+    fn test_referenced_variables() {
+        let referenced = referenced_variables(
+            "{% set greeting = 'hi' %}{{ greeting }} {{ name }} {{ entity.fields | length }}\n\
+             {% for f in entity.fields %}{{ f.name }}{{ loop.index }}{% endfor %}\n\
+             {% if license is defined and license %}{{ license }}{% endif %}\n\
+             {{ author | default(value=owner) }}",
+        )
+        .unwrap();
+        let referenced: Vec<&str> = referenced.iter().map(String::as_str).collect();
+        assert_eq!(referenced, vec!["entity", "name", "owner"]);
+        assert!(referenced_variables("{{ unclosed").is_err());
+    }
+
+    #[test]
+    fn test_required_and_undeclared_vars() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "vars",
+            r#"{
+                "name": "vars",
+                "displayName": "Vars",
+                "description": "declares its inputs",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main" }
+                ],
+                "default_vars": { "license": "MIT" },
+                "required_vars": ["table"]
+            }"#,
+            &[("main.tmpl", "// {{ table }} {{ license }} {{ author }}")],
+        );
+        let dirs = [archetypes.path().to_path_buf()];
+
+        let manifest = load_archetype(&dirs, "vars").unwrap();
+        let problems = validate_manifest(&manifest, &manifest.dir);
+        assert_eq!(
+            problems,
+            vec!["template main.tmpl uses variable(s) not declared in default_vars or required_vars: author"]
+        );
+
+        let mut options = plain_options("sample", "vars", target.path());
+        options.vars = vec![("author".into(), "Jane".into())];
+        let err = scaffold_feature(&dirs, &options).unwrap_err();
+        assert!(err.to_string().contains("not provided: table"), "{}", err);
+
+        options.vars.push(("table".into(), "users".into()));
+        options.check_vars = true;
+        let err = scaffold_feature(&dirs, &options).unwrap_err();
+        assert_eq!(ErrorKind::of(&err), ErrorKind::Validation);
+        assert!(!target.path().join("src/sample.rs").exists());
+
+        options.check_vars = false;
+        scaffold_feature(&dirs, &options).unwrap();
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/sample.rs")).unwrap(),
+            "// users MIT Jane\n"
+        );
+    }
+
+    #[test]
+    fn test_environment_vars() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+
+        let environment = environment_vars(Path::new("."));
+        let mut context = build_context("sample", "Sample", "", &[]).unwrap();
+        assert!(context.get("date").is_none());
+        insert_environment_vars(&mut context, &environment);
+        let date = context.get("date").and_then(|v| v.as_str()).unwrap();
+        let parts: Vec<u32> = date.split('-').map(|part| part.parse().unwrap()).collect();
+        assert_eq!(date.len(), 10, "{}", date);
+        assert!((1..=12).contains(&parts[1]) && (1..=31).contains(&parts[2]));
+        assert_eq!(context.get("year"), Some(&serde_json::json!(parts[0])));
+
+        // --varで上書きできる
+        let mut context = build_context(
+            "sample",
+            "Sample",
+            "",
+            &[("date".into(), "2000-01-01".into())],
+        )
+        .unwrap();
+        insert_environment_vars(&mut context, &environment);
+        assert_eq!(context.get("date"), Some(&serde_json::json!("2000-01-01")));
+    }
+
+    #[test]
+    fn test_environment_vars_reads_git_config_of_target() {
+        let target = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            std::process::Command::new("git")
+                .arg("-C")
+                .arg(target.path())
+                .args(args)
+                .output()
+                .is_ok_and(|output| output.status.success())
+        };
+        if !git(&["init", "-q"]) {
+            return;
+        }
+        assert!(git(&["config", "user.name", "Target Author"]));
+        assert!(git(&["config", "user.email", "author@example.com"]));
+
+        let environment = environment_vars(&target.path().join("not/yet/created"));
+        assert_eq!(
+            environment.get("git_author_name"),
+            Some(&serde_json::json!("Target Author"))
+        );
+        assert_eq!(
+            environment.get("git_author_email"),
+            Some(&serde_json::json!("author@example.com"))
+        );
+    }
+
+    #[test]
+    fn test_rerender_reuses_recorded_environment() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "dated",
+            r#"{
+                "name": "dated",
+                "displayName": "Dated",
+                "description": "",
+                "files": [
+                    { "template": "file.rs.tera", "output": "src/{{ name }}_{{ year }}.rs", "layer": "domain" }
+                ]
+            }"#,
+            &[("file.rs.tera", "// {{ date }}\n")],
+        );
+        let dirs = [archetypes.path().to_path_buf()];
+        let mut options = test_options("stock", "dated", target.path());
+        options.record = true;
+        scaffold_feature(&dirs, &options).unwrap();
+
+        // 生成した年が過ぎた後の再レンダリングを、記録の値を書き換えて再現する
+        let path = record_path(target.path(), "stock");
+        let mut record = read_record(&path).unwrap().unwrap();
+        let year = record.environment["year"].clone();
+        let generated = target.path().join(format!("src/stock_{}.rs", year));
+        let old = target.path().join("src/stock_1999.rs");
+        fs::rename(&generated, &old).unwrap();
+        record.environment.insert("year".into(), 1999.into());
+        record.files[0].path = "src/stock_1999.rs".into();
+        fs::write(&path, serde_json::to_string_pretty(&record).unwrap()).unwrap();
+        assert_eq!(
+            recorded_environment(Some(&record), target.path())["year"],
+            serde_json::json!(1999)
+        );
+
+        // 記録した年で出力パスを解決するので、生成したファイルは削除対象にならない
+        prune_feature(
+            &dirs,
+            &PruneOptions {
+                name: "stock".into(),
+                target: target.path().to_path_buf(),
+                force: true,
+                verbosity: Verbosity::Normal,
+            },
+        )
+        .unwrap();
+        assert!(old.exists());
+    }
+
+    #[test]
+    fn test_init_project_skips_existing_files() {
+        let target = tempfile::tempdir().unwrap();
+        let domain_mod = target.path().join("src/domain/mod.rs");
+        fs::create_dir_all(domain_mod.parent().unwrap()).unwrap();
+        fs::write(&domain_mod, "pub mod existing;\n").unwrap();
+
+        init_project(target.path()).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/lib.rs")).unwrap(),
+            "pub mod domain;\npub mod ports;\npub mod adapters;\n"
+        );
+        assert_eq!(
+            fs::read_to_string(&domain_mod).unwrap(),
+            "pub mod existing;\n"
+        );
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/ports/mod.rs")).unwrap(),
+            ""
+        );
+        assert!(target.path().join("src/adapters/mod.rs").exists());
+    }
+
+    #[test]
+    fn test_archetype_extends_parent() {
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_archetype(
+            archetypes.path(),
+            "base",
+            r#"{
+                "name": "base",
+                "displayName": "Base",
+                "description": "parent archetype",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main" },
+                    { "template": "test.tmpl", "output": "tests/{{name}}.rs", "layer": "test" }
+                ]
+            }"#,
+            &[
+                (
+                    "main.tmpl",
+                    "{% include \"header.tera\" %}// base {{ name }}",
+                ),
+                ("test.tmpl", "// base test"),
+                ("header.tera", "// base header\n"),
+            ],
+        );
+        write_archetype(
+            archetypes.path(),
+            "child",
+            r#"{
+                "name": "child",
+                "displayName": "child",
+                "description": "",
+                "extends": "base",
+                "files": [
+                    { "template": "test.tmpl", "output": "tests/{{name}}.rs", "layer": "test" },
+                    { "template": "bench.tmpl", "output": "benches/{{name}}.rs", "layer": "bench" }
+                ]
+            }"#,
+            &[
+                ("test.tmpl", "// child test"),
+                ("bench.tmpl", "// child bench"),
+                ("header.tera", "// child header\n"),
+            ],
+        );
+
+        let manifest = load_archetype(&[archetypes.path().into()], "child").unwrap();
+        assert_eq!(manifest.description, "parent archetype");
+        assert_eq!(manifest.files.len(), 3);
+        assert_eq!(
+            manifest.source_dirs(),
+            vec![
+                archetypes.path().join("base"),
+                archetypes.path().join("child")
+            ]
+        );
+
+        scaffold_feature(
+            &[archetypes.path().into()],
+            &plain_options("sample", "child", target.path()),
+        )
+        .unwrap();
+        let read = |path: &str| fs::read_to_string(target.path().join(path)).unwrap();
+        // 親から継承したテンプレートでも、パーシャルは子のものが優先される
+        assert_eq!(read("src/sample.rs"), "// child header\n// base sample\n");
+        assert_eq!(read("tests/sample.rs"), "// child test\n");
+        assert_eq!(read("benches/sample.rs"), "// child bench\n");
+
+        write_archetype(
+            archetypes.path(),
+            "base",
+            r#"{ "name": "base", "displayName": "base", "description": "", "extends": "child", "files": [] }"#,
+            &[],
+        );
+        let err = load_archetype(&[archetypes.path().into()], "child").unwrap_err();
+        assert!(format!("{:#}", err).contains("child -> base -> child"));
+    }
+
+    #[test]
+    fn test_verbosity_from_flags() {
+        assert_eq!(Verbosity::from_flags(false, false), Verbosity::Normal);
+        assert_eq!(Verbosity::from_flags(false, true), Verbosity::Verbose);
+        assert_eq!(Verbosity::from_flags(true, false), Verbosity::Quiet);
+        assert!(Verbosity::Quiet < Verbosity::Normal && Verbosity::Normal < Verbosity::Verbose);
+    }
+
+    #[test]
+    fn test_validate_feature_name() {
+        assert!(validate_feature_name("stock_price").is_ok());
+        assert!(validate_feature_name("version_2").is_ok());
+
+        let err = validate_feature_name(&to_snake_case("2fa-login")).unwrap_err();
+        assert!(err.to_string().contains("cannot start with a digit"));
+        assert!(err.to_string().contains("feature_2fa_login"));
+
+        for keyword in ["type", "match", "async"] {
+            let err = validate_feature_name(keyword).unwrap_err();
+            assert!(
+                err.to_string().contains("reserved Rust keyword"),
+                "{}",
+                keyword
+            );
+        }
+
+        assert!(validate_feature_name("").is_err());
+        assert!(validate_feature_name(&to_snake_case("--")).is_err());
+
+        let err = validate_feature_name("café").unwrap_err();
+        assert!(err.to_string().contains("Try 'caf_'"));
+    }
+
+    #[test]
+    fn test_find_workspace_member() {
+        let root = tempfile::tempdir().unwrap();
+        let write = |path: &str, content: &str| {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+        write(
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"crates/*\", \"tools/cli\"]\nexclude = [\"crates/legacy\"]\n",
+        );
+        write(
+            "crates/my-service/Cargo.toml",
+            "[package]\nname = \"my-service\"\n",
+        );
+        write("crates/legacy/Cargo.toml", "[package]\nname = \"legacy\"\n");
+        write("tools/cli/Cargo.toml", "[package]\nname = \"my-cli\"\n");
+
+        // メンバーの配下からでもルートのワークスペースを見つける
+        let start = root.path().join("tools/cli");
+        assert_eq!(
+            find_workspace_member(&start, "my-service").unwrap(),
+            root.path().join("crates/my-service")
+        );
+        assert_eq!(
+            find_workspace_member(root.path(), "my-cli").unwrap(),
+            root.path().join("tools/cli")
+        );
+
+        let err = find_workspace_member(root.path(), "legacy").unwrap_err();
+        assert!(err.to_string().contains("Members: my-cli, my-service"));
+
+        let standalone = tempfile::tempdir().unwrap();
+        assert!(find_workspace_member(standalone.path(), "my-service").is_err());
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        assert_eq!(unified_diff("a.rs", "same\n", "same\n"), None);
+
+        let diff =
+            unified_diff("a.rs", "fn a() {}\nfn b() {}\n", "fn a() {}\nfn c() {}\n").unwrap();
+        assert!(diff.starts_with("--- a.rs (on disk)\n+++ a.rs (rendered)\n"));
+        assert!(diff.contains("-fn b() {}\n"));
+        assert!(diff.contains("+fn c() {}\n"));
+        assert!(diff.contains(" fn a() {}\n"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinked_archetypes_and_templates() {
+        use std::os::unix::fs::symlink;
+
+        let shared = tempfile::tempdir().unwrap();
+        let archetypes = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_single_file_archetype(shared.path());
+        fs::write(shared.path().join("common.tera"), "// shared").unwrap();
+        // テンプレートとパーシャルをリンクで共有し、親を指すリンクも置く
+        write_archetype(
+            archetypes.path(),
+            "linked",
+            r#"{
+                "name": "linked",
+                "displayName": "linked",
+                "description": "",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main" }
+                ]
+            }"#,
+            &[],
+        );
+        let linked = archetypes.path().join("linked");
+        symlink(
+            shared.path().join("single/main.tmpl"),
+            linked.join("main.tmpl"),
+        )
+        .unwrap();
+        symlink(
+            shared.path().join("common.tera"),
+            linked.join("common.tera"),
+        )
+        .unwrap();
+        symlink(&linked, linked.join("loop")).unwrap();
+        symlink(
+            shared.path().join("single"),
+            archetypes.path().join("single"),
+        )
+        .unwrap();
+
+        let dirs = [archetypes.path().to_path_buf()];
+        let names: Vec<String> = load_all_archetypes(&dirs)
+            .unwrap()
+            .into_iter()
+            .map(|manifest| manifest.name)
+            .collect();
+        assert_eq!(names, vec!["linked", "single"]);
+
+        scaffold_feature(&dirs, &plain_options("sample", "linked", target.path())).unwrap();
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/sample.rs")).unwrap(),
+            "// sample\n"
+        );
+    }
+
+    #[test]
+    fn test_later_archetype_dirs_take_precedence() {
+        let shared = tempfile::tempdir().unwrap();
+        let local = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write_single_file_archetype(shared.path());
+        write_archetype(
+            shared.path(),
+            "base",
+            r#"{ "name": "base", "displayName": "Base", "description": "shared", "files": [] }"#,
+            &[],
+        );
+        write_archetype(
+            local.path(),
+            "single",
+            r#"{
+                "name": "single",
+                "displayName": "single",
+                "description": "",
+                "extends": "base",
+                "files": [
+                    { "template": "main.tmpl", "output": "src/{{name}}.rs", "layer": "main" }
+                ]
+            }"#,
+            &[("main.tmpl", "// local {{ name }}")],
+        );
+        let dirs = [shared.path().to_path_buf(), local.path().to_path_buf()];
+
+        let archetypes = load_all_archetypes(&dirs).unwrap();
+        let names: Vec<_> = archetypes.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["base", "single"]);
+        assert_eq!(archetypes[1].dir, local.path().join("single"));
+        // 親は別のディレクトリにあっても解決できる
+        assert_eq!(archetypes[1].description, "shared");
+
+        scaffold_feature(&dirs, &plain_options("sample", "single", target.path())).unwrap();
+        assert_eq!(
+            fs::read_to_string(target.path().join("src/sample.rs")).unwrap(),
+            "// local sample\n"
+        );
+    }
+
+    #[test]
+    fn test_manifest_schema() {
+        let schema = serde_json::to_value(schemars::schema_for!(Manifest)).unwrap();
+        let properties = schema["properties"].as_object().unwrap();
+        for key in [
+            "name",
+            "extends",
+            "displayName",
+            "files",
+            "mod_updates",
+            "next_steps",
+        ] {
+            assert!(properties.contains_key(key), "missing {}", key);
+        }
+        // 内部用のフィールドはスキーマに含めない
+        assert!(!properties.contains_key("dir"));
+        assert!(!properties.contains_key("parent_dirs"));
+        assert_eq!(
+            schema["required"],
+            serde_json::json!(["name", "displayName", "description", "files"])
+        );
+        assert!(schema["$defs"]["FileSpec"]["properties"]
+            .get("when")
+            .is_some());
+    }
+
+    #[test]
+    fn test_pascal_case() {
+        assert_eq!("stock_price".to_pascal_case(), "StockPrice");
+        assert_eq!("market_analysis".to_pascal_case(), "MarketAnalysis");
+        assert_eq!(
+            to_snake_case("StockPriceService").to_pascal_case(),
+            "StockPriceService"
+        );
+        assert_eq!(to_snake_case("HTTPServer").to_pascal_case(), "HttpServer");
+    }
+}
@@ -1,15 +1,50 @@
 //! Aegis Architect - Architecture Enforcing Scaffold Tool
 //!
 //! RustでRustを生成する。これがメタプログラミング。
+//!
+//! 処理本体はライブラリクレート（`src/lib.rs`）にあり、ここは引数の解釈と表示のみ。
 
+use aegis_architect::{
+    cargo_check, dependency_snippet, feature_name_from_dir, find_workspace_member, init_project,
+    load_config, load_config_file, load_var_file, print_manifest_schema, render_template, status,
+    Architect, DiffOptions, ErrorKind, FormatOutcome, GeneratedFile, LineEnding, Manifest,
+    ModUpdate, ProjectSize, PruneOptions, RemoveOptions, RenameOptions, ScaffoldOptions,
+    ScaffoldReport, Verbosity, DEFAULT_ARCHETYPE, SHARED_TEMPLATES_DIR_ENV,
+};
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
-use colored::Colorize;
-use heck::ToPascalCase;
-use serde::Deserialize;
-use std::fs;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use colored::{Color, ColoredString, Colorize};
+use notify::{RecursiveMode, Watcher};
+use std::io::{IsTerminal, Write};
 use std::path::{Path, PathBuf};
-use tera::Tera;
+use std::process::ExitCode;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+/// `out`に1行書き込む（書き込みの失敗は無視する）
+macro_rules! emit {
+    ($out:expr) => {{
+        let _ = writeln!($out);
+    }};
+    ($out:expr, $($arg:tt)*) => {{
+        let _ = writeln!($out, $($arg)*);
+    }};
+}
+
+/// 詳細度が`Normal`以上のときだけ`emit!`する（`status!`の出力先を選べる版）
+macro_rules! emit_status {
+    ($out:expr, $verbosity:expr) => {
+        if $verbosity >= Verbosity::Normal {
+            emit!($out);
+        }
+    };
+    ($out:expr, $verbosity:expr, $($arg:tt)*) => {
+        if $verbosity >= Verbosity::Normal {
+            emit!($out, $($arg)*);
+        }
+    };
+}
 
 /// Aegis Architect - アーキタイプベースのスキャフォールドツール
 #[derive(Parser)]
@@ -20,9 +55,57 @@ struct Cli {
     #[command(subcommand)]
     command: Commands,
 
-    /// アーキタイプディレクトリのパス
+    /// アーキタイプディレクトリのパス（複数指定可、`:`区切りも可。後のものが優先）
+    #[arg(long, global = true)]
+    archetypes_dir: Vec<PathBuf>,
+
+    /// `@shared/`で始まるテンプレートを解決する共有テンプレートのディレクトリ
+    #[arg(long, global = true, value_name = "PATH")]
+    shared_templates_dir: Option<PathBuf>,
+
+    /// 設定ファイル（指定すると.aegisrcを探さずにこのファイルだけを読み込む）
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// 出力形式
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// テンプレートごとのサイズや処理時間を表示
     #[arg(long, global = true)]
-    archetypes_dir: Option<PathBuf>,
+    verbose: bool,
+
+    /// エラーと最終結果の1行以外を表示しない
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// 色付けを無効化（環境変数NO_COLORや非TTY出力でも無効になる）
+    #[arg(long, global = true)]
+    no_color: bool,
+}
+
+/// 出力を色付けするか判定する
+///
+/// `--no-color`指定時、`NO_COLOR`が空でない値で設定されている場合は色付けしない。
+/// それ以外は`.aegisrc`の`color`に従い、指定がなければ標準出力が端末の場合のみ色付けする。
+fn color_enabled(
+    no_color_flag: bool,
+    no_color_env: Option<&str>,
+    config_color: Option<bool>,
+    stdout_is_tty: bool,
+) -> bool {
+    !no_color_flag
+        && no_color_env.is_none_or(str::is_empty)
+        && config_color.unwrap_or(stdout_is_tty)
+}
+
+/// 出力形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// 人間向けのテキスト
+    Text,
+    /// 機械可読なJSON
+    Json,
 }
 
 #[derive(Subcommand)]
@@ -30,326 +113,1708 @@ enum Commands {
     /// 新機能のスキャフォールドを生成
     Scaffold {
         /// 機能名（snake_case推奨）
-        #[arg(short, long)]
-        name: String,
+        #[arg(short, long, required_unless_present = "name_from_dir")]
+        name: Option<String>,
+
+        /// --name を省略し、生成先ディレクトリの名前（snake_caseに変換）を機能名にする
+        #[arg(long, conflicts_with = "name")]
+        name_from_dir: bool,
 
         /// 機能の説明
+        #[arg(short, long, required_unless_present = "description_file")]
+        description: Option<String>,
+
+        /// 機能の説明をファイルから読み込む（複数行やMarkdownを含む場合）
+        #[arg(long, conflicts_with = "description")]
+        description_file: Option<PathBuf>,
+
+        /// アーキタイプ（デフォルト: .aegisrc、端末では対話的に選択、それ以外は rust_hexagonal）
         #[arg(short, long)]
-        description: String,
+        archetype: Option<String>,
 
-        /// アーキタイプ（デフォルト: rust_hexagonal）
-        #[arg(short, long, default_value = "rust_hexagonal")]
-        archetype: String,
+        /// 生成先ディレクトリ（デフォルト: .aegisrc または .）
+        #[arg(short, long)]
+        target: Option<PathBuf>,
 
-        /// 生成先ディレクトリ
-        #[arg(short, long, default_value = ".")]
-        target: PathBuf,
+        /// 生成先をワークスペースのメンバー名（パッケージ名）で指定
+        #[arg(long = "crate", conflicts_with = "target")]
+        crate_name: Option<String>,
 
         /// mod.rsの自動更新をスキップ
         #[arg(long)]
         no_mod_update: bool,
+
+        /// アーキタイプのmod_updatesの代わりに登録するmod.rs（Teraテンプレート、--mod-lineと対で複数指定可）
+        #[arg(
+            long,
+            value_name = "PATH",
+            requires = "mod_line",
+            conflicts_with = "no_mod_update"
+        )]
+        mod_file: Vec<String>,
+
+        /// --mod-fileに追加する行（Teraテンプレート。例: "pub mod {{name}};"）
+        #[arg(long, value_name = "LINE", requires = "mod_file")]
+        mod_line: Vec<String>,
+
+        /// ファイルを書き込まずに生成計画のみ表示
+        #[arg(long)]
+        dry_run: bool,
+
+        /// 生成されるファイルの一覧をJSONで出力して終了（何も書き込まない）
+        #[arg(long)]
+        plan: bool,
+
+        /// 生成されるファイルの数だけを出力して終了（何も書き込まない）
+        #[arg(long, conflicts_with_all = ["plan", "emit_prompt", "json_report", "watch"])]
+        count_only: bool,
+
+        /// 既存ファイルを上書きする
+        #[arg(long)]
+        force: bool,
+
+        /// テンプレート変数（key=value、複数指定可）
+        #[arg(long = "var", value_parser = parse_key_val)]
+        vars: Vec<(String, String)>,
+
+        /// テンプレート変数のファイル（JSONまたはTOML。--varが優先）
+        #[arg(long)]
+        var_file: Option<PathBuf>,
+
+        /// JSONのテンプレート変数（key=<json>、複数指定可）
+        #[arg(long = "var-json", value_parser = parse_key_json)]
+        json_vars: Vec<(String, serde_json::Value)>,
+
+        /// 生成した.rsファイルにrustfmtを実行
+        #[arg(long)]
+        fmt: bool,
+
+        /// 生成後に生成先のクレートでcargo checkを実行（失敗時もファイルは残す）
+        #[arg(long, conflicts_with = "dry_run")]
+        check: bool,
+
+        /// 生成先に未コミットの変更がある場合は中断
+        #[arg(long)]
+        require_clean: bool,
+
+        /// 生成内容の記録を.aegis/<name>.jsonに保存
+        #[arg(long)]
+        record: bool,
+
+        /// 同じ名前のモジュールが既にmod.rsにある場合は中断（既定は警告のみ）
+        #[arg(long)]
+        no_clobber_modules: bool,
+
+        /// 生成ファイルの先頭に元のアーキタイプを示すコメントを付ける（既定は.rsのみ）
+        #[arg(long, conflicts_with = "no_banner")]
+        banner: bool,
+
+        /// 生成ファイルにバナーのコメントを付けない
+        #[arg(long)]
+        no_banner: bool,
+
+        /// この実行だけテンプレートを差し替える（出力パス=path または layer=path、複数指定可）
+        #[arg(long = "template-override", value_parser = parse_key_val)]
+        template_overrides: Vec<(String, String)>,
+
+        /// テンプレートがdefault_vars・required_varsで宣言されていない変数を参照していれば中断
+        #[arg(long)]
+        check_vars: bool,
+
+        /// 既存の機能の編集済みの内容を、名前を置き換えて新しい機能のファイルに引き継ぐ
+        #[arg(long, value_name = "EXISTING_NAME")]
+        seed_from: Option<String>,
+
+        /// 既存ファイルの`AEGIS:BEGIN generated`〜`AEGIS:END generated`の領域だけを再生成し、その外の編集を残す
+        #[arg(long)]
+        merge_markers: bool,
+
+        /// アーキタイプが必要とするクレートのうち未登録のものを生成先のCargo.tomlに追加
+        #[arg(long)]
+        add_deps: bool,
+
+        /// 何も書き込まず、再生成でファイルやmod行が変わる場合は差分の概要を表示して失敗（CI向け）
+        #[arg(long, conflicts_with_all = ["dry_run", "plan", "check", "watch", "emit_prompt"])]
+        assert_unchanged: bool,
+
+        /// ファイルを生成せず、アーキタイプのprompt_templateをレンダリングして表示
+        #[arg(long, conflicts_with_all = ["dry_run", "plan", "watch", "check"])]
+        emit_prompt: bool,
+
+        /// 全ファイルを機能ディレクトリ（既定はsrc/features/<name>/）に生成し、そのmod.rsに登録
+        #[arg(long)]
+        feature_dir: bool,
+
+        /// 指定したレイヤーのファイルのみ生成（複数指定可、`adapter*`のようなglobも可）
+        #[arg(long)]
+        only_layer: Vec<String>,
+
+        /// 指定したレイヤーのファイルを生成しない（複数指定可、globも可。--only-layerから除く）
+        #[arg(long)]
+        skip_layer: Vec<String>,
+
+        /// アーキタイプのpost_generateフック（任意のシェルコマンド）を実行
+        #[arg(long)]
+        run_hooks: bool,
+
+        /// プロジェクトの規模（アーキタイプのsizeと異なる場合に警告）
+        #[arg(long, value_enum)]
+        project_size: Option<ProjectSize>,
+
+        /// 生成ファイルの改行コード
+        #[arg(long, value_enum, default_value_t = LineEnding::Lf)]
+        line_ending: LineEnding,
+
+        /// 生成ファイルをレイヤーごとのツリーで表示（6ファイル以上ではデフォルト）
+        #[arg(long, conflicts_with = "no_tree")]
+        tree: bool,
+
+        /// 生成ファイルを常に1行1ファイルで表示（スクリプト向け）
+        #[arg(long)]
+        no_tree: bool,
+
+        /// 生成結果のパスをカレントディレクトリからの相対パスでなく絶対パスで表示（--verboseでも絶対パス）
+        #[arg(long)]
+        absolute_paths: bool,
+
+        /// 生成結果をJSONで標準出力に出力（人間向けの表示は標準エラー出力へ）
+        #[arg(long, conflicts_with_all = ["plan", "emit_prompt", "watch"])]
+        json_report: bool,
+
+        /// 生成後もアーキタイプを監視し、テンプレートの変更ごとに差分を表示して再生成
+        #[arg(long, conflicts_with_all = ["dry_run", "plan", "check"])]
+        watch: bool,
+    },
+
+    /// スキャフォールド済みの機能を削除
+    Remove {
+        /// 機能名
+        #[arg(short, long)]
+        name: String,
+
+        /// 生成時に指定した機能の説明（生成物との比較に使用）
+        #[arg(short, long, default_value = "")]
+        description: String,
+
+        /// アーキタイプ（デフォルト: .aegisrc または rust_hexagonal）
+        #[arg(short, long)]
+        archetype: Option<String>,
+
+        /// 生成先ディレクトリ（デフォルト: .aegisrc または .）
+        #[arg(short, long)]
+        target: Option<PathBuf>,
+
+        /// 生成先をワークスペースのメンバー名（パッケージ名）で指定
+        #[arg(long = "crate", conflicts_with = "target")]
+        crate_name: Option<String>,
+
+        /// 生成時に指定したテンプレート変数（key=value、複数指定可）
+        #[arg(long = "var", value_parser = parse_key_val)]
+        vars: Vec<(String, String)>,
+
+        /// テンプレート変数のファイル（JSONまたはTOML。--varが優先）
+        #[arg(long)]
+        var_file: Option<PathBuf>,
+
+        /// JSONのテンプレート変数（key=<json>、複数指定可）
+        #[arg(long = "var-json", value_parser = parse_key_json)]
+        json_vars: Vec<(String, serde_json::Value)>,
+
+        /// 生成時から編集されたファイルも削除する
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// 生成記録にあるが、現在のアーキタイプでは生成されなくなったファイルを削除
+    Prune {
+        /// 機能名
+        #[arg(short, long)]
+        name: String,
+
+        /// 生成先ディレクトリ（デフォルト: .aegisrc または .）
+        #[arg(short, long)]
+        target: Option<PathBuf>,
+
+        /// 生成先をワークスペースのメンバー名（パッケージ名）で指定
+        #[arg(long = "crate", conflicts_with = "target")]
+        crate_name: Option<String>,
+
+        /// 実際に削除する（省略時は対象の一覧を表示するだけ）
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// スキャフォールド済みの機能の名前を変更（ファイルの移動とmod.rsの更新）
+    Rename {
+        /// 現在の機能名
+        old: String,
+
+        /// 新しい機能名
+        new: String,
+
+        /// アーキタイプ（デフォルト: .aegisrc または rust_hexagonal。生成記録があればそちらを使用）
+        #[arg(short, long)]
+        archetype: Option<String>,
+
+        /// 生成先ディレクトリ（デフォルト: .aegisrc または .）
+        #[arg(short, long)]
+        target: Option<PathBuf>,
+
+        /// 生成先をワークスペースのメンバー名（パッケージ名）で指定
+        #[arg(long = "crate", conflicts_with = "target")]
+        crate_name: Option<String>,
+
+        /// 生成時に指定したテンプレート変数（key=value、複数指定可）
+        #[arg(long = "var", value_parser = parse_key_val)]
+        vars: Vec<(String, String)>,
+
+        /// テンプレート変数のファイル（JSONまたはTOML。--varが優先）
+        #[arg(long)]
+        var_file: Option<PathBuf>,
+
+        /// JSONのテンプレート変数（key=<json>、複数指定可）
+        #[arg(long = "var-json", value_parser = parse_key_json)]
+        json_vars: Vec<(String, serde_json::Value)>,
+
+        /// 移動したファイル内の旧名（snake_case・PascalCase）も新しい名前に置換
+        #[arg(long)]
+        rewrite_contents: bool,
+
+        /// --feature-dirで生成した機能として扱う（生成記録があれば記録に従う）
+        #[arg(long)]
+        feature_dir: bool,
+    },
+
+    /// 生成済みのファイルと現在のアーキタイプでの再レンダリング結果を比較
+    Diff {
+        /// 機能名
+        #[arg(short, long)]
+        name: String,
+
+        /// 生成時に指定した機能の説明
+        #[arg(short, long, default_value = "")]
+        description: String,
+
+        /// アーキタイプ（デフォルト: .aegisrc または rust_hexagonal）
+        #[arg(short, long)]
+        archetype: Option<String>,
+
+        /// 生成先ディレクトリ（デフォルト: .aegisrc または .）
+        #[arg(short, long)]
+        target: Option<PathBuf>,
+
+        /// 生成先をワークスペースのメンバー名（パッケージ名）で指定
+        #[arg(long = "crate", conflicts_with = "target")]
+        crate_name: Option<String>,
+
+        /// 生成時に指定したテンプレート変数（key=value、複数指定可）
+        #[arg(long = "var", value_parser = parse_key_val)]
+        vars: Vec<(String, String)>,
+
+        /// テンプレート変数のファイル（JSONまたはTOML。--varが優先）
+        #[arg(long)]
+        var_file: Option<PathBuf>,
+
+        /// JSONのテンプレート変数（key=<json>、複数指定可）
+        #[arg(long = "var-json", value_parser = parse_key_json)]
+        json_vars: Vec<(String, serde_json::Value)>,
+
+        /// --feature-dirで生成した機能として比べる（生成記録があれば記録に従う）
+        #[arg(long)]
+        feature_dir: bool,
+    },
+
+    /// アーキタイプを使わずにテンプレートを1つレンダリング（標準入力または--template-file）
+    Render {
+        /// 機能名
+        #[arg(short, long)]
+        name: String,
+
+        /// 機能の説明
+        #[arg(short, long, default_value = "")]
+        description: String,
+
+        /// テンプレートファイル（省略時は標準入力から読み込む）
+        #[arg(long)]
+        template_file: Option<PathBuf>,
+
+        /// 出力先ファイル（省略時は標準出力）
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+
+        /// テンプレート変数（key=value、複数指定可）
+        #[arg(long = "var", value_parser = parse_key_val)]
+        vars: Vec<(String, String)>,
+
+        /// テンプレート変数のファイル（JSONまたはTOML。--varが優先）
+        #[arg(long)]
+        var_file: Option<PathBuf>,
+
+        /// JSONのテンプレート変数（key=<json>、複数指定可）
+        #[arg(long = "var-json", value_parser = parse_key_json)]
+        json_vars: Vec<(String, serde_json::Value)>,
+    },
+
+    /// Hexagonal構成のsrc/lib.rsとmod.rsの雛形を作成
+    Init {
+        /// 生成先ディレクトリ（デフォルト: .aegisrc または .）
+        #[arg(short, long)]
+        target: Option<PathBuf>,
     },
 
     /// 利用可能なアーキタイプ一覧を表示
     List,
-}
 
-/// アーキタイプのマニフェスト
-#[derive(Debug, Deserialize)]
-struct Manifest {
-    name: String,
-    #[serde(rename = "displayName")]
-    display_name: String,
-    description: String,
-    #[serde(default)]
-    use_when: Vec<String>,
-    #[serde(default)]
-    avoid_when: Vec<String>,
-    files: Vec<FileSpec>,
+    /// アーキタイプの詳細を表示
+    Describe {
+        /// 表示するアーキタイプ
+        archetype: String,
+
+        /// テンプレート・出力先・レイヤーの対応とテンプレートの有無を表示
+        #[arg(long)]
+        show_files: bool,
+
+        /// 出力パスの展開に使う仮の機能名
+        #[arg(short, long, default_value = "example")]
+        name: String,
+    },
+
+    /// アーキタイプのマニフェストを検証
+    Validate {
+        /// 検証するアーキタイプ（省略時は全て）
+        archetype: Option<String>,
+    },
+
+    /// 新しいアーキタイプの雛形を作成
+    NewArchetype {
+        /// アーキタイプ名（ディレクトリ名）
+        name: String,
+    },
+
+    /// アーキタイプディレクトリや外部コマンドなど、実行環境を検査
+    Doctor {
+        /// Rustプロジェクトか確認するディレクトリ（デフォルト: .aegisrc または .）
+        #[arg(short, long)]
+        target: Option<PathBuf>,
+    },
+
+    /// manifest.jsonのJSON Schemaを出力
+    Schema,
+
+    /// シェル補完スクリプトを標準出力に出力
+    Completions {
+        /// 対象のシェル
+        #[arg(value_enum)]
+        shell: Shell,
+    },
 }
 
-/// 生成ファイルの仕様
-#[derive(Debug, Deserialize)]
-struct FileSpec {
-    template: String,
-    output: String,
-    layer: String,
+/// エラーを表示し、種類に応じた終了コードで終了する（[`ErrorKind::exit_code`]）
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            ExitCode::from(ErrorKind::of(&err).exit_code())
+        }
+    }
 }
 
-fn main() -> Result<()> {
+fn run() -> Result<()> {
     let cli = Cli::parse();
 
-    // アーキタイプディレクトリを決定
-    let archetypes_dir = cli.archetypes_dir.unwrap_or_else(|| {
-        // 実行ファイルと同じディレクトリ、または親ディレクトリのarchetypesを探す
-        let exe_dir = std::env::current_exe()
-            .ok()
-            .and_then(|p| p.parent().map(|p| p.to_path_buf()));
-
-        if let Some(dir) = exe_dir {
-            let candidate = dir.join("archetypes");
-            if candidate.exists() {
-                return candidate;
-            }
-            // 親ディレクトリも探す
-            if let Some(parent) = dir.parent() {
-                let candidate = parent.join("archetypes");
-                if candidate.exists() {
-                    return candidate;
-                }
-            }
-        }
+    // 出力を始める前に色付けの可否を決める
+    let no_color_env = std::env::var("NO_COLOR").ok();
+    if !color_enabled(
+        cli.no_color,
+        no_color_env.as_deref(),
+        None,
+        std::io::stdout().is_terminal(),
+    ) {
+        colored::control::set_override(false);
+    }
 
-        // フォールバック: カレントディレクトリ
-        PathBuf::from("archetypes")
-    });
+    // 補完スクリプトは設定やアーキタイプに依存しない
+    if let Commands::Completions { shell } = cli.command {
+        let mut command = Cli::command();
+        let name = command.get_name().to_string();
+        clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+        return Ok(());
+    }
+
+    let config = match &cli.config {
+        Some(path) => load_config_file(path)?,
+        None => load_config()?,
+    };
+    if config.color.is_some() {
+        colored::control::set_override(color_enabled(
+            cli.no_color,
+            no_color_env.as_deref(),
+            config.color,
+            std::io::stdout().is_terminal(),
+        ));
+    }
+    let verbosity = config.verbosity(cli.quiet, cli.verbose);
+
+    // 共有テンプレートのディレクトリ（CLI > 環境変数 > .aegisrc）
+    let shared_templates_dir = cli
+        .shared_templates_dir
+        .clone()
+        .or_else(|| {
+            std::env::var_os(SHARED_TEMPLATES_DIR_ENV)
+                .filter(|value| !value.is_empty())
+                .map(PathBuf::from)
+        })
+        .or_else(|| config.shared_templates_dir.clone());
+
+    // アーキタイプディレクトリを決定（CLI > 環境変数 > .aegisrc > 自動探索）
+    let cli_dirs: Vec<PathBuf> = cli
+        .archetypes_dir
+        .iter()
+        .flat_map(std::env::split_paths)
+        .collect();
+    let (archetypes_dirs, searched, source) = if !cli_dirs.is_empty() {
+        (cli_dirs, Vec::new(), "--archetypes-dir")
+    } else if let Some(dirs) = env_archetypes_dirs(std::env::var_os(ARCHETYPES_DIR_ENV)) {
+        (dirs, Vec::new(), ARCHETYPES_DIR_ENV)
+    } else if let Some(dir) = config.archetypes_dir.clone() {
+        (
+            vec![dir],
+            Vec::new(),
+            if cli.config.is_some() {
+                "--config"
+            } else {
+                ".aegisrc"
+            },
+        )
+    } else {
+        let (dir, searched) = discover_archetypes_dir();
+        (vec![dir], searched, "auto-discovery")
+    };
+    if verbosity == Verbosity::Verbose {
+        let dirs: Vec<String> = archetypes_dirs
+            .iter()
+            .map(|dir| dir.display().to_string())
+            .collect();
+        eprintln!(
+            "{}",
+            format!(
+                "Archetypes directory: {} (from {})",
+                dirs.join(", "),
+                source
+            )
+            .dimmed()
+        );
+    }
+
+    // アーキタイプを読み込むコマンドは、ディレクトリがなければ何もせずに中断する
+    if !matches!(
+        cli.command,
+        Commands::Init { .. }
+            | Commands::Doctor { .. }
+            | Commands::Schema
+            | Commands::NewArchetype { .. }
+            | Commands::Render { .. }
+    ) {
+        check_archetypes_dirs(&archetypes_dirs, &searched)?;
+    }
+    let architect =
+        Architect::new(archetypes_dirs).with_shared_templates_dir(shared_templates_dir.clone());
+
+    // 生成先を決定（--crate > CLI > .aegisrc > カレントディレクトリ）
+    let resolve_target = |target: Option<PathBuf>, crate_name: Option<String>| match crate_name {
+        Some(name) => {
+            let current_dir = std::env::current_dir().context("Failed to get current directory")?;
+            find_workspace_member(&current_dir, &name)
+        }
+        None => Ok(config.target(target)),
+    };
 
     match cli.command {
-        Commands::List => list_archetypes(&archetypes_dir),
+        Commands::List => list_archetypes(&architect, cli.format, verbosity),
+        Commands::Describe {
+            archetype,
+            show_files,
+            name,
+        } => describe_archetype(&architect, &archetype, show_files, &name, cli.format),
+        Commands::Remove {
+            name,
+            description,
+            archetype,
+            target,
+            crate_name,
+            vars,
+            var_file,
+            json_vars,
+            force,
+        } => architect.remove(&RemoveOptions {
+            name,
+            description,
+            archetype: config.archetype(archetype),
+            target: resolve_target(target, crate_name)?,
+            vars: collect_vars(var_file.as_deref(), vars, json_vars)?,
+            force,
+            verbosity,
+            shared_templates_dir: shared_templates_dir.clone(),
+        }),
+        Commands::Prune {
+            name,
+            target,
+            crate_name,
+            force,
+        } => architect.prune(&PruneOptions {
+            name,
+            target: resolve_target(target, crate_name)?,
+            force,
+            verbosity,
+        }),
+        Commands::Diff {
+            name,
+            description,
+            archetype,
+            target,
+            crate_name,
+            vars,
+            var_file,
+            json_vars,
+            feature_dir,
+        } => architect
+            .diff(&DiffOptions {
+                name,
+                description,
+                archetype: config.archetype(archetype),
+                target: resolve_target(target, crate_name)?,
+                vars: collect_vars(var_file.as_deref(), vars, json_vars)?,
+                shared_templates_dir: shared_templates_dir.clone(),
+                feature_dir,
+            })
+            .map(|_| ()),
+        Commands::Rename {
+            old,
+            new,
+            archetype,
+            target,
+            crate_name,
+            vars,
+            var_file,
+            json_vars,
+            rewrite_contents,
+            feature_dir,
+        } => architect.rename(&RenameOptions {
+            old,
+            new,
+            archetype: config.archetype(archetype),
+            target: resolve_target(target, crate_name)?,
+            vars: collect_vars(var_file.as_deref(), vars, json_vars)?,
+            rewrite_contents,
+            feature_dir,
+            verbosity,
+        }),
+        Commands::Render {
+            name,
+            description,
+            template_file,
+            out,
+            vars,
+            var_file,
+            json_vars,
+        } => {
+            let template = match &template_file {
+                Some(path) => std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read template: {:?}", path))?,
+                None => std::io::read_to_string(std::io::stdin())
+                    .context("Failed to read template from stdin")?,
+            };
+            let vars = collect_vars(var_file.as_deref(), vars, json_vars)?;
+            let rendered = render_template(&template, &name, &description, &vars)?;
+            match out {
+                Some(path) => std::fs::write(&path, rendered)
+                    .with_context(|| format!("Failed to write file: {:?}", path)),
+                None => {
+                    print!("{}", rendered);
+                    Ok(())
+                }
+            }
+        }
+        Commands::Init { target } => init_project(&config.target(target)),
+        Commands::Doctor { target } => architect.doctor(&config.target(target)),
+        Commands::Schema => print_manifest_schema(),
+        Commands::Completions { .. } => unreachable!("handled before loading the config"),
+        Commands::NewArchetype { name } => architect.new_archetype(&name),
+        Commands::Validate { archetype } => architect.validate(archetype.as_deref()),
         Commands::Scaffold {
             name,
+            name_from_dir,
             description,
             archetype,
             target,
+            crate_name,
             no_mod_update,
-        } => scaffold_feature(
-            &archetypes_dir,
-            &name,
-            &description,
-            &archetype,
-            &target,
-            !no_mod_update,
-        ),
+            mod_file,
+            mod_line,
+            dry_run,
+            plan,
+            count_only,
+            force,
+            vars,
+            var_file,
+            json_vars,
+            fmt,
+            check,
+            require_clean,
+            record,
+            only_layer,
+            skip_layer,
+            no_clobber_modules,
+            feature_dir,
+            template_overrides,
+            banner,
+            no_banner,
+            check_vars,
+            seed_from,
+            merge_markers,
+            add_deps,
+            assert_unchanged,
+            emit_prompt,
+            run_hooks,
+            project_size,
+            line_ending,
+            description_file,
+            tree,
+            no_tree,
+            absolute_paths,
+            json_report,
+            watch,
+        } => {
+            let target = resolve_target(target, crate_name)?;
+            // clapが--nameか--name-from-dirのどちらかを必須にしている
+            let name = match name {
+                Some(name) => name,
+                None if name_from_dir => feature_name_from_dir(&target)?,
+                None => unreachable!("--name or --name-from-dir is required"),
+            };
+            let options = ScaffoldOptions {
+                name,
+                description: read_description(description, description_file.as_deref())?,
+                archetype: match archetype {
+                    // 指定も設定もなく端末から実行された場合は対話的に選ぶ
+                    None if config.default_archetype.is_none()
+                        && std::io::stdin().is_terminal() =>
+                    {
+                        pick_archetype(&architect)?
+                    }
+                    archetype => config.archetype(archetype),
+                },
+                target,
+                update_mod: !no_mod_update,
+                dry_run,
+                force,
+                vars: collect_vars(var_file.as_deref(), vars, json_vars)?,
+                fmt,
+                require_clean,
+                record,
+                only_layers: only_layer,
+                skip_layers: skip_layer,
+                no_clobber_modules,
+                feature_dir,
+                template_overrides: template_overrides
+                    .into_iter()
+                    .map(|(key, path)| (key, PathBuf::from(path)))
+                    .collect(),
+                banner: if banner || no_banner {
+                    Some(banner)
+                } else {
+                    None
+                },
+                check_vars,
+                seed_from,
+                assert_unchanged,
+                add_deps,
+                merge_markers,
+                run_hooks,
+                line_ending,
+                progress: verbosity != Verbosity::Quiet,
+                stream_hooks: verbosity != Verbosity::Quiet,
+                mod_lines: pair_mod_lines(mod_file, mod_line)?,
+                shared_templates_dir: shared_templates_dir.clone(),
+            };
+            if emit_prompt {
+                print!("{}", architect.prompt(&options)?);
+                return Ok(());
+            }
+            if plan {
+                let planned = architect.plan(&options)?;
+                println!("{}", serde_json::to_string_pretty(&planned)?);
+                return Ok(());
+            }
+            if count_only {
+                println!("{}", architect.plan(&options)?.len());
+                return Ok(());
+            }
+            if let Some(project_size) = project_size {
+                warn_size_mismatch(&architect, &options.archetype, project_size)?;
+            }
+            let report = architect.scaffold(&options)?;
+            if assert_unchanged {
+                println!(
+                    "{} {} file(s) of '{}' match the archetype",
+                    "ok".green(),
+                    report.unchanged.len(),
+                    report.name
+                );
+                return Ok(());
+            }
+            let tree = tree || (!no_tree && report.generated.len() > TREE_THRESHOLD);
+            // --json-reportでは標準出力をJSONだけにする
+            let mut out: Box<dyn Write> = if json_report {
+                Box::new(std::io::stderr())
+            } else {
+                Box::new(std::io::stdout())
+            };
+            let paths = PathStyle {
+                cwd: std::env::current_dir().ok(),
+                absolute: absolute_paths || verbosity == Verbosity::Verbose,
+            };
+            print_scaffold_report(&mut out, &options, &report, verbosity, tree, &paths);
+            if json_report {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&report.to_json(&options.target))?
+                );
+            }
+            if check {
+                emit_status!(out, verbosity, "\nRunning cargo check...");
+                let crate_dir = cargo_check(&options.target)?;
+                emit_status!(out, verbosity, "  {} {}", "ok".green(), crate_dir.display());
+            }
+            if watch {
+                watch_archetype(&architect, &options, verbosity)?;
+            }
+            Ok(())
+        }
     }
 }
 
-/// アーキタイプ一覧を表示
-fn list_archetypes(archetypes_dir: &Path) -> Result<()> {
-    println!("{}", "Available Archetypes:".bold());
-    println!("{}", "=".repeat(60));
+/// `--watch`で連続した保存を1回の再生成にまとめる待ち時間
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
 
-    let archetypes = load_all_archetypes(archetypes_dir)?;
+/// `--watch`の監視ループへの通知
+enum WatchEvent {
+    /// アーキタイプのファイルが変更された
+    Changed,
+    /// Ctrl-Cが押された
+    Stop,
+}
 
-    for manifest in archetypes {
-        println!("\n[{}]", manifest.name.cyan());
-        println!("\n  {}", manifest.display_name.bold());
-        println!("  {}", manifest.description);
+/// アーキタイプのディレクトリ（継承元を含む）を監視し、変更のたびに再生成する
+///
+/// 再生成の前に現在の生成物との差分を表示する。編集途中のテンプレートで
+/// 失敗してもエラーを表示して監視を続け、Ctrl-Cで終了する。
+fn watch_archetype(
+    architect: &Architect,
+    options: &ScaffoldOptions,
+    verbosity: Verbosity,
+) -> Result<()> {
+    let manifest = architect.archetype(&options.archetype)?;
+    let (sender, receiver) = mpsc::channel();
 
-        if !manifest.use_when.is_empty() {
-            println!("\n  {}:", "Use when".green());
-            for item in &manifest.use_when {
-                println!("    - {}", item);
-            }
+    let stop = sender.clone();
+    ctrlc::set_handler(move || {
+        let _ = stop.send(WatchEvent::Stop);
+    })
+    .context("Failed to install Ctrl-C handler")?;
+
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if event.is_ok_and(|event| !event.kind.is_access()) {
+            let _ = sender.send(WatchEvent::Changed);
         }
+    })
+    .context("Failed to start watching the archetype")?;
+    for dir in manifest.source_dirs() {
+        watcher
+            .watch(dir, RecursiveMode::Recursive)
+            .with_context(|| format!("Failed to watch {:?}", dir))?;
+        status!(verbosity, "\nWatching {} (Ctrl-C to stop)", dir.display());
+    }
 
-        if !manifest.avoid_when.is_empty() {
-            println!("\n  {}:", "Avoid when".red());
-            for item in &manifest.avoid_when {
-                println!("    - {}", item);
+    // 再生成では既存のファイルを上書きし、フックは初回の生成でのみ実行する
+    let options = ScaffoldOptions {
+        force: true,
+        run_hooks: false,
+        ..options.clone()
+    };
+    let diff_options = DiffOptions {
+        name: options.name.clone(),
+        description: options.description.clone(),
+        archetype: options.archetype.clone(),
+        target: options.target.clone(),
+        vars: options.vars.clone(),
+        shared_templates_dir: options.shared_templates_dir.clone(),
+        feature_dir: options.feature_dir,
+    };
+
+    while let Ok(WatchEvent::Changed) = receiver.recv() {
+        // 保存が続く間は待ち、静かになってから1回だけ再生成する
+        let stopped = loop {
+            match receiver.recv_timeout(WATCH_DEBOUNCE) {
+                Ok(WatchEvent::Changed) => continue,
+                Ok(WatchEvent::Stop) | Err(RecvTimeoutError::Disconnected) => break true,
+                Err(RecvTimeoutError::Timeout) => break false,
             }
+        };
+        if stopped {
+            break;
+        }
+
+        status!(
+            verbosity,
+            "\n{}",
+            "Archetype changed, regenerating...".bold()
+        );
+        match architect
+            .diff(&diff_options)
+            .and_then(|_| architect.scaffold(&options))
+        {
+            Ok(report) => status!(
+                verbosity,
+                "{}",
+                format!("Regenerated {} files", report.generated.len()).green()
+            ),
+            Err(err) => eprintln!("{} {:#}", "error:".red(), err),
         }
     }
 
-    println!("\n{}", "=".repeat(60));
+    status!(verbosity, "\nStopped watching");
     Ok(())
 }
 
-/// 全アーキタイプを読み込む
-fn load_all_archetypes(archetypes_dir: &Path) -> Result<Vec<Manifest>> {
-    let mut result = Vec::new();
+/// アーキタイプディレクトリを指定する環境変数
+const ARCHETYPES_DIR_ENV: &str = "AEGIS_ARCHETYPES_DIR";
 
-    for entry in fs::read_dir(archetypes_dir)
-        .with_context(|| format!("Failed to read archetypes directory: {:?}", archetypes_dir))?
+/// 環境変数の値からアーキタイプディレクトリを得る
+///
+/// `--archetypes-dir`と同じくパス区切りで複数指定できる。すべてが存在する場合のみ使う。
+fn env_archetypes_dirs(value: Option<std::ffi::OsString>) -> Option<Vec<PathBuf>> {
+    let dirs: Vec<PathBuf> = std::env::split_paths(&value?)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .collect();
+    (!dirs.is_empty() && dirs.iter().all(|dir| dir.is_dir())).then_some(dirs)
+}
+
+/// 実行ファイルの位置からアーキタイプディレクトリを探す
+///
+/// 実行ファイルと同じディレクトリ、その親ディレクトリ、カレントディレクトリの順に
+/// `archetypes`を探し、見つからなければ最後の候補を返す。2つ目の値は探した候補の一覧。
+fn discover_archetypes_dir() -> (PathBuf, Vec<PathBuf>) {
+    let mut candidates = Vec::new();
+    if let Some(exe_dir) = std::env::current_exe()
+        .ok()
+        .and_then(|p| p.parent().map(|p| p.to_path_buf()))
     {
-        let entry = entry?;
-        let path = entry.path();
-
-        if path.is_dir() {
-            let manifest_path = path.join("manifest.json");
-            if manifest_path.exists() {
-                let content = fs::read_to_string(&manifest_path)?;
-                let manifest: Manifest = serde_json::from_str(&content)
-                    .with_context(|| format!("Failed to parse manifest: {:?}", manifest_path))?;
-                result.push(manifest);
-            }
+        candidates.push(exe_dir.join("archetypes"));
+        if let Some(parent) = exe_dir.parent() {
+            candidates.push(parent.join("archetypes"));
         }
     }
+    // フォールバック: カレントディレクトリ
+    candidates.push(PathBuf::from("archetypes"));
 
-    // 名前でソート
-    result.sort_by(|a, b| a.name.cmp(&b.name));
-    Ok(result)
+    let found = candidates
+        .iter()
+        .find(|candidate| candidate.exists())
+        .unwrap_or_else(|| candidates.last().unwrap())
+        .clone();
+    (found, candidates)
 }
 
-/// アーキタイプを読み込む
-fn load_archetype(archetypes_dir: &Path, name: &str) -> Result<Manifest> {
-    let manifest_path = archetypes_dir.join(name).join("manifest.json");
-
-    if !manifest_path.exists() {
-        let available: Vec<_> = load_all_archetypes(archetypes_dir)?
+/// アーキタイプディレクトリが存在することを確認する
+///
+/// 自動探索した場合は、探した候補もエラーメッセージに含める。
+fn check_archetypes_dirs(dirs: &[PathBuf], searched: &[PathBuf]) -> Result<()> {
+    let Some(missing) = dirs.iter().find(|dir| !dir.is_dir()) else {
+        return Ok(());
+    };
+    let mut message = format!(
+        "No archetypes directory found at {}; pass --archetypes-dir or create one",
+        missing.display()
+    );
+    if !searched.is_empty() {
+        let searched: Vec<String> = searched
             .iter()
-            .map(|m| m.name.clone())
+            .map(|path| path.display().to_string())
             .collect();
-
-        anyhow::bail!(
-            "Archetype '{}' not found. Available: {}",
-            name,
-            available.join(", ")
-        );
+        message.push_str(&format!(" (searched: {})", searched.join(", ")));
     }
-
-    let content = fs::read_to_string(&manifest_path)?;
-    let manifest: Manifest = serde_json::from_str(&content)?;
-    Ok(manifest)
+    anyhow::bail!(message)
 }
 
-/// スキャフォールドを生成
-fn scaffold_feature(
-    archetypes_dir: &Path,
-    name: &str,
-    description: &str,
+/// アーキタイプの想定規模がプロジェクトと食い違う場合に警告する（生成は止めない）
+fn warn_size_mismatch(
+    architect: &Architect,
     archetype: &str,
-    target: &Path,
-    update_mod: bool,
+    project_size: ProjectSize,
 ) -> Result<()> {
-    // 名前を正規化
-    let snake_name = to_snake_case(name);
-    let pascal_name = snake_name.to_pascal_case();
+    let manifest = architect.archetype(archetype)?;
+    let Some(size) = manifest
+        .size
+        .filter(|_| manifest.size_mismatch(project_size))
+    else {
+        return Ok(());
+    };
 
-    println!("{}", "=".repeat(60));
-    println!(
+    eprintln!(
+        "{} archetype '{}' is meant for {} projects, but this project is {}",
+        "warning:".yellow().bold(),
+        manifest.name,
+        size,
+        project_size
+    );
+    if !manifest.avoid_when.is_empty() {
+        eprintln!("  {}:", "Avoid when".yellow());
+        for item in &manifest.avoid_when {
+            eprintln!("    - {}", item);
+        }
+    }
+    Ok(())
+}
+
+/// `--tree`/`--no-tree`の指定がない場合にツリー表示へ切り替えるファイル数
+const TREE_THRESHOLD: usize = 5;
+
+/// 生成結果に表示するパスの形式
+struct PathStyle {
+    /// 相対パスの基準にするカレントディレクトリ（取得できない場合は`None`）
+    cwd: Option<PathBuf>,
+    /// カレントディレクトリからの相対パスにせず絶対パスで表示するか
+    absolute: bool,
+}
+
+impl PathStyle {
+    /// カレントディレクトリからの相対パス（`absolute`では絶対パス）で表示。
+    /// カレントディレクトリの外にあるなど相対にできない場合は元のパスのまま
+    fn show(&self, path: &Path) -> String {
+        let Some(cwd) = &self.cwd else {
+            return path.display().to_string();
+        };
+        let full = cwd.join(path);
+        if self.absolute {
+            return full.display().to_string();
+        }
+        match full.strip_prefix(cwd) {
+            Ok(relative) if !relative.as_os_str().is_empty() => relative.display().to_string(),
+            _ => path.display().to_string(),
+        }
+    }
+}
+
+/// スキャフォールドの結果を表示
+fn print_scaffold_report(
+    out: &mut dyn Write,
+    options: &ScaffoldOptions,
+    report: &ScaffoldReport,
+    verbosity: Verbosity,
+    tree: bool,
+    paths: &PathStyle,
+) {
+    emit_status!(out, verbosity, "{}", "=".repeat(60));
+    emit_status!(
+        out,
+        verbosity,
         "{}",
         "Aegis Architect - Architecture Enforced Scaffolding".bold()
     );
-    println!("{}", "=".repeat(60));
-    println!("Feature:   {}", snake_name.cyan());
-    println!("Archetype: {}", archetype.cyan());
-    println!("Target:    {}", target.display().to_string().cyan());
-    println!("{}\n", "=".repeat(60));
+    emit_status!(out, verbosity, "{}", "=".repeat(60));
+    emit_status!(out, verbosity, "Feature:   {}", report.name.cyan());
+    emit_status!(out, verbosity, "Archetype: {}", options.archetype.cyan());
+    emit_status!(
+        out,
+        verbosity,
+        "Target:    {}",
+        options.target.display().to_string().cyan()
+    );
+    if report.dry_run {
+        emit_status!(
+            out,
+            verbosity,
+            "Mode:      {}",
+            "dry-run (no files will be written)".yellow()
+        );
+    }
+    if !options.only_layers.is_empty() {
+        emit_status!(
+            out,
+            verbosity,
+            "Layers:    {}",
+            options.only_layers.join(", ").cyan()
+        );
+    }
+    if !options.skip_layers.is_empty() {
+        emit_status!(
+            out,
+            verbosity,
+            "Skipping:  {}",
+            options.skip_layers.join(", ").cyan()
+        );
+    }
+    emit_status!(out, verbosity, "{}\n", "=".repeat(60));
 
-    // マニフェスト読み込み
-    let manifest = load_archetype(archetypes_dir, archetype)?;
-    println!(
+    emit_status!(
+        out,
+        verbosity,
         "Using archetype: {}",
-        manifest.display_name.bold()
+        report.display_name.bold()
     );
-    println!("  {}\n", manifest.description);
+    emit_status!(out, verbosity, "  {}\n", report.archetype_description);
 
-    // Teraコンテキスト作成
-    let mut context = tera::Context::new();
-    context.insert("name", &snake_name);
-    context.insert("pascal_name", &pascal_name);
-    context.insert("description", description);
-
-    // ファイル生成
-    let archetype_dir = archetypes_dir.join(archetype);
-    let mut generated = Vec::new();
-
-    println!("Generated files:");
+    if !options.force {
+        for (path, line) in &report.existing_modules {
+            eprintln!(
+                "{} `{}` is already registered in {}; the feature likely exists (use --force to overwrite or `rename` to rename it)",
+                "warning:".yellow(),
+                line,
+                paths.show(path)
+            );
+        }
+    }
 
-    for file_spec in &manifest.files {
-        // テンプレート読み込み
-        let template_path = archetype_dir.join(&file_spec.template);
-        let template_content = fs::read_to_string(&template_path)
-            .with_context(|| format!("Failed to read template: {:?}", template_path))?;
+    if report.dry_run {
+        emit_status!(out, verbosity, "Planned files:");
+    } else {
+        emit_status!(out, verbosity, "Generated files:");
+    }
+    if tree {
+        print_generated_tree(out, options, report, verbosity);
+    }
+    let width = report
+        .generated
+        .iter()
+        .map(|file| file.layer.chars().count())
+        .max()
+        .unwrap_or(0);
+    for file in report.generated.iter().filter(|_| !tree) {
+        let layer = layer_tag(&file.layer, width);
+        if report.dry_run {
+            emit_status!(
+                out,
+                verbosity,
+                "  {} {} ({} bytes)",
+                layer,
+                paths.show(&file.path),
+                file.bytes
+            );
+        } else {
+            emit_status!(out, verbosity, "  {} {}", layer, paths.show(&file.path));
+        }
+        if verbosity == Verbosity::Verbose {
+            emit!(
+                out,
+                "{}",
+                format!(
+                    "      template: {}, {} bytes, rendered in {:.2?}",
+                    file.template.display(),
+                    file.bytes,
+                    file.render_time
+                )
+                .dimmed()
+            );
+        }
+    }
 
-        // Teraでレンダリング
-        let rendered = Tera::one_off(&template_content, &context, false)
-            .with_context(|| format!("Failed to render template: {}", file_spec.template))?;
+    for file in &report.skipped {
+        emit_status!(
+            out,
+            verbosity,
+            "{}",
+            format!(
+                "  [SKIP] {} (when: {})",
+                paths.show(&file.path),
+                file.condition
+            )
+            .dimmed()
+        );
+    }
 
-        // 出力パスを生成（変数置換）
-        let output_path = file_spec
-            .output
-            .replace("{{name}}", &snake_name)
-            .replace("{{pascal_name}}", &pascal_name);
+    for path in &report.unchanged {
+        emit_status!(
+            out,
+            verbosity,
+            "{}",
+            format!("  [UNCHANGED] {}", paths.show(path)).dimmed()
+        );
+    }
 
-        let full_path = target.join(&output_path);
+    if let Some(seed_from) = options
+        .seed_from
+        .as_ref()
+        .filter(|_| !report.seeded.is_empty())
+    {
+        emit_status!(out, verbosity, "\nSeeded from feature '{}':", seed_from);
+        for path in &report.seeded {
+            emit_status!(out, verbosity, "  {}", paths.show(path));
+        }
+    }
 
-        // ディレクトリ作成
-        if let Some(parent) = full_path.parent() {
-            fs::create_dir_all(parent)?;
+    if report.dry_run && !report.mod_updates.is_empty() {
+        emit_status!(out, verbosity, "\nmod.rs changes:");
+        for update in &report.mod_updates {
+            let label = format!("[{}]", update.status.label(true));
+            let label = if update.is_change() {
+                label.yellow()
+            } else {
+                label.dimmed()
+            };
+            emit_status!(
+                out,
+                verbosity,
+                "  {} {}: {}",
+                label,
+                paths.show(&update.mod_file),
+                update.line
+            );
+        }
+    } else if report.mod_updates.iter().any(|update| update.is_change()) {
+        emit_status!(out, verbosity, "\nUpdated mod.rs files:");
+        for update in report
+            .mod_updates
+            .iter()
+            .filter(|update| update.is_change())
+        {
+            emit_status!(out, verbosity, "  {}", paths.show(&update.mod_file));
         }
+    }
 
-        // ファイル書き込み
-        fs::write(&full_path, rendered)?;
+    for (cargo_toml, added) in &report.added_dependencies {
+        if added.is_empty() {
+            continue;
+        }
+        if report.dry_run {
+            emit_status!(out, verbosity, "\nWould add to {}:", paths.show(cargo_toml));
+        } else {
+            emit_status!(out, verbosity, "\nAdded to {}:", paths.show(cargo_toml));
+        }
+        for name in added {
+            emit_status!(out, verbosity, "  {}", name);
+        }
+    }
+    if report.added_dependencies.is_empty() && !report.dependencies.is_empty() {
+        emit_status!(
+            out,
+            verbosity,
+            "\nAdd to Cargo.toml (or re-run with --add-deps):"
+        );
+        for line in dependency_snippet(&report.dependencies).lines() {
+            emit_status!(out, verbosity, "  {}", line);
+        }
+    }
 
-        println!(
-            "  [{}] {}",
-            file_spec.layer.to_uppercase().green(),
-            full_path.display()
+    if let Some(path) = &report.record {
+        emit_status!(
+            out,
+            verbosity,
+            "\nRecorded generation: {}",
+            paths.show(path)
         );
-        generated.push((file_spec.layer.clone(), full_path));
     }
 
-    // mod.rs更新（rust_hexagonalのみ）
-    if update_mod && archetype == "rust_hexagonal" {
-        let updated = update_mod_files(target, &snake_name)?;
-        if !updated.is_empty() {
-            println!("\nUpdated mod.rs files:");
-            for path in updated {
-                println!("  {}", path.display());
+    if !report.hooks.is_empty() {
+        // 出力は実行中に流しているので、ここではコマンドだけを並べる
+        emit_status!(out, verbosity, "\nRan post_generate hooks:");
+        for hook in &report.hooks {
+            emit_status!(out, verbosity, "  $ {}", hook.command);
+        }
+    }
+    if !report.skipped_hooks.is_empty() {
+        if report.dry_run {
+            emit_status!(
+                out,
+                verbosity,
+                "\nWould run post_generate hooks (with --run-hooks):"
+            );
+        } else {
+            emit_status!(
+                out,
+                verbosity,
+                "\n{}",
+                "Skipped post_generate hooks (use --run-hooks to run them):".yellow()
+            );
+        }
+        for command in &report.skipped_hooks {
+            emit_status!(out, verbosity, "  $ {}", command);
+        }
+    }
+
+    if !report.formatted.is_empty() {
+        emit_status!(out, verbosity, "\nFormatting with rustfmt:");
+    }
+    for (path, outcome) in &report.formatted {
+        match outcome {
+            FormatOutcome::Formatted => emit_status!(out, verbosity, "  {}", paths.show(path)),
+            FormatOutcome::Failed(stderr) => {
+                emit!(
+                    out,
+                    "  {} rustfmt failed for {}",
+                    "warning:".yellow(),
+                    paths.show(path)
+                );
+                for line in stderr.lines() {
+                    emit!(out, "    {}", line);
+                }
+            }
+            FormatOutcome::Unavailable(err) => {
+                emit!(
+                    out,
+                    "  {} failed to run rustfmt: {}",
+                    "warning:".yellow(),
+                    err
+                );
             }
         }
     }
 
-    println!("\n{}", "=".repeat(60));
-    println!(
+    if verbosity == Verbosity::Verbose {
+        let total_bytes: usize = report.generated.iter().map(|file| file.bytes).sum();
+        let verb = if report.dry_run {
+            "would be written"
+        } else {
+            "written"
+        };
+        emit_status!(
+            out,
+            verbosity,
+            "\nTotal: {} bytes {} in {:.2?}",
+            total_bytes,
+            verb,
+            report.elapsed
+        );
+    }
+
+    emit_status!(out, verbosity, "\n{}", "=".repeat(60));
+    // 最終結果の1行は`--quiet`でも表示する
+    if report.dry_run {
+        emit!(
+            out,
+            "{}",
+            format!(
+                "Dry run complete. {} files would be created for feature '{}'",
+                report.generated.len(),
+                report.name
+            )
+            .yellow()
+            .bold()
+        );
+        print_layer_counts(out, report, verbosity);
+        emit_status!(out, verbosity, "{}", "=".repeat(60));
+        return;
+    }
+
+    emit!(
+        out,
         "{}",
         format!(
             "Architecture enforced successfully! Created {} files for feature '{}'",
-            generated.len(),
-            snake_name
+            report.generated.len(),
+            report.name
         )
         .green()
         .bold()
     );
+    print_layer_counts(out, report, verbosity);
+    emit_status!(out, verbosity, "{}", "=".repeat(60));
+
+    if !report.next_steps.is_empty() {
+        emit_status!(out, verbosity, "\n{}", "Next steps:".bold());
+        for (i, step) in report.next_steps.iter().enumerate() {
+            emit_status!(out, verbosity, "  {}. {}", i + 1, step);
+        }
+    }
+}
+
+/// レイヤーの色の候補
+const LAYER_PALETTE: [Color; 6] = [
+    Color::Green,
+    Color::Cyan,
+    Color::Magenta,
+    Color::Blue,
+    Color::Yellow,
+    Color::BrightGreen,
+];
+
+/// レイヤー名から決まる色（同じレイヤーは常に同じ色）
+fn layer_color(layer: &str) -> Color {
+    // FNV-1a: 実行ごとに変わらないハッシュ
+    let hash = layer.bytes().fold(0x811c9dc5u32, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x01000193)
+    });
+    LAYER_PALETTE[hash as usize % LAYER_PALETTE.len()]
+}
+
+/// `[LAYER]`のタグを、最長のレイヤー名に合わせて右側を空白で埋めた文字列
+fn layer_label(layer: &str, width: usize) -> String {
+    format!(
+        "{:<width$}",
+        format!("[{}]", layer.to_uppercase()),
+        width = width + 2
+    )
+}
+
+/// 色付きの`[LAYER]`タグ
+fn layer_tag(layer: &str, width: usize) -> ColoredString {
+    layer_label(layer, width).color(layer_color(layer))
+}
+
+/// 生成ファイルをレイヤーごとにまとめ、生成先からの相対パスのツリーで表示する
+fn print_generated_tree(
+    out: &mut dyn Write,
+    options: &ScaffoldOptions,
+    report: &ScaffoldReport,
+    verbosity: Verbosity,
+) {
+    let mut layers: Vec<(&str, Vec<(PathBuf, String)>)> = Vec::new();
+    for file in &report.generated {
+        let path = file
+            .path
+            .strip_prefix(&options.target)
+            .unwrap_or(&file.path)
+            .to_path_buf();
+        let note = if verbosity == Verbosity::Verbose {
+            format!(
+                " (template: {}, {} bytes, rendered in {:.2?})",
+                file.template.display(),
+                file.bytes,
+                file.render_time
+            )
+            .dimmed()
+            .to_string()
+        } else if report.dry_run {
+            format!(" ({} bytes)", file.bytes)
+        } else {
+            String::new()
+        };
+        match layers.iter_mut().find(|(layer, _)| *layer == file.layer) {
+            Some((_, files)) => files.push((path, note)),
+            None => layers.push((&file.layer, vec![(path, note)])),
+        }
+    }
+
+    for (layer, files) in layers {
+        emit_status!(out, verbosity, "  {}", layer_tag(layer, 0));
+        for line in render_tree(&files) {
+            emit_status!(out, verbosity, "  {}", line);
+        }
+    }
+}
+
+/// ツリー表示の節（ディレクトリまたはファイル）
+#[derive(Default)]
+struct TreeNode {
+    children: std::collections::BTreeMap<String, TreeNode>,
+    /// ファイルの場合に名前の後ろへ付ける注記
+    note: Option<String>,
+}
+
+/// パスの一覧を罫線文字のツリーにする
+///
+/// 子がディレクトリ1つだけのディレクトリは`src/domain/`のようにまとめて表示する。
+fn render_tree(files: &[(PathBuf, String)]) -> Vec<String> {
+    let mut root = TreeNode::default();
+    for (path, note) in files {
+        let mut node = &mut root;
+        for component in path.iter() {
+            node = node
+                .children
+                .entry(component.to_string_lossy().into_owned())
+                .or_default();
+        }
+        node.note = Some(note.clone());
+    }
+
+    let mut lines = Vec::new();
+    render_tree_children(&root, "", &mut lines);
+    lines
+}
+
+/// 子ノードを罫線付きで1行ずつ追加する（`prefix`は親までの罫線）
+fn render_tree_children(node: &TreeNode, prefix: &str, lines: &mut Vec<String>) {
+    let count = node.children.len();
+    for (index, (name, child)) in node.children.iter().enumerate() {
+        let last = index + 1 == count;
+        let branch = if last { "└── " } else { "├── " };
+
+        let mut label = name.clone();
+        let mut child = child;
+        while child.note.is_none() && child.children.len() == 1 {
+            let (next_name, next) = child.children.iter().next().unwrap();
+            if next.note.is_some() {
+                break;
+            }
+            label = format!("{}/{}", label, next_name);
+            child = next;
+        }
+
+        match &child.note {
+            Some(note) => lines.push(format!("{}{}{}{}", prefix, branch, label, note)),
+            None => {
+                lines.push(format!("{}{}{}/", prefix, branch, label));
+                let indent = if last { "    " } else { "│   " };
+                render_tree_children(child, &format!("{}{}", prefix, indent), lines);
+            }
+        }
+    }
+}
+
+/// レイヤーごとの生成ファイル数（最初に現れた順）
+fn layer_counts(generated: &[GeneratedFile]) -> Vec<(&str, usize)> {
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for file in generated {
+        match counts.iter_mut().find(|(layer, _)| *layer == file.layer) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((&file.layer, 1)),
+        }
+    }
+    counts
+}
+
+/// レイヤーごとの生成ファイル数を1行で表示（例: `domain: 1, port: 1, adapter: 1`）
+fn print_layer_counts(out: &mut dyn Write, report: &ScaffoldReport, verbosity: Verbosity) {
+    let counts: Vec<String> = layer_counts(&report.generated)
+        .into_iter()
+        .map(|(layer, count)| format!("{}: {}", layer.color(layer_color(layer)), count))
+        .collect();
+    if !counts.is_empty() {
+        emit_status!(out, verbosity, "{}", counts.join(", "));
+    }
+}
+
+/// アーキタイプ一覧を表示
+fn list_archetypes(
+    architect: &Architect,
+    format: OutputFormat,
+    verbosity: Verbosity,
+) -> Result<()> {
+    let archetypes = architect.list()?;
+
+    if format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&archetypes)?);
+        return Ok(());
+    }
+
+    if verbosity == Verbosity::Quiet {
+        for manifest in archetypes {
+            println!("{}", manifest.name);
+        }
+        return Ok(());
+    }
+
+    println!("{}", "Available Archetypes:".bold());
     println!("{}", "=".repeat(60));
 
+    for manifest in &archetypes {
+        print_archetype(manifest);
+    }
+
+    println!("\n{}", "=".repeat(60));
     Ok(())
 }
 
-/// mod.rsファイルを更新
-fn update_mod_files(target: &Path, name: &str) -> Result<Vec<PathBuf>> {
-    let mut updated = Vec::new();
+/// アーキタイプの名前・説明・選択基準を表示
+fn print_archetype(manifest: &Manifest) {
+    let aliases = if manifest.aliases.is_empty() {
+        String::new()
+    } else {
+        format!(" (alias: {})", manifest.aliases.join(", "))
+    };
+    println!(
+        "\n[{}]{} {}",
+        manifest.name.cyan(),
+        aliases,
+        format!("({})", manifest.dir.display()).dimmed()
+    );
+    println!("\n  {}", manifest.display_name.bold());
+    println!("  {}", manifest.description);
 
-    let mod_files = [
-        (target.join("src/domain/mod.rs"), format!("pub mod {};", name)),
-        (
-            target.join("src/ports/mod.rs"),
-            format!("pub mod {}_port;", name),
-        ),
-        (
-            target.join("src/adapters/mod.rs"),
-            format!("pub mod {}_adapter;", name),
-        ),
-    ];
+    if !manifest.use_when.is_empty() {
+        println!("\n  {}:", "Use when".green());
+        for item in &manifest.use_when {
+            println!("    - {}", item);
+        }
+    }
 
-    for (mod_path, mod_line) in mod_files {
-        let mod_line_with_newline = format!("{}\n", mod_line);
+    if !manifest.avoid_when.is_empty() {
+        println!("\n  {}:", "Avoid when".red());
+        for item in &manifest.avoid_when {
+            println!("    - {}", item);
+        }
+    }
+}
 
-        if mod_path.exists() {
-            let content = fs::read_to_string(&mod_path)?;
-            if !content.contains(&mod_line) {
-                let mut file = fs::OpenOptions::new().append(true).open(&mod_path)?;
-                std::io::Write::write_all(&mut file, mod_line_with_newline.as_bytes())?;
-                updated.push(mod_path);
-            }
-        } else {
-            if let Some(parent) = mod_path.parent() {
-                fs::create_dir_all(parent)?;
+/// 1つのアーキタイプの詳細を表示（`--show-files`でファイル仕様も表示）
+fn describe_archetype(
+    architect: &Architect,
+    archetype: &str,
+    show_files: bool,
+    name: &str,
+    format: OutputFormat,
+) -> Result<()> {
+    if show_files {
+        let files = architect.describe_files(archetype, name)?;
+        if format == OutputFormat::Json {
+            println!("{}", serde_json::to_string_pretty(&files)?);
+            return Ok(());
+        }
+        print_archetype(&architect.archetype(archetype)?);
+        println!("\n  {} (name: {}):", "Files".bold(), name);
+        for file in &files {
+            let output = match &file.resolved_output {
+                Some(resolved) => resolved.clone(),
+                None => format!("{} {}", file.output, "(unresolved)".dimmed()),
+            };
+            let status = if file.template_exists {
+                "ok".green()
+            } else {
+                "missing".red()
+            };
+            println!(
+                "    {} {} -> {} [{}]",
+                status,
+                file.template,
+                output,
+                file.layer.cyan()
+            );
+            if let Some(condition) = &file.when {
+                println!("        {}", format!("when: {}", condition).dimmed());
             }
-            fs::write(&mod_path, mod_line_with_newline)?;
-            updated.push(mod_path);
         }
+        return Ok(());
+    }
+
+    let manifest = architect.archetype(archetype)?;
+    if format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&manifest)?);
+    } else {
+        print_archetype(&manifest);
+    }
+    Ok(())
+}
+
+/// アーキタイプ一覧から対話的に1つ選ぶ（初期選択は組み込みデフォルト）
+fn pick_archetype(architect: &Architect) -> Result<String> {
+    let archetypes = architect.list()?;
+    if archetypes.is_empty() {
+        anyhow::bail!("No archetypes found in {:?}", architect.archetypes_dirs());
+    }
+
+    let items: Vec<String> = archetypes
+        .iter()
+        .map(|manifest| format!("{} - {}", manifest.display_name, manifest.description))
+        .collect();
+    let default = archetypes
+        .iter()
+        .position(|manifest| manifest.name == DEFAULT_ARCHETYPE)
+        .unwrap_or(0);
+
+    let selected = dialoguer::Select::new()
+        .with_prompt("Select an archetype")
+        .items(&items)
+        .default(default)
+        .interact()
+        .context("Failed to read archetype selection")?;
+    Ok(archetypes[selected].name.clone())
+}
+
+/// `--mod-file`と`--mod-line`を指定順に対にする
+///
+/// `--mod-file`が1つだけなら、全ての`--mod-line`をそのファイルに登録する。
+fn pair_mod_lines(files: Vec<String>, lines: Vec<String>) -> Result<Vec<ModUpdate>> {
+    let files = match files.as_slice() {
+        [file] => vec![file.clone(); lines.len()],
+        _ if files.len() == lines.len() => files,
+        _ => anyhow::bail!(
+            "--mod-file ({}) and --mod-line ({}) must be given the same number of times, or --mod-file once",
+            files.len(),
+            lines.len()
+        ),
+    };
+    Ok(files
+        .into_iter()
+        .zip(lines)
+        .map(|(mod_file, line)| ModUpdate {
+            mod_file,
+            line,
+            layer: None,
+        })
+        .collect())
+}
+
+/// `key=value`形式の引数をパース
+fn parse_key_val(arg: &str) -> Result<(String, String), String> {
+    let (key, value) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("invalid KEY=VALUE: no `=` found in `{}`", arg))?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(format!("invalid KEY=VALUE: empty key in `{}`", arg));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// `--description`または`--description-file`から説明を得る
+///
+/// ファイルの末尾の改行は取り除く。
+fn read_description(
+    description: Option<String>,
+    description_file: Option<&Path>,
+) -> Result<String> {
+    match (description, description_file) {
+        (Some(description), None) => Ok(description),
+        (None, Some(path)) => {
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read description file: {:?}", path))?;
+            Ok(content.trim_end_matches(['\r', '\n']).to_string())
+        }
+        (Some(_), Some(_)) => {
+            anyhow::bail!("--description and --description-file cannot be used together")
+        }
+        (None, None) => anyhow::bail!("Either --description or --description-file is required"),
     }
+}
+
+/// `--var-file`・`--var`・`--var-json`を結合する（同じキーは後のものが優先）
+fn collect_vars(
+    var_file: Option<&Path>,
+    vars: Vec<(String, String)>,
+    json_vars: Vec<(String, serde_json::Value)>,
+) -> Result<Vec<(String, serde_json::Value)>> {
+    let mut collected = match var_file {
+        Some(path) => load_var_file(path)?,
+        None => Vec::new(),
+    };
+    collected.extend(
+        vars.into_iter()
+            .map(|(key, value)| (key, cli_var_value(value))),
+    );
+    collected.extend(json_vars);
+    Ok(collected)
+}
 
-    Ok(updated)
+/// `--var`の値をテンプレート変数の値にする
+///
+/// `when`や`{% if %}`で偽になるよう、`true`/`false`は真偽値、`0`は数値にする
+/// （空文字列はそのままで偽になる）。それ以外は文字列のまま渡す。
+fn cli_var_value(value: String) -> serde_json::Value {
+    match value.as_str() {
+        "true" => true.into(),
+        "false" => false.into(),
+        "0" => 0.into(),
+        _ => value.into(),
+    }
 }
 
-/// snake_caseに変換
-fn to_snake_case(name: &str) -> String {
-    name.to_lowercase()
-        .replace('-', "_")
-        .replace(' ', "_")
+/// `key=<json>`形式の引数をパース
+fn parse_key_json(arg: &str) -> Result<(String, serde_json::Value), String> {
+    let (key, value) = parse_key_val(arg)?;
+    let value = serde_json::from_str(&value)
+        .map_err(|err| format!("invalid JSON for `{}`: {}", key, err))?;
+    Ok((key, value))
 }
 
 #[cfg(test)]
@@ -357,15 +1822,301 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_to_snake_case() {
-        assert_eq!(to_snake_case("MyFeature"), "myfeature");
-        assert_eq!(to_snake_case("my-feature"), "my_feature");
-        assert_eq!(to_snake_case("my feature"), "my_feature");
+    fn test_env_archetypes_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = dir.path().to_path_buf();
+        assert_eq!(
+            env_archetypes_dirs(Some(shared.clone().into_os_string())),
+            Some(vec![shared.clone()])
+        );
+        assert_eq!(env_archetypes_dirs(None), None);
+        assert_eq!(env_archetypes_dirs(Some("".into())), None);
+        // 存在しないディレクトリを含む場合は次の候補へ進む
+        let value = std::env::join_paths([shared.clone(), shared.join("missing")]).unwrap();
+        assert_eq!(env_archetypes_dirs(Some(value)), None);
+    }
+
+    #[test]
+    fn test_check_archetypes_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(check_archetypes_dirs(&[dir.path().to_path_buf()], &[]).is_ok());
+
+        let missing = dir.path().join("archetypes");
+        let err = check_archetypes_dirs(std::slice::from_ref(&missing), &[]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "No archetypes directory found at {}; pass --archetypes-dir or create one",
+                missing.display()
+            )
+        );
+
+        let searched = vec![dir.path().join("bin/archetypes"), missing.clone()];
+        let err = check_archetypes_dirs(&[missing], &searched).unwrap_err();
+        assert!(err.to_string().contains(&format!(
+            "(searched: {}, ",
+            dir.path().join("bin/archetypes").display()
+        )));
+    }
+
+    #[test]
+    fn test_read_description() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("description.md");
+        std::fs::write(&path, "# Orders\n\nTracks `order` state & \"history\".\n").unwrap();
+        assert_eq!(
+            read_description(None, Some(&path)).unwrap(),
+            "# Orders\n\nTracks `order` state & \"history\"."
+        );
+        assert_eq!(
+            read_description(Some("inline".into()), None).unwrap(),
+            "inline"
+        );
+        assert!(read_description(Some("inline".into()), Some(&path)).is_err());
+        assert!(read_description(None, None).is_err());
+        assert!(read_description(None, Some(&dir.path().join("missing.md"))).is_err());
+    }
+
+    #[test]
+    fn test_layer_tags() {
+        assert_eq!(layer_label("port", 7), "[PORT]   ");
+        assert_eq!(layer_label("adapter", 7), "[ADAPTER]");
+        assert_eq!(layer_label("domain", 0), "[DOMAIN]");
+
+        // 同じレイヤーは常に同じ色で、Hexagonalの3レイヤーは色が分かれる
+        assert_eq!(layer_color("domain"), layer_color("domain"));
+        assert_ne!(layer_color("domain"), layer_color("port"));
+        assert_ne!(layer_color("port"), layer_color("adapter"));
+        assert_ne!(layer_color("domain"), layer_color("adapter"));
+    }
+
+    #[test]
+    fn test_layer_counts() {
+        let file = |layer: &str| GeneratedFile {
+            layer: layer.into(),
+            path: PathBuf::new(),
+            template: PathBuf::new(),
+            bytes: 0,
+            render_time: Duration::ZERO,
+        };
+        let generated = [file("domain"), file("adapter"), file("domain")];
+        assert_eq!(
+            layer_counts(&generated),
+            vec![("domain", 2), ("adapter", 1)]
+        );
+    }
+
+    #[test]
+    fn test_render_tree() {
+        let files = vec![
+            (PathBuf::from("src/domain/stock.rs"), String::new()),
+            (
+                PathBuf::from("src/domain/mod.rs"),
+                " (12 bytes)".to_string(),
+            ),
+            (PathBuf::from("src/ports/stock_port.rs"), String::new()),
+            (PathBuf::from("tests/stock.rs"), String::new()),
+        ];
+        assert_eq!(
+            render_tree(&files),
+            vec![
+                "├── src/",
+                "│   ├── domain/",
+                "│   │   ├── mod.rs (12 bytes)",
+                "│   │   └── stock.rs",
+                "│   └── ports/",
+                "│       └── stock_port.rs",
+                "└── tests/",
+                "    └── stock.rs",
+            ]
+        );
+
+        // 子が1つだけのディレクトリはまとめる
+        let files = vec![
+            (PathBuf::from("src/adapters/db/a.rs"), String::new()),
+            (PathBuf::from("src/adapters/db/b.rs"), String::new()),
+        ];
+        assert_eq!(
+            render_tree(&files),
+            vec!["└── src/adapters/db/", "    ├── a.rs", "    └── b.rs"]
+        );
+    }
+
+    #[test]
+    fn test_path_style() {
+        let cwd = std::env::temp_dir().join("project");
+        let relative = PathStyle {
+            cwd: Some(cwd.clone()),
+            absolute: false,
+        };
+        assert_eq!(
+            relative.show(Path::new("./src/stock.rs")),
+            Path::new("src/stock.rs").display().to_string()
+        );
+        assert_eq!(
+            relative.show(&cwd.join("src/stock.rs")),
+            Path::new("src/stock.rs").display().to_string()
+        );
+        let outside = std::env::temp_dir().join("other/src/stock.rs");
+        assert_eq!(relative.show(&outside), outside.display().to_string());
+
+        let absolute = PathStyle {
+            cwd: Some(cwd.clone()),
+            absolute: true,
+        };
+        assert_eq!(
+            absolute.show(Path::new("src/stock.rs")),
+            cwd.join("src/stock.rs").display().to_string()
+        );
+    }
+
+    #[test]
+    fn test_pair_mod_lines() {
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+        let pairs = |updates: Vec<ModUpdate>| -> Vec<(String, String)> {
+            updates
+                .into_iter()
+                .map(|update| (update.mod_file, update.line))
+                .collect()
+        };
+
+        let updates = pair_mod_lines(
+            strings(&["src/lib.rs"]),
+            strings(&["pub mod {{name}};", "pub use {{name}}::*;"]),
+        )
+        .unwrap();
+        assert_eq!(
+            pairs(updates),
+            [
+                ("src/lib.rs".into(), "pub mod {{name}};".into()),
+                ("src/lib.rs".into(), "pub use {{name}}::*;".into()),
+            ]
+        );
+
+        let updates = pair_mod_lines(
+            strings(&["src/lib.rs", "src/app/mod.rs"]),
+            strings(&["pub mod a;", "pub mod b;"]),
+        )
+        .unwrap();
+        assert_eq!(
+            pairs(updates)[1],
+            ("src/app/mod.rs".into(), "pub mod b;".into())
+        );
+
+        assert!(pair_mod_lines(
+            strings(&["src/lib.rs", "src/app/mod.rs"]),
+            strings(&["pub mod a;"])
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_key_val() {
+        assert_eq!(
+            parse_key_val("author=Jane Doe").unwrap(),
+            ("author".to_string(), "Jane Doe".to_string())
+        );
+        assert_eq!(
+            parse_key_val("query=a=b").unwrap(),
+            ("query".to_string(), "a=b".to_string())
+        );
+        assert!(parse_key_val("author").unwrap_err().contains("no `=`"));
+        assert!(parse_key_val("=value").is_err());
+    }
+
+    #[test]
+    fn test_parse_key_json() {
+        let (key, value) =
+            parse_key_json(r#"entity={"fields":[{"name":"id","ty":"i64"}]}"#).unwrap();
+        assert_eq!(key, "entity");
+        assert_eq!(value["fields"][0]["ty"], "i64");
+
+        let err = parse_key_json("entity={fields}").unwrap_err();
+        assert!(err.contains("invalid JSON for `entity`"));
+        assert!(parse_key_json(r#"{"a":1}"#).is_err());
+    }
+
+    #[test]
+    fn test_cli_vars_override_var_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let var_file = dir.path().join("vars.toml");
+        std::fs::write(&var_file, "author = \"file\"\nenable_cache = true\n").unwrap();
+
+        let vars = collect_vars(
+            Some(&var_file),
+            vec![("author".to_string(), "cli".to_string())],
+            Vec::new(),
+        )
+        .unwrap();
+        // 後の値がTeraコンテキストで優先される
+        assert_eq!(
+            vars,
+            vec![
+                ("author".to_string(), serde_json::json!("file")),
+                ("enable_cache".to_string(), serde_json::json!(true)),
+                ("author".to_string(), serde_json::json!("cli")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cli_vars_coerce_falsy_values() {
+        let vars = collect_vars(
+            None,
+            ["flag=false", "zero=0", "empty=", "on=true", "author=jane"]
+                .into_iter()
+                .map(|arg| parse_key_val(arg).unwrap())
+                .collect(),
+            Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            vars.iter()
+                .map(|(_, value)| value.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                serde_json::json!(false),
+                serde_json::json!(0),
+                serde_json::json!(""),
+                serde_json::json!(true),
+                serde_json::json!("jane"),
+            ]
+        );
+
+        let template = "{% if flag or zero or empty %}on{% else %}off{% endif %} {{ zero }}";
+        assert_eq!(
+            render_template(template, "sample", "", &vars).unwrap(),
+            "off 0"
+        );
+    }
+
+    #[test]
+    fn test_completions_cover_subcommands() {
+        Cli::command().debug_assert();
+
+        let mut script = Vec::new();
+        clap_complete::generate(
+            Shell::Bash,
+            &mut Cli::command(),
+            "aegis-architect",
+            &mut script,
+        );
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("scaffold"));
+        assert!(script.contains("--only-layer"));
     }
 
     #[test]
-    fn test_pascal_case() {
-        assert_eq!("stock_price".to_pascal_case(), "StockPrice");
-        assert_eq!("market_analysis".to_pascal_case(), "MarketAnalysis");
+    fn test_color_enabled() {
+        assert!(color_enabled(false, None, None, true));
+        assert!(color_enabled(false, Some(""), None, true));
+        assert!(!color_enabled(true, None, None, true));
+        assert!(!color_enabled(false, Some("1"), None, true));
+        assert!(!color_enabled(false, None, None, false));
+        // .aegisrcのcolorは端末かどうかより優先し、--no-colorやNO_COLORには負ける
+        assert!(color_enabled(false, None, Some(true), false));
+        assert!(!color_enabled(false, None, Some(false), true));
+        assert!(!color_enabled(true, None, Some(true), true));
+        assert!(!color_enabled(false, Some("1"), Some(true), true));
     }
 }